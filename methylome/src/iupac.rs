@@ -1,6 +1,7 @@
 use std::fmt::Display;
 
 use anyhow::bail;
+use serde::{Deserialize, Serialize};
 
 /// Represents an IUPAC nucleotide base.
 ///
@@ -24,10 +25,15 @@ use anyhow::bail;
 /// - `V`: Not T (A, C, or G)
 /// - `N`: Any base
 ///
+/// Uracil (`U`, for RNA) has no variant of its own and is parsed as `T`, so an RNA
+/// motif is searched like the DNA motif and the complement stays an involution.
+///
 /// # References
 /// Based on IUPAC nucleotide code conventions.
 /// For more details, see: https://en.wikipedia.org/wiki/Nucleic_acid_notation
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Serializes as the IUPAC character, e.g. `"R"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IupacBase {
     A,
     T,
@@ -46,6 +52,36 @@ pub enum IupacBase {
     N,
 }
 
+/// Per-base data, indexed by `IupacBase as usize`.
+struct BaseInfo {
+    symbol: char,
+    complement: IupacBase,
+    regex: &'static str,
+    bases: &'static [IupacBase],
+}
+
+use IupacBase::{A, B, C, D, G, H, K, M, N, R, S, T, V, W, Y};
+
+/// One row per variant, in declaration order.
+#[rustfmt::skip]
+const BASE_TABLE: [BaseInfo; 15] = [
+    BaseInfo { symbol: 'A', complement: T, regex: "A", bases: &[A] },
+    BaseInfo { symbol: 'T', complement: A, regex: "T", bases: &[T] },
+    BaseInfo { symbol: 'G', complement: C, regex: "G", bases: &[G] },
+    BaseInfo { symbol: 'C', complement: G, regex: "C", bases: &[C] },
+    BaseInfo { symbol: 'R', complement: Y, regex: "[AG]", bases: &[A, G] },
+    BaseInfo { symbol: 'Y', complement: R, regex: "[CT]", bases: &[C, T] },
+    BaseInfo { symbol: 'S', complement: S, regex: "[CG]", bases: &[C, G] },
+    BaseInfo { symbol: 'W', complement: W, regex: "[AT]", bases: &[A, T] },
+    BaseInfo { symbol: 'K', complement: M, regex: "[GT]", bases: &[G, T] },
+    BaseInfo { symbol: 'M', complement: K, regex: "[AC]", bases: &[A, C] },
+    BaseInfo { symbol: 'B', complement: V, regex: "[CGT]", bases: &[C, G, T] },
+    BaseInfo { symbol: 'D', complement: H, regex: "[AGT]", bases: &[A, G, T] },
+    BaseInfo { symbol: 'H', complement: D, regex: "[ACT]", bases: &[A, C, T] },
+    BaseInfo { symbol: 'V', complement: B, regex: "[ACG]", bases: &[A, C, G] },
+    BaseInfo { symbol: 'N', complement: N, regex: ".", bases: &[A, C, G, T] },
+];
+
 impl Display for IupacBase {
     /// Formats the `IupacBase` as a string for display purposes.
     ///
@@ -59,12 +95,20 @@ impl Display for IupacBase {
     /// assert_eq!(format!("{}", base), "A");
     /// ```
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self.info().symbol)
     }
 }
 
 impl IupacBase {
-    /// Parses a single character into an `IupacBase` enum variant.
+    /// All variants in declaration order.
+    pub const ALL: [IupacBase; 15] = [A, T, G, C, R, Y, S, W, K, M, B, D, H, V, N];
+
+    fn info(&self) -> &'static BaseInfo {
+        &BASE_TABLE[*self as usize]
+    }
+
+    /// Parses a single character into an `IupacBase` enum variant, ignoring case, so
+    /// soft-masked (lowercase) bases parse as their uppercase code.
     ///
     /// # Arguments
     /// - `base`: A character representing an IUPAC nucleotide code.
@@ -79,14 +123,16 @@ impl IupacBase {
     ///
     /// let base = IupacBase::parse_char('A').unwrap();
     /// assert_eq!(base, IupacBase::A);
+    /// assert_eq!(IupacBase::parse_char('r').unwrap(), IupacBase::R);
+    /// assert_eq!(IupacBase::parse_char('U').unwrap(), IupacBase::T);
     ///
     /// let invalid = IupacBase::parse_char('Z');
     /// assert!(invalid.is_err());
     /// ```
     pub fn parse_char(base: char) -> anyhow::Result<Self> {
-        let iupac_base = match base {
+        let iupac_base = match base.to_ascii_uppercase() {
             'A' => Ok(Self::A),
-            'T' => Ok(Self::T),
+            'T' | 'U' => Ok(Self::T),
             'G' => Ok(Self::G),
             'C' => Ok(Self::C),
             'R' => Ok(Self::R),
@@ -105,37 +151,6 @@ impl IupacBase {
         iupac_base
     }
 
-    /// Converts the `IupacBase` to its string representation.
-    ///
-    /// This method maps the `IupacBase` variant to its corresponding single-character string.
-    ///
-    /// # Examples
-    /// ```
-    /// use methylome::IupacBase;
-    ///
-    /// let base = IupacBase::A;
-    /// assert_eq!(base.to_string(), "A");
-    /// ```
-    pub fn to_string(&self) -> String {
-        match self {
-            IupacBase::A => "A".to_string(),
-            IupacBase::T => "T".to_string(),
-            IupacBase::G => "G".to_string(),
-            IupacBase::C => "C".to_string(),
-            IupacBase::R => "R".to_string(),
-            IupacBase::Y => "Y".to_string(),
-            IupacBase::S => "S".to_string(),
-            IupacBase::W => "W".to_string(),
-            IupacBase::K => "K".to_string(),
-            IupacBase::M => "M".to_string(),
-            IupacBase::B => "B".to_string(),
-            IupacBase::D => "D".to_string(),
-            IupacBase::H => "H".to_string(),
-            IupacBase::V => "V".to_string(),
-            IupacBase::N => "N".to_string(),
-        }
-    }
-
     /// Returns the complement of the given IUPAC base.
     ///
     /// Complements are defined as follows:
@@ -163,23 +178,7 @@ impl IupacBase {
     /// assert_eq!(complement, IupacBase::T);
     /// ```
     pub fn to_complement_base(base: &IupacBase) -> Self {
-        match base {
-            IupacBase::A => IupacBase::T,
-            IupacBase::T => IupacBase::A,
-            IupacBase::G => IupacBase::C,
-            IupacBase::C => IupacBase::G,
-            IupacBase::R => IupacBase::Y,
-            IupacBase::Y => IupacBase::R,
-            IupacBase::S => IupacBase::S,
-            IupacBase::W => IupacBase::W,
-            IupacBase::K => IupacBase::M,
-            IupacBase::M => IupacBase::K,
-            IupacBase::B => IupacBase::V,
-            IupacBase::D => IupacBase::H,
-            IupacBase::H => IupacBase::D,
-            IupacBase::V => IupacBase::B,
-            IupacBase::N => IupacBase::N,
-        }
+        base.info().complement
     }
 
     /// Converts the `IupacBase` into its corresponding regular expression representation.
@@ -194,22 +193,102 @@ impl IupacBase {
     /// assert_eq!(regex, "[AG]");
     /// ```
     pub fn to_regex(&self) -> &str {
-        match self {
-            IupacBase::A => "A",
-            IupacBase::T => "T",
-            IupacBase::G => "G",
-            IupacBase::C => "C",
-            IupacBase::R => "[AG]",
-            IupacBase::Y => "[CT]",
-            IupacBase::S => "[CG]",
-            IupacBase::W => "[AT]",
-            IupacBase::K => "[GT]",
-            IupacBase::M => "[AC]",
-            IupacBase::B => "[CGT]",
-            IupacBase::D => "[AGT]",
-            IupacBase::H => "[ACT]",
-            IupacBase::V => "[ACG]",
-            IupacBase::N => ".",
+        self.info().regex
+    }
+
+    /// Returns the concrete bases (A, C, G, T) the code stands for.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::IupacBase;
+    ///
+    /// assert_eq!(IupacBase::R.concrete_bases(), &[IupacBase::A, IupacBase::G]);
+    /// ```
+    pub fn concrete_bases(&self) -> &'static [IupacBase] {
+        self.info().bases
+    }
+
+    /// Returns true if the contig base `base` matches the code, the same way as the
+    /// motif regex: `N` matches any base, the other codes match the concrete bases
+    /// they stand for in either case.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::IupacBase;
+    ///
+    /// assert!(IupacBase::R.contains(b'G'));
+    /// assert!(!IupacBase::R.contains(b'C'));
+    /// assert!(IupacBase::R.contains(b'g'));
+    /// assert!(!IupacBase::R.contains(b'R'));
+    /// assert!(IupacBase::N.contains(b'n'));
+    /// ```
+    pub fn contains(&self, base: u8) -> bool {
+        let base = base.to_ascii_uppercase();
+        *self == IupacBase::N
+            || self
+                .concrete_bases()
+                .iter()
+                .any(|b| b.info().symbol as u8 == base)
+    }
+
+    /// Returns the number of concrete bases the code stands for, e.g. 1 for `A`
+    /// and 4 for `N`.
+    pub fn degeneracy(&self) -> usize {
+        self.info().bases.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_table_matches_variants() {
+        for base in IupacBase::ALL {
+            assert_eq!(
+                IupacBase::parse_char(base.info().symbol).unwrap(),
+                base,
+                "BASE_TABLE row out of order for {}",
+                base
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_char_ignores_case() {
+        for base in IupacBase::ALL {
+            let lowercase = base.info().symbol.to_ascii_lowercase();
+            assert_eq!(IupacBase::parse_char(lowercase).unwrap(), base);
+        }
+        assert!(IupacBase::parse_char('z').is_err());
+    }
+
+    #[test]
+    fn test_complement_is_involution() {
+        for base in IupacBase::ALL {
+            let complement = IupacBase::to_complement_base(&base);
+            assert_eq!(IupacBase::to_complement_base(&complement), base);
+            assert_eq!(complement.degeneracy(), base.degeneracy());
+        }
+    }
+
+    #[test]
+    fn test_uracil() {
+        assert_eq!(IupacBase::parse_char('U').unwrap(), IupacBase::T);
+        assert_eq!(IupacBase::parse_char('u').unwrap(), IupacBase::T);
+    }
+
+    #[test]
+    fn test_concrete_bases() {
+        for base in IupacBase::ALL {
+            let concrete = base.concrete_bases();
+            assert_eq!(concrete.len(), base.degeneracy());
+            assert!(concrete.iter().all(|b| b.degeneracy() == 1));
         }
+        assert_eq!(IupacBase::N.degeneracy(), 4);
+        assert_eq!(
+            IupacBase::B.concrete_bases(),
+            &[IupacBase::C, IupacBase::G, IupacBase::T]
+        );
     }
 }
@@ -1,4 +1,6 @@
-use regex::Regex;
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::{borrow::Borrow, collections::BTreeSet};
 
 pub mod iupac;
 pub mod modtype;
@@ -10,15 +12,400 @@ pub use modtype::ModType;
 pub use motif::Motif;
 pub use strand::Strand;
 
+/// Returns the complement of a single IUPAC base, keeping its case.
+///
+/// # Examples
+/// ```
+/// assert_eq!(methylome::complement('A').unwrap(), 'T');
+/// assert_eq!(methylome::complement('r').unwrap(), 'y');
+/// assert!(methylome::complement('Z').is_err());
+/// ```
+pub fn complement(base: char) -> Result<char> {
+    let complement = IupacBase::to_complement_base(&IupacBase::parse_char(base)?)
+        .to_string()
+        .chars()
+        .next()
+        .expect("Display of IupacBase is a single character");
+    Ok(if base.is_ascii_lowercase() {
+        complement.to_ascii_lowercase()
+    } else {
+        complement
+    })
+}
+
+/// Reverse complements a DNA sequence of IUPAC codes, keeping the case of each base.
+///
+/// # Errors
+/// Returns an error naming the first character that is not an IUPAC code.
+///
+/// # Examples
+/// ```
+/// assert_eq!(methylome::reverse_complement("GATCRy").unwrap(), "rYGATC");
+/// assert!(methylome::reverse_complement("GAT-C").is_err());
+/// ```
+pub fn reverse_complement(sequence: &str) -> Result<String> {
+    sequence
+        .chars()
+        .rev()
+        .map(|base| {
+            complement(base).with_context(|| {
+                format!(
+                    "Cannot reverse complement '{}': invalid base '{}'",
+                    sequence, base
+                )
+            })
+        })
+        .collect()
+}
+
+/// A match of a motif in a contig: the span `start..end` of the match (`end` is
+/// exclusive) and the contig index of a modified base in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MotifMatch {
+    pub start: usize,
+    pub end: usize,
+    pub mod_position: usize,
+}
+
+/// Returns every match of `motif` in `contig` with its span, e.g. for annotation.
+/// A motif with several mod positions gives one match per position, with the same
+/// span. The matches are ordered by start, and by modified base within a match.
+///
+/// Overlapping matches of a motif with several mod positions can share a modified
+/// base, which is then in both matches. `find_motif_indices_in_contig` returns the
+/// `mod_position`s of the matches sorted and without such duplicates.
+pub fn find_motif_matches_in_contig(contig: &str, motif: &Motif) -> Vec<MotifMatch> {
+    find_motif_matches_iter(contig, motif).collect()
+}
+
+/// Returns the positions of the modified base of every match of `motif` in `contig`,
+/// sorted and without duplicates. A motif with several mod positions gives one index
+/// per position for each match, and a base shared by overlapping matches is returned
+/// once, e.g. 4 of `GCAGCAGC` for `GCAGC_m_1+4`.
+///
+/// Overlapping matches are all reported, e.g. `GCTGC` in `GCAGCTGC` for the motif
+/// `GCWGC`. Contigs shorter than the motif cannot contain a match and are not
+/// scanned.
 pub fn find_motif_indices_in_contig(contig: &str, motif: &Motif) -> Vec<usize> {
-    let regex_str = motif.to_regex();
-    let re = Regex::new(&regex_str).expect("Expected regex pattern");
+    find_motif_indices_iter(contig, motif).collect()
+}
+
+/// Returns the motif regex matching contig bases in either case, so soft-masked
+/// (lowercase) regions are searched like the rest of the contig.
+fn motif_regex(pattern: &str) -> Regex {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .expect("Expected regex pattern")
+}
+
+/// Returns the regex finding the starts of `motif` and of its reverse complement in
+/// one pass, `fwd|rev`, or the regex of `motif` alone if it is palindromic.
+fn combined_motif_regex(motif: &Motif, reverse: &Motif) -> Regex {
+    if motif.is_palindromic() {
+        motif_regex(&motif.to_regex())
+    } else {
+        motif_regex(&format!(
+            "(?:{})|(?:{})",
+            motif.to_regex(),
+            reverse.to_regex()
+        ))
+    }
+}
+
+/// A motif with the regexes of both of its strands compiled, to search many contigs.
+///
+/// The `find_motif_*` functions compile the regex of the motif on every call. A run
+/// searching a motif in thousands of contigs builds a `MotifSearcher` once instead,
+/// and gets the same indices from it.
+#[derive(Debug, Clone)]
+pub struct MotifSearcher {
+    motif: Motif,
+    reverse: Motif,
+    forward_regex: Regex,
+    reverse_regex: Regex,
+    combined_regex: Regex,
+}
+
+impl MotifSearcher {
+    pub fn new(motif: &Motif) -> Self {
+        let reverse = motif.reverse_complement();
+        Self {
+            forward_regex: motif_regex(&motif.to_regex()),
+            reverse_regex: motif_regex(&reverse.to_regex()),
+            combined_regex: combined_motif_regex(motif, &reverse),
+            motif: motif.clone(),
+            reverse,
+        }
+    }
+
+    pub fn motif(&self) -> &Motif {
+        &self.motif
+    }
+
+    /// Returns the indices of the modified base of the motif on `strand`, the motif on
+    /// the positive strand and its reverse complement on the negative one. They are
+    /// those of `find_motif_indices_in_circular_contig` if `circular`, and of
+    /// `find_motif_indices_in_contig` otherwise.
+    pub fn indices(&self, contig: &str, strand: Strand, circular: bool) -> Vec<usize> {
+        let (motif, re) = self.strand(strand);
+        if circular {
+            circular_indices(contig, motif, re)
+        } else {
+            sorted_unique_indices(
+                regex_matches(contig, motif, searchable(contig, motif).then_some(re)),
+                motif.mod_position as usize,
+            )
+            .collect()
+        }
+    }
+
+    /// Returns the number of indices `indices` would return, without collecting those
+    /// of a linear contig.
+    pub fn count(&self, contig: &str, strand: Strand, circular: bool) -> usize {
+        let (motif, re) = self.strand(strand);
+        if circular {
+            circular_indices(contig, motif, re).len()
+        } else {
+            sorted_unique_indices(
+                regex_matches(contig, motif, searchable(contig, motif).then_some(re)),
+                motif.mod_position as usize,
+            )
+            .count()
+        }
+    }
+
+    /// Same as `find_motif_matches_combined`.
+    pub fn combined_indices(&self, contig: &str) -> (Vec<usize>, Vec<usize>) {
+        if !searchable(contig, &self.motif) {
+            return (Vec::new(), Vec::new());
+        }
+        combined_indices(contig, &self.motif, &self.reverse, &self.combined_regex)
+    }
+
+    fn strand(&self, strand: Strand) -> (&Motif, &Regex) {
+        match strand {
+            Strand::Positive => (&self.motif, &self.forward_regex),
+            Strand::Negative => (&self.reverse, &self.reverse_regex),
+        }
+    }
+}
+
+/// Whether `contig` is long enough to contain a match of `motif`. Shorter contigs are
+/// not scanned.
+fn searchable(contig: &str, motif: &Motif) -> bool {
+    contig.len() >= motif.sequence.len()
+}
+
+/// Lazy version of `find_motif_indices_in_contig`, yielding the same indices in the
+/// same order without collecting them, e.g. to count the occurrences.
+pub fn find_motif_indices_iter<'a>(
+    contig: &'a str,
+    motif: &Motif,
+) -> impl Iterator<Item = usize> + 'a {
+    let min_mod_position = motif.mod_position as usize;
+    sorted_unique_indices(find_motif_matches_iter(contig, motif), min_mod_position)
+}
+
+/// Yields the modified bases of `matches`, which are ordered by start, sorted and
+/// without duplicates.
+///
+/// The bases of a match are held back until no later match can have a smaller one:
+/// a later match starts after the last start, so its bases are beyond the last start
+/// plus `min_mod_position`. At most the bases of one motif length are held.
+fn sorted_unique_indices(
+    mut matches: impl Iterator<Item = MotifMatch>,
+    min_mod_position: usize,
+) -> impl Iterator<Item = usize> {
+    let mut pending = BTreeSet::new();
+    let mut last_start = None;
+    let mut exhausted = false;
+    std::iter::from_fn(move || loop {
+        if let Some(&first) = pending.first() {
+            if exhausted || last_start.is_some_and(|start: usize| first <= start + min_mod_position)
+            {
+                return pending.pop_first();
+            }
+        }
+        if exhausted {
+            return None;
+        }
+        match matches.next() {
+            Some(m) => {
+                pending.insert(m.mod_position);
+                last_start = Some(m.start);
+            }
+            None => exhausted = true,
+        }
+    })
+}
+
+/// Sorts indices and removes duplicates, which overlapping matches of a motif with
+/// several mod positions can give.
+fn sort_unique(indices: &mut Vec<usize>) {
+    indices.sort_unstable();
+    indices.dedup();
+}
+
+/// Lazy version of `find_motif_matches_in_contig`.
+pub fn find_motif_matches_iter<'a>(
+    contig: &'a str,
+    motif: &Motif,
+) -> impl Iterator<Item = MotifMatch> + 'a {
+    let re = searchable(contig, motif).then(|| motif_regex(&motif.to_regex()));
+    regex_matches(contig, motif, re)
+}
+
+/// Yields the matches of `motif` found by `re`, its compiled regex, or nothing without
+/// a regex.
+fn regex_matches<'a>(
+    contig: &'a str,
+    motif: &Motif,
+    re: Option<impl Borrow<Regex> + 'a>,
+) -> impl Iterator<Item = MotifMatch> + 'a {
+    let motif_len = motif.sequence.len();
+    let mod_positions = motif.mod_positions();
+    regex_starts(contig, re).flat_map(move |start| {
+        mod_positions
+            .clone()
+            .into_iter()
+            .map(move |position| MotifMatch {
+                start,
+                end: start + motif_len,
+                mod_position: start + position as usize,
+            })
+    })
+}
+
+/// Lazily yields the start of every, possibly overlapping, match of `re`.
+fn regex_starts<'a>(
+    contig: &'a str,
+    re: Option<impl Borrow<Regex> + 'a>,
+) -> impl Iterator<Item = usize> + 'a {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        let m = re.as_ref()?.borrow().find_at(contig, start)?;
+        start = m.start() + 1;
+        Some(m.start())
+    })
+}
 
-    let indices = re
-        .find_iter(contig)
-        .map(|m| m.start() as usize + motif.mod_position as usize)
+/// Finds the occurrences of the motif on both strands in a single pass.
+///
+/// Returns the indices of the modified base of the forward motif and of its reverse
+/// complement, as two calls of `find_motif_indices_in_contig` would. One regex with
+/// the alternation `fwd|rev` finds every start where either strand matches, and the
+/// window at that start is then checked against both strands. A start where both
+/// match, as every start of a palindromic motif does, therefore gives one index per
+/// strand. A palindromic motif is searched with its own regex only.
+pub fn find_motif_matches_combined(contig: &str, motif: &Motif) -> (Vec<usize>, Vec<usize>) {
+    if !searchable(contig, motif) {
+        return (Vec::new(), Vec::new());
+    }
+    let reverse = motif.reverse_complement();
+    let re = combined_motif_regex(motif, &reverse);
+    combined_indices(contig, motif, &reverse, &re)
+}
+
+/// Returns the indices of `motif` and of `reverse`, its reverse complement, at the
+/// starts found by `re`, their combined regex.
+fn combined_indices(
+    contig: &str,
+    motif: &Motif,
+    reverse: &Motif,
+    re: &Regex,
+) -> (Vec<usize>, Vec<usize>) {
+    let motif_len = motif.sequence.len();
+    let bytes = contig.as_bytes();
+    let matches_at = |motif: &Motif, start: usize| {
+        motif
+            .sequence
+            .iter()
+            .zip(&bytes[start..start + motif_len])
+            .all(|(motif_base, contig_base)| motif_base.contains(*contig_base))
+    };
+
+    let (fwd_positions, rev_positions) = (motif.mod_positions(), reverse.mod_positions());
+    let mut fwd_indices = Vec::new();
+    let mut rev_indices = Vec::new();
+    let mut start = 0;
+    while let Some(m) = re.find_at(contig, start) {
+        if matches_at(motif, m.start()) {
+            fwd_indices.extend(fwd_positions.iter().map(|&p| m.start() + p as usize));
+        }
+        if matches_at(reverse, m.start()) {
+            rev_indices.extend(rev_positions.iter().map(|&p| m.start() + p as usize));
+        }
+        start = m.start() + 1;
+    }
+    if fwd_positions.len() > 1 {
+        sort_unique(&mut fwd_indices);
+        sort_unique(&mut rev_indices);
+    }
+
+    (fwd_indices, rev_indices)
+}
+
+/// Same as `find_motif_indices_in_contig`, but for a circular contig: motif
+/// occurrences spanning the end to start junction are found as well.
+///
+/// The search runs over the contig with its first `motif.len() - 1` bases appended,
+/// and positions past the end wrap around to the start. The returned indices are
+/// sorted and without duplicates.
+pub fn find_motif_indices_in_circular_contig(contig: &str, motif: &Motif) -> Vec<usize> {
+    if contig.is_empty() {
+        return Vec::new();
+    }
+    circular_indices(contig, motif, &motif_regex(&motif.to_regex()))
+}
+
+/// Returns the indices of `motif` in the circular `contig`, found with `re`, its
+/// compiled regex.
+fn circular_indices(contig: &str, motif: &Motif, re: &Regex) -> Vec<usize> {
+    let contig_len = contig.len();
+    if contig_len == 0 {
+        return Vec::new();
+    }
+
+    let n_wrapped = (motif.sequence.len() - 1).min(contig_len);
+    let mut extended = String::with_capacity(contig_len + n_wrapped);
+    extended.push_str(contig);
+    extended.push_str(&contig[..n_wrapped]);
+
+    let mod_positions = motif.mod_positions();
+    let re = searchable(&extended, motif).then_some(re);
+    let mut indices: Vec<usize> = regex_starts(&extended, re)
+        .filter(|&start| start < contig_len)
+        .flat_map(|start| {
+            mod_positions
+                .iter()
+                .map(move |&position| (start + position as usize) % contig_len)
+        })
         .collect();
+    sort_unique(&mut indices);
+    indices
+}
 
+/// Same as `find_motif_indices_in_contig`, but without a regex: the motif is slid
+/// over the contig and each base is checked with `IupacBase::contains`.
+///
+/// This is slower than the regex and serves as an independent reference for it.
+pub fn find_motif_indices_in_contig_sliding(contig: &str, motif: &Motif) -> Vec<usize> {
+    let mod_positions = motif.mod_positions();
+    let mut indices: Vec<usize> = contig
+        .as_bytes()
+        .windows(motif.sequence.len())
+        .enumerate()
+        .filter(|(_, window)| {
+            motif
+                .sequence
+                .iter()
+                .zip(window.iter())
+                .all(|(motif_base, contig_base)| motif_base.contains(*contig_base))
+        })
+        .flat_map(|(start, _)| mod_positions.iter().map(move |&p| start + p as usize))
+        .collect();
+    sort_unique(&mut indices);
     indices
 }
 
@@ -46,4 +433,387 @@ mod tests {
 
         assert_eq!(find_motif_indices_in_contig(&contig2, &motif4), vec![3])
     }
+
+    #[test]
+    fn test_motif_searcher() {
+        let contigs = [
+            "TTGGACGATCCTggaTtTtccT",
+            "TCCAGGATCGG",
+            "GATCGCAGCAGC",
+            "GG",
+            "",
+        ];
+        let motifs = [
+            Motif::new("GGANNNTCC", "a", 2).unwrap(),
+            Motif::new("GATC", "m", 3).unwrap(),
+            "GCAGC_m_1+4".parse().unwrap(),
+            Motif::new("CCAG", "m", 1).unwrap(),
+        ];
+        assert_eq!(
+            MotifSearcher::new(&motifs[0]).indices(contigs[0], Strand::Positive, false),
+            vec![4, 14]
+        );
+
+        // The same searcher gives the indices of the free functions in every contig.
+        for motif in &motifs {
+            let searcher = MotifSearcher::new(motif);
+            assert_eq!(searcher.motif(), motif);
+            let reverse = motif.reverse_complement();
+            for contig in contigs {
+                for (strand, strand_motif) in
+                    [(Strand::Positive, motif), (Strand::Negative, &reverse)]
+                {
+                    let linear = find_motif_indices_in_contig(contig, strand_motif);
+                    let circular = find_motif_indices_in_circular_contig(contig, strand_motif);
+                    assert_eq!(searcher.indices(contig, strand, false), linear);
+                    assert_eq!(searcher.count(contig, strand, false), linear.len());
+                    assert_eq!(searcher.indices(contig, strand, true), circular);
+                    assert_eq!(searcher.count(contig, strand, true), circular.len());
+                }
+                assert_eq!(
+                    searcher.combined_indices(contig),
+                    find_motif_matches_combined(contig, motif)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_motif_matches_in_contig() {
+        let motif = Motif::new("RGATCY", "a", 2).unwrap();
+        let contig = "AGATCTTTGGATCC";
+        assert_eq!(
+            find_motif_matches_in_contig(contig, &motif),
+            vec![
+                MotifMatch {
+                    start: 0,
+                    end: 6,
+                    mod_position: 2
+                },
+                MotifMatch {
+                    start: 8,
+                    end: 14,
+                    mod_position: 10
+                },
+            ]
+        );
+        assert_eq!(find_motif_indices_in_contig(contig, &motif), vec![2, 10]);
+
+        // One match per modified base, with the same span.
+        let motif: Motif = "GAAC_a_1+2".parse().unwrap();
+        let spans: Vec<(usize, usize, usize)> = find_motif_matches_in_contig("TGAACG", &motif)
+            .into_iter()
+            .map(|m| (m.start, m.end, m.mod_position))
+            .collect();
+        assert_eq!(spans, vec![(1, 5, 2), (1, 5, 3)]);
+
+        assert!(find_motif_matches_in_contig("GAT", &motif).is_empty());
+    }
+
+    #[test]
+    fn test_find_motif_indices_in_circular_contig() {
+        let motif = Motif::new("GATC", "a", 1).unwrap();
+
+        // GATC spans the junction as TC...GA.
+        let contig = "TCAAAAGA";
+        assert!(find_motif_indices_in_contig(contig, &motif).is_empty());
+        assert_eq!(
+            find_motif_indices_in_circular_contig(contig, &motif),
+            vec![7]
+        );
+
+        // The modified base itself can wrap around to the start.
+        let contig = "CAAAAGAT";
+        assert_eq!(
+            find_motif_indices_in_circular_contig(contig, &motif),
+            vec![6]
+        );
+        let motif_m = Motif::new("GATC", "m", 3).unwrap();
+        assert_eq!(
+            find_motif_indices_in_circular_contig(contig, &motif_m),
+            vec![0]
+        );
+        assert_eq!(
+            find_motif_indices_in_circular_contig(contig, &motif.reverse_complement()),
+            vec![7]
+        );
+
+        // Sites inside the contig are found as in the linear search.
+        let contig = "GGATCTCCATGATC";
+        assert_eq!(
+            find_motif_indices_in_circular_contig(contig, &motif_m),
+            find_motif_indices_in_contig(contig, &motif_m)
+        );
+        assert!(find_motif_indices_in_circular_contig("", &motif).is_empty());
+    }
+
+    #[test]
+    fn test_find_motif_matches_combined() {
+        let contig = "TGGACGATCCCGATCGAAGAAGTTC";
+        for (motif, expected) in [
+            // Palindromic: every occurrence is a site on both strands.
+            (
+                Motif::new("GATC", "a", 1).unwrap(),
+                (vec![6, 12], vec![7, 13]),
+            ),
+            (
+                Motif::new("GATC", "m", 3).unwrap(),
+                (vec![8, 14], vec![5, 11]),
+            ),
+            // Not palindromic: GAAG at 15 and 18 overlap, CTTC occurs as GAAG reversed.
+            (Motif::new("GAAG", "a", 1).unwrap(), (vec![16, 19], vec![])),
+            (Motif::new("CTTC", "m", 0).unwrap(), (vec![], vec![18, 21])),
+        ] {
+            let two_pass = (
+                find_motif_indices_in_contig(contig, &motif),
+                find_motif_indices_in_contig(contig, &motif.reverse_complement()),
+            );
+            assert_eq!(two_pass, expected);
+            assert_eq!(find_motif_matches_combined(contig, &motif), expected);
+        }
+
+        assert_eq!(
+            find_motif_matches_combined("GA", &Motif::new("GATC", "a", 1).unwrap()),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn test_contig_shorter_than_motif() {
+        let motif = Motif::new("GAC N{6} GTT", "a", 1).unwrap();
+        assert!(find_motif_indices_in_contig("GA", &motif).is_empty());
+        assert!(find_motif_indices_in_contig("", &motif.reverse_complement()).is_empty());
+    }
+
+    #[test]
+    fn test_find_bipartite_motif_indices_in_contig() {
+        let contig = "AAGACTTTTTTGTTAAAACGGGGGGGTC";
+        let motif = Motif::new("GAC N{6} GTT", "a", 1).unwrap();
+
+        assert_eq!(find_motif_indices_in_contig(contig, &motif), vec![3]);
+        // AAC N{6} GTC on the forward strand. The modified A is on the reverse strand,
+        // opposite the T of GTC.
+        assert_eq!(
+            find_motif_indices_in_contig(contig, &motif.reverse_complement()),
+            vec![26]
+        );
+    }
+
+    #[test]
+    fn test_reverse_complement() {
+        assert_eq!(reverse_complement("").unwrap(), "");
+        assert_eq!(reverse_complement("GATC").unwrap(), "GATC");
+        assert_eq!(reverse_complement("CCAGG").unwrap(), "CCTGG");
+        // Degenerate bases, soft-masked bases and N.
+        assert_eq!(reverse_complement("RYSWKMBDHVN").unwrap(), "NBDHVKMWSRY");
+        assert_eq!(reverse_complement("acgtN").unwrap(), "Nacgt");
+
+        let err = reverse_complement("GAXTC").unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid base 'X'"));
+    }
+
+    #[test]
+    fn test_rna_motif() {
+        // U is read as T, so the RNA motif is the DNA motif.
+        let motif = Motif::new("GAUC", "a", 1).unwrap();
+        assert_eq!(motif, Motif::new("GATC", "a", 1).unwrap());
+        assert_eq!(find_motif_indices_in_contig("AAGATCAA", &motif), vec![3]);
+        assert_eq!(
+            find_motif_indices_in_contig("AAGATCAA", &motif.reverse_complement()),
+            vec![4]
+        );
+    }
+
+    #[test]
+    fn test_multiple_mod_positions() {
+        let motif: Motif = "GAAC_a_1+2".parse().unwrap();
+        let contig = "TGAACGTTCA";
+        assert_eq!(find_motif_indices_in_contig(contig, &motif), vec![2, 3]);
+        assert_eq!(
+            find_motif_indices_in_contig_sliding(contig, &motif),
+            vec![2, 3]
+        );
+        assert_eq!(
+            find_motif_matches_combined(contig, &motif),
+            (vec![2, 3], vec![6, 7])
+        );
+        assert_eq!(
+            find_motif_indices_in_circular_contig("AACTTTG", &motif),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn test_overlapping_mod_positions() {
+        // Both matches of GCAGC in GCAGCAGC modify the C at 4.
+        let motif: Motif = "GCAGC_m_1+4".parse().unwrap();
+        let contig = "GCAGCAGC";
+        assert_eq!(find_motif_indices_in_contig(contig, &motif), vec![1, 4, 7]);
+        assert_eq!(
+            find_motif_indices_in_contig_sliding(contig, &motif),
+            vec![1, 4, 7]
+        );
+        assert_eq!(find_motif_indices_iter(contig, &motif).count(), 3);
+        // The matches keep the shared base, once per match.
+        assert_eq!(find_motif_matches_in_contig(contig, &motif).len(), 4);
+
+        let motif = Motif::new("AAAA", "a", 0)
+            .unwrap()
+            .with_additional_mod_positions(&[2])
+            .unwrap();
+        let contig = "AAAAAA";
+        let expected = vec![0, 1, 2, 3, 4];
+        assert_eq!(find_motif_indices_in_contig(contig, &motif), expected);
+        assert_eq!(
+            find_motif_indices_in_contig_sliding(contig, &motif),
+            expected
+        );
+        // The reverse motif TTTT_a_1+3 is searched on the reverse strand.
+        let (fwd, rev) = find_motif_matches_combined("AAAAAATTTTTT", &motif);
+        assert_eq!(fwd, expected);
+        assert_eq!(rev, vec![7, 8, 9, 10, 11]);
+        assert_eq!(
+            find_motif_indices_in_circular_contig("AAAAA", &motif),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_bipartite_mod_positions() {
+        // Both adenines of a bipartite motif, built from the single-position API.
+        let motif = Motif::new("GACNNNNNGTAC", "a", 1)
+            .unwrap()
+            .with_additional_mod_positions(&[10])
+            .unwrap();
+        assert_eq!(motif, "GACNNNNNGTAC_a_1+10".parse().unwrap());
+
+        // One index per modified base per match.
+        let contig = "TTGACAAAAAGTACCCGACTTTTTGTAC";
+        assert_eq!(
+            find_motif_indices_in_contig(contig, &motif),
+            vec![3, 12, 17, 26]
+        );
+        assert_eq!(
+            find_motif_indices_in_contig_sliding(contig, &motif),
+            vec![3, 12, 17, 26]
+        );
+
+        // Every listed position must be the base of the mod type: the T of GANTC is not.
+        assert!(Motif::new("GANTC", "a", 1)
+            .unwrap()
+            .with_additional_mod_positions(&[3])
+            .is_err());
+        assert!("GANTC_a_1+3".parse::<Motif>().is_err());
+    }
+
+    #[test]
+    fn test_soft_masked_contig() {
+        let motif = Motif::new("GATC", "a", 1).unwrap();
+        let contig = "tggacgatcccGAtcgaagaagttc";
+        assert_eq!(find_motif_indices_in_contig(contig, &motif), vec![6, 12]);
+        assert_eq!(
+            find_motif_indices_in_contig_sliding(contig, &motif),
+            vec![6, 12]
+        );
+        assert_eq!(
+            find_motif_matches_combined(contig, &motif),
+            (vec![6, 12], vec![7, 13])
+        );
+        assert_eq!(
+            find_motif_indices_in_circular_contig("tcaaaaGa", &motif),
+            vec![7]
+        );
+    }
+
+    #[test]
+    fn test_find_motif_indices_iter() {
+        let contig = "TGGACGATCCCGATCGCGCGC";
+        for motif in [
+            Motif::new("GATC", "a", 1).unwrap(),
+            Motif::new("GCGC", "m", 3).unwrap(),
+            Motif::new("RGATCY", "m", 4).unwrap(),
+        ] {
+            let indices: Vec<usize> = find_motif_indices_iter(contig, &motif).collect();
+            assert_eq!(indices, find_motif_indices_in_contig(contig, &motif));
+        }
+
+        let motif = Motif::new("GCGC", "m", 1).unwrap();
+        let mut iter = find_motif_indices_iter(contig, &motif);
+        assert_eq!(iter.next(), Some(16));
+        assert_eq!(iter.count(), 1);
+        assert_eq!(find_motif_indices_iter("GC", &motif).count(), 0);
+    }
+
+    #[test]
+    fn test_overlapping_motif_matches() {
+        let motif = Motif::new("GCWGC", "m", 1).unwrap();
+        assert_eq!(find_motif_indices_in_contig("GCAGCTGC", &motif), vec![1, 4]);
+        assert_eq!(
+            find_motif_indices_in_contig_sliding("GCAGCTGC", &motif),
+            vec![1, 4]
+        );
+
+        // GCGC starts at 0 and 2 of GCGCGC, on both strands.
+        let motif = Motif::new("GCGC", "m", 1).unwrap();
+        assert_eq!(find_motif_indices_in_contig("GCGCGC", &motif), vec![1, 3]);
+        assert_eq!(
+            find_motif_matches_combined("GCGCGC", &motif),
+            (vec![1, 3], vec![2, 4])
+        );
+    }
+
+    /// xorshift64, so the property test is reproducible without extra dependencies.
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_regex_and_sliding_matchers_agree() {
+        // Lowercase bases match like uppercase ones, ambiguous contig bases only match
+        // N in a motif.
+        const CONTIG_BASES: &[u8] = b"ACGTACGTACGTNacgR";
+        let mut state = 0x2545_f491_4f6c_dd1d;
+
+        for _ in 0..500 {
+            let contig_len = (next_random(&mut state) % 60) as usize;
+            let contig: String = (0..contig_len)
+                .map(|_| {
+                    CONTIG_BASES[(next_random(&mut state) % CONTIG_BASES.len() as u64) as usize]
+                        as char
+                })
+                .collect();
+
+            let motif_len = 1 + (next_random(&mut state) % 8) as usize;
+            let mut bases: Vec<IupacBase> = (0..motif_len)
+                .map(|_| IupacBase::ALL[(next_random(&mut state) % 15) as usize])
+                .collect();
+            let mod_position = (next_random(&mut state) % motif_len as u64) as u8;
+            bases[mod_position as usize] = IupacBase::A;
+            let motif = Motif::from_bases(bases, ModType::SixMA, mod_position).unwrap();
+
+            assert_eq!(
+                find_motif_matches_combined(&contig, &motif),
+                (
+                    find_motif_indices_in_contig(&contig, &motif),
+                    find_motif_indices_in_contig(&contig, &motif.reverse_complement())
+                ),
+                "motif {} in contig {}",
+                motif.sequence_to_string(),
+                contig
+            );
+
+            for motif in [motif.clone(), motif.reverse_complement()] {
+                assert_eq!(
+                    find_motif_indices_in_contig(&contig, &motif),
+                    find_motif_indices_in_contig_sliding(&contig, &motif),
+                    "motif {} in contig {}",
+                    motif.sequence_to_string(),
+                    contig
+                );
+            }
+        }
+    }
 }
@@ -1,6 +1,9 @@
 use anyhow::{bail, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, str::FromStr};
 
+use crate::IupacBase;
+
 /// Represents a DNA base modification type.
 ///
 /// This enum defines the types of modifications that can occur on DNA bases,
@@ -48,6 +51,44 @@ impl ModType {
             ModType::FourMC => "21839",
         }
     }
+
+    /// Returns the unmodified base the modification occurs on.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::{IupacBase, ModType};
+    ///
+    /// assert_eq!(ModType::SixMA.canonical_base(), IupacBase::A);
+    /// assert_eq!(ModType::FourMC.canonical_base(), IupacBase::C);
+    /// ```
+    pub fn canonical_base(&self) -> IupacBase {
+        match self {
+            ModType::SixMA => IupacBase::A,
+            ModType::FiveMC | ModType::FourMC => IupacBase::C,
+        }
+    }
+
+    /// Parses a pileup code, ignoring the case of letter codes.
+    ///
+    /// Some pileup exports write the mod codes in uppercase (`A`, `M`). This is
+    /// ambiguous: in the SAM `MM` tag and in modkit an uppercase letter is the
+    /// canonical base, and a code such as `A` or `C` can also mean "any modification
+    /// of that base". Parsing `A` as 6mA and `M` as 5mC is therefore only correct if
+    /// the pileup is known to use uppercase mod codes. `C` is not accepted.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::ModType;
+    ///
+    /// assert_eq!(ModType::from_pileup_code_ignore_case("A").unwrap(), ModType::SixMA);
+    /// assert_eq!(ModType::from_pileup_code_ignore_case("m").unwrap(), ModType::FiveMC);
+    /// assert!(ModType::from_pileup_code_ignore_case("C").is_err());
+    /// ```
+    pub fn from_pileup_code_ignore_case(code: &str) -> Result<Self> {
+        code.to_ascii_lowercase()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Unsupported mod type: {}", code))
+    }
 }
 
 impl fmt::Display for ModType {
@@ -113,3 +154,17 @@ impl FromStr for ModType {
         }
     }
 }
+
+/// Serializes as the pileup code, e.g. `"a"`.
+impl Serialize for ModType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.to_pileup_code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ModType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(de::Error::custom)
+    }
+}
@@ -1,6 +1,11 @@
 use crate::{IupacBase, ModType};
-use anyhow::{bail, Result};
-use std::str::FromStr;
+use anyhow::{bail, Context, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// Maximum length of a motif sequence, including its gaps, so that every position
+/// fits the `u8` of `mod_position`.
+pub const MAX_MOTIF_LENGTH: usize = u8::MAX as usize;
 
 /// Represents a biological motif, which includes a nucleotide sequence,
 /// its modification type, and the position of the modification.
@@ -9,11 +14,15 @@ use std::str::FromStr;
 /// - `sequence`: A vector of IUPAC bases representing the motif sequence.
 /// - `mod_type`: The type of modification (e.g., 6mA, 5mC).
 /// - `mod_position`: The position of the modification within the sequence (0-indexed).
+/// - `additional_mod_positions`: Further modified positions of the same mod type,
+///   e.g. both As of `AANNNNNNTT`, in ascending order and after `mod_position`. Empty
+///   for a motif with a single modified base. See `with_additional_mod_positions`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Motif {
     pub sequence: Vec<IupacBase>,
     pub mod_type: ModType,
     pub mod_position: u8,
+    pub additional_mod_positions: Vec<u8>,
 }
 
 impl Motif {
@@ -21,12 +30,17 @@ impl Motif {
     ///
     /// # Arguments
     /// - `sequence`: A string representing the nucleotide sequence (using IUPAC codes).
+    ///   A fixed-length gap can be written as `N{k}`, e.g. `GAC N{6} GTT` for a
+    ///   bipartite motif. Whitespace is ignored and the gap is expanded to `k` N bases.
     /// - `mod_type`: A string representing the modification type (e.g., "a" (6mA), "m" (5mC), "21839" (4mC)0).
     /// - `mod_position`: The 0-indexed position of the modification in the sequence.
+    ///   For gapped motifs it is the position in the expanded sequence.
     ///
     /// # Errors
     /// Returns an error if:
-    /// - The `sequence` contains invalid IUPAC codes.
+    /// - The `sequence` is empty.
+    /// - The `sequence` contains invalid IUPAC codes or a malformed gap.
+    /// - The expanded `sequence` is longer than `MAX_MOTIF_LENGTH`.
     /// - The `mod_position` is out of bounds for the sequence.
     /// - The `mod_type` does not match the base at `mod_position` (e.g., 6mA must modify an 'A').
     ///
@@ -36,11 +50,17 @@ impl Motif {
     ///
     /// let motif = Motif::new("GATC", "a", 1).unwrap();
     /// assert_eq!(motif.mod_type, ModType::SixMA);
+    ///
+    /// let bipartite = Motif::new("GAC N{6} GTT", "a", 1).unwrap();
+    /// assert_eq!(bipartite.sequence_to_string(), "GACNNNNNNGTT");
     /// ```
     pub fn new(sequence: &str, mod_type: &str, mod_position: u8) -> Result<Self> {
+        if sequence.trim().is_empty() {
+            bail!("motif sequence cannot be empty");
+        }
         let mod_type = ModType::from_str(mod_type)?;
 
-        let parsed_sequence = sequence
+        let parsed_sequence = expand_gaps(sequence)?
             .chars()
             .map(|b| {
                 IupacBase::parse_char(b).map_err(|_| {
@@ -53,42 +73,172 @@ impl Motif {
             })
             .collect::<Result<Vec<IupacBase>>>()?;
 
-        if mod_position as usize > parsed_sequence.len() - 1 {
+        Self::from_bases(parsed_sequence, mod_type, mod_position)
+    }
+
+    /// Constructs a new `Motif` from already parsed IUPAC bases.
+    ///
+    /// # Arguments
+    /// - `sequence`: The motif sequence as IUPAC bases.
+    /// - `mod_type`: The modification type.
+    /// - `mod_position`: The 0-indexed position of the modification in the sequence.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The `sequence` is longer than `MAX_MOTIF_LENGTH`.
+    /// - The `mod_position` is out of bounds for the sequence.
+    /// - The `mod_type` does not match the base at `mod_position`.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::{IupacBase, Motif, ModType};
+    ///
+    /// let bases = vec![IupacBase::G, IupacBase::A, IupacBase::T, IupacBase::C];
+    /// let motif = Motif::from_bases(bases, ModType::SixMA, 1).unwrap();
+    /// assert_eq!(motif.sequence_to_string(), "GATC");
+    /// ```
+    pub fn from_bases(
+        sequence: Vec<IupacBase>,
+        mod_type: ModType,
+        mod_position: u8,
+    ) -> Result<Self> {
+        if sequence.len() > MAX_MOTIF_LENGTH {
             bail!(
-                "mod_position {} is out of bounds for sequence of length {}. Note mod_position is 0-indexed.",
-                mod_position,
-                parsed_sequence.len()
+                "Motif sequence of {} bases is longer than the maximum of {} bases.",
+                sequence.len(),
+                MAX_MOTIF_LENGTH
             );
         }
-
-        let base_at_position = &parsed_sequence[mod_position as usize];
-        match mod_type {
-            ModType::SixMA => {
-                if *base_at_position != IupacBase::A {
-                    bail!(
-                        "mod_position {} points to base '{}' which is invalid for 6mA.",
-                        mod_position,
-                        base_at_position
-                    );
-                }
-            }
-            ModType::FiveMC | ModType::FourMC => {
-                if *base_at_position != IupacBase::C {
-                    bail!(
-                        "mod_position {} points to base '{}' which is invalid for {} modification type.",
-                        mod_position, base_at_position, mod_type
-                    );
-                }
-            }
-        }
+        validate_mod_position(&sequence, mod_type, mod_position)?;
 
         Ok(Self {
-            sequence: parsed_sequence,
+            sequence,
             mod_type,
             mod_position,
+            additional_mod_positions: Vec::new(),
         })
     }
 
+    /// Returns a copy of the motif that is also modified at `positions`, e.g. for a
+    /// motif with two modifiable As. The sites of every position are aggregated into
+    /// the statistics of the motif.
+    ///
+    /// Every position is validated against the modification type. The positions are
+    /// kept sorted, so `mod_position` becomes the smallest one.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::Motif;
+    ///
+    /// let motif = Motif::new("AANNNNNNTT", "a", 1).unwrap();
+    /// let motif = motif.with_additional_mod_positions(&[0]).unwrap();
+    /// assert_eq!(motif.mod_positions(), vec![0, 1]);
+    /// assert_eq!(motif.to_string(), "AANNNNNNTT_a_0+1");
+    ///
+    /// assert!(motif.with_additional_mod_positions(&[8]).is_err());
+    /// ```
+    pub fn with_additional_mod_positions(&self, positions: &[u8]) -> Result<Self> {
+        for position in positions {
+            validate_mod_position(&self.sequence, self.mod_type, *position)?;
+        }
+        let mut mod_positions = self.mod_positions();
+        mod_positions.extend_from_slice(positions);
+        Ok(Self::with_sorted_positions(
+            self.sequence.clone(),
+            self.mod_type,
+            mod_positions,
+        ))
+    }
+
+    /// Builds a motif from validated positions, the smallest becoming `mod_position`.
+    fn with_sorted_positions(
+        sequence: Vec<IupacBase>,
+        mod_type: ModType,
+        mut mod_positions: Vec<u8>,
+    ) -> Self {
+        mod_positions.sort_unstable();
+        mod_positions.dedup();
+        Self {
+            sequence,
+            mod_type,
+            mod_position: mod_positions[0],
+            additional_mod_positions: mod_positions[1..].to_vec(),
+        }
+    }
+
+    /// All modified positions in ascending order, `mod_position` first.
+    pub fn mod_positions(&self) -> Vec<u8> {
+        let mut positions = vec![self.mod_position];
+        positions.extend_from_slice(&self.additional_mod_positions);
+        positions
+    }
+
+    /// The modified positions separated by `+`, e.g. `1` or `0+1`, as written in the
+    /// mod_position field of a motif.
+    pub fn mod_positions_to_string(&self) -> String {
+        self.mod_positions()
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<String>>()
+            .join("+")
+    }
+
+    /// Returns a copy of the motif with a different modification position, and no
+    /// additional positions.
+    ///
+    /// The base at the new position is validated against the modification type.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::Motif;
+    ///
+    /// let motif = Motif::new("GATC", "m", 3).unwrap();
+    /// assert!(motif.with_mod_position(0).is_err());
+    ///
+    /// let motif = Motif::new("GATGATC", "a", 1).unwrap();
+    /// assert_eq!(motif.with_mod_position(4).unwrap().mod_position, 4);
+    /// ```
+    pub fn with_mod_position(&self, mod_position: u8) -> Result<Self> {
+        Self::from_bases(self.sequence.clone(), self.mod_type, mod_position)
+    }
+
+    /// Returns a copy of the motif with a different modification type.
+    ///
+    /// The bases at the modified positions are validated against the new modification
+    /// type.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::{Motif, ModType};
+    ///
+    /// let motif = Motif::new("GATC", "m", 3).unwrap();
+    /// let motif_4mc = motif.with_mod_type(ModType::FourMC).unwrap();
+    /// assert_eq!(motif_4mc.mod_type, ModType::FourMC);
+    /// assert!(motif.with_mod_type(ModType::SixMA).is_err());
+    /// ```
+    pub fn with_mod_type(&self, mod_type: ModType) -> Result<Self> {
+        Self::from_bases(self.sequence.clone(), mod_type, self.mod_position)?
+            .with_additional_mod_positions(&self.additional_mod_positions)
+    }
+
+    /// Returns true if the motif sequence is its own reverse complement, e.g. `GATC`.
+    /// The occurrences on the two strands of a palindromic motif are then at the same
+    /// genomic positions. The mod positions are not compared.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::Motif;
+    ///
+    /// assert!(Motif::new("GATC", "a", 1).unwrap().is_palindromic());
+    /// assert!(!Motif::new("GAAC", "a", 1).unwrap().is_palindromic());
+    /// ```
+    pub fn is_palindromic(&self) -> bool {
+        self.sequence
+            .iter()
+            .zip(self.sequence.iter().rev())
+            .all(|(base, opposite)| *base == IupacBase::to_complement_base(opposite))
+    }
+
     /// Returns the reverse complement of the motif.
     ///
     /// The reverse complement reverses the sequence and replaces each base
@@ -105,23 +255,25 @@ impl Motif {
     /// assert_eq!(rev_comp.mod_position, 3);
     /// ```
     pub fn reverse_complement(&self) -> Self {
-        Self {
-            // sequence: (&self.sequence.chars().rev().collect::<String>()).to_string(),
-            sequence: self
-                .sequence
-                .iter()
-                .rev()
-                .map(IupacBase::to_complement_base)
-                .collect(),
-            mod_type: self.mod_type.clone(),
-            mod_position: self.sequence.len() as u8 - self.mod_position - 1,
-        }
+        let sequence = self
+            .sequence
+            .iter()
+            .rev()
+            .map(IupacBase::to_complement_base)
+            .collect();
+        let mod_positions = self
+            .mod_positions()
+            .iter()
+            .map(|position| self.sequence.len() as u8 - position - 1)
+            .collect();
+        Self::with_sorted_positions(sequence, self.mod_type, mod_positions)
     }
 
     /// Converts the motif sequence into a regular expression string.
     ///
     /// Each base in the sequence is mapped to its corresponding regex
     /// pattern based on IUPAC codes. For example, `R` (purine) becomes `[AG]`.
+    /// Runs of several `N` are written as a gap, e.g. `.{6}`.
     ///
     /// # Examples
     /// ```
@@ -130,9 +282,32 @@ impl Motif {
     /// let motif = Motif::new("RGATCY", "a", 2).unwrap();
     /// let regex = motif.to_regex();
     /// assert_eq!(regex, "[AG]GATC[CT]");
+    ///
+    /// let bipartite = Motif::new("GAC N{6} GTT", "a", 1).unwrap();
+    /// assert_eq!(bipartite.to_regex(), "GAC.{6}GTT");
     /// ```
     pub fn to_regex(&self) -> String {
-        self.sequence.iter().map(IupacBase::to_regex).collect()
+        let mut regex = String::new();
+        let mut n_run = 0;
+
+        let flush_gap = |regex: &mut String, n_run: usize| match n_run {
+            0 => {}
+            1 => regex.push_str(IupacBase::N.to_regex()),
+            n => regex.push_str(&format!(".{{{}}}", n)),
+        };
+
+        for base in &self.sequence {
+            if *base == IupacBase::N {
+                n_run += 1;
+                continue;
+            }
+            flush_gap(&mut regex, n_run);
+            n_run = 0;
+            regex.push_str(base.to_regex());
+        }
+        flush_gap(&mut regex, n_run);
+
+        regex
     }
 
     /// Converts the motif sequence into a plain string representation.
@@ -152,6 +327,251 @@ impl Motif {
     }
 }
 
+/// Checks that `mod_position` is in the sequence and points to a base the mod type
+/// can modify.
+fn validate_mod_position(
+    sequence: &[IupacBase],
+    mod_type: ModType,
+    mod_position: u8,
+) -> Result<()> {
+    if mod_position as usize >= sequence.len() {
+        bail!(
+            "mod_position {} is out of bounds for sequence of length {}. Note mod_position is 0-indexed.",
+            mod_position,
+            sequence.len()
+        );
+    }
+
+    let base_at_position = &sequence[mod_position as usize];
+    match mod_type {
+        ModType::SixMA => {
+            if *base_at_position != IupacBase::A {
+                bail!(
+                    "mod_position {} points to base '{}' which is invalid for 6mA.",
+                    mod_position,
+                    base_at_position
+                );
+            }
+        }
+        ModType::FiveMC | ModType::FourMC => {
+            if *base_at_position != IupacBase::C {
+                bail!(
+                    "mod_position {} points to base '{}' which is invalid for {} modification type.",
+                    mod_position, base_at_position, mod_type
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Expands fixed-length gaps written as `N{k}` into `k` N bases and removes whitespace.
+fn expand_gaps(sequence: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(sequence.len());
+    let mut chars = sequence.chars().filter(|c| !c.is_whitespace()).peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            expanded.push(c);
+            continue;
+        }
+
+        if !expanded.ends_with(['N', 'n']) {
+            bail!(
+                "Gap in sequence '{}' must be written as N{{k}}, e.g. GAC N{{6}} GTT",
+                sequence
+            );
+        }
+
+        let mut length = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => length.push(c),
+                None => bail!("Unclosed gap '{{' in sequence '{}'", sequence),
+            }
+        }
+        if length.contains(',') {
+            bail!(
+                "Variable-length gap N{{{}}} in sequence '{}' is not supported. Only fixed-length gaps N{{k}} are.",
+                length,
+                sequence
+            );
+        }
+        let length: usize = length.parse().map_err(|_| {
+            anyhow::anyhow!("Invalid gap length '{}' in sequence '{}'", length, sequence)
+        })?;
+        if length == 0 {
+            bail!("Gap length must be at least 1 in sequence '{}'", sequence);
+        }
+
+        // The N before the brace is the first base of the gap.
+        expanded.push_str(&"N".repeat(length - 1));
+    }
+
+    Ok(expanded)
+}
+
+/// Formats the motif as `<sequence>_<mod_code>_<mod_position>`, e.g. `GATC_a_1` or
+/// `AANNNNNNTT_a_0+1`, the form parsed by `FromStr`.
+impl fmt::Display for Motif {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}_{}_{}",
+            self.sequence_to_string(),
+            self.mod_type.to_pileup_code(),
+            self.mod_positions_to_string()
+        )
+    }
+}
+
+/// Serializes as the `Display` form, e.g. `"GATC_a_1"`, so motif lists stay
+/// human-editable.
+impl Serialize for Motif {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Motif {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let motif = String::deserialize(deserializer)?;
+        motif
+            .parse()
+            .map_err(|e| de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+/// Parses a motif whose modified base is written in lowercase, e.g. `GaTC`. Returns
+/// `None` if no base is lowercase.
+fn parse_lowercase_notation(motif: &str) -> Result<Option<Motif>> {
+    let sequence = expand_gaps(motif)?;
+    let lowercase: Vec<(usize, char)> = sequence
+        .chars()
+        .enumerate()
+        .filter(|(_, base)| base.is_ascii_lowercase())
+        .collect();
+
+    let (mod_position, base) = match lowercase.as_slice() {
+        [] => return Ok(None),
+        [single] => *single,
+        _ => bail!(
+            "Invalid motif '{}': more than one base is lowercase. Write only the modified base in lowercase, or use '<sequence>_<mod_type>_<mod_position>'.",
+            motif
+        ),
+    };
+    let mod_type = match base {
+        'a' => ModType::SixMA,
+        'c' => ModType::FiveMC,
+        _ => bail!(
+            "Invalid motif '{}': no mod type for the lowercase base '{}'. Only a (6mA) and c (5mC) can be inferred, use '<sequence>_<mod_type>_<mod_position>' otherwise.",
+            motif,
+            base
+        ),
+    };
+    let mod_position = u8::try_from(mod_position).with_context(|| {
+        format!(
+            "mod_position {} in motif '{}' is too large",
+            mod_position, motif
+        )
+    })?;
+
+    Motif::new(&sequence, mod_type.to_pileup_code(), mod_position)
+        .with_context(|| format!("Failed to create motif from '{}'", motif))
+        .map(Some)
+}
+
+/// Whether `field` consists of IUPAC bases only, in any case.
+fn looks_like_sequence(field: &str) -> bool {
+    !field.is_empty()
+        && field
+            .chars()
+            .all(|base| IupacBase::parse_char(base).is_ok())
+}
+
+/// Parses a motif written as `<sequence>_<mod_type>_<mod_position>`.
+///
+/// Several modified positions of the same mod type are separated by `+`, e.g.
+/// `AANNNNNNTT_a_0+1`. See `Motif::with_additional_mod_positions`.
+///
+/// A motif without the `_<mod_type>_<mod_position>` suffix can instead mark the
+/// modified base by writing it in lowercase, e.g. `GaTC` for `GATC_a_1`. The mod type
+/// follows from the base: `a` is 6mA and `c` is 5mC. This is ambiguous for
+/// cytosines, as 4mC can't be expressed, and a fully uppercase motif has no modified
+/// base; use the explicit form for those. With the suffix the case is ignored.
+///
+/// # Examples
+///
+/// ```
+/// use methylome::Motif;
+///
+/// let motif: Motif = "GATC_a_1".parse().unwrap();
+/// assert_eq!(motif, Motif::new("GATC", "a", 1).unwrap());
+///
+/// assert!("GATC_a".parse::<Motif>().is_err());
+///
+/// let motif: Motif = "GaTC".parse().unwrap();
+/// assert_eq!(motif, Motif::new("GATC", "a", 1).unwrap());
+/// ```
+impl FromStr for Motif {
+    type Err = anyhow::Error;
+
+    fn from_str(motif: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = motif.split("_").collect();
+
+        if parts.len() == 1 {
+            if let Some(parsed) = parse_lowercase_notation(motif)? {
+                return Ok(parsed);
+            }
+        }
+        if parts.len() != 3 {
+            bail!(
+                "Invalid motif format '{}' encountered. Expected format: '<sequence>_<mod_type>_<mod_position>'",
+                motif
+            );
+        }
+
+        let sequence = parts[0];
+        let mod_type = parts[1];
+        // An uppercase mod code such as 'A' is left to the mod type error below.
+        if ModType::from_str(&mod_type.to_ascii_lowercase()).is_err()
+            && looks_like_sequence(mod_type)
+        {
+            if ModType::from_str(sequence).is_ok() {
+                bail!(
+                    "Invalid motif '{}': '{}' is a mod type and '{}' a sequence. Did you swap the fields? Expected '{}_{}_{}'.",
+                    motif,
+                    sequence,
+                    mod_type,
+                    mod_type,
+                    sequence,
+                    parts[2]
+                );
+            }
+            bail!(
+                "Invalid motif '{}': the mod_type '{}' looks like a nucleotide sequence. Expected format: '<sequence>_<mod_type>_<mod_position>' with mod_type a, m or 21839.",
+                motif,
+                mod_type
+            );
+        }
+        let mod_positions = parts[2]
+            .split('+')
+            .map(u8::from_str)
+            .collect::<Result<Vec<u8>, _>>()
+            .with_context(|| {
+                format!(
+                    "Failed to parse mod_position '{}' in motif '{}'.",
+                    parts[2], motif
+                )
+            })?;
+
+        Motif::new(sequence, mod_type, mod_positions[0])
+            .and_then(|parsed| parsed.with_additional_mod_positions(&mod_positions[1..]))
+            .with_context(|| format!("Failed to create motif from '{}'", motif))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +591,224 @@ mod tests {
         assert_eq!(motif.mod_position, 1);
     }
 
+    #[test]
+    fn test_motif_from_str() {
+        let motif: Motif = "RGATCY_a_2".parse().unwrap();
+        assert_eq!(motif, Motif::new("RGATCY", "a", 2).unwrap());
+
+        for invalid in ["GATC_a", "GATC_a_1_2", "GATC"] {
+            assert_eq!(
+                invalid.parse::<Motif>().unwrap_err().to_string(),
+                format!(
+                    "Invalid motif format '{}' encountered. Expected format: '<sequence>_<mod_type>_<mod_position>'",
+                    invalid
+                )
+            );
+        }
+
+        let error = "GATC_a_x".parse::<Motif>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to parse mod_position 'x' in motif 'GATC_a_x'."
+        );
+
+        let error = "GATC_a_3".parse::<Motif>().unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to create motif from 'GATC_a_3': mod_position 3 points to base 'C' which is invalid for 6mA."
+        );
+
+        let error = "GAXC_a_1".parse::<Motif>().unwrap_err();
+        assert_eq!(error.to_string(), "Failed to create motif from 'GAXC_a_1'");
+
+        let error = "GATC_x_1".parse::<Motif>().unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to create motif from 'GATC_x_1': Unsupported mod type: x"
+        );
+    }
+
+    #[test]
+    fn test_motif_display_round_trip() {
+        for name in ["GATC_a_1", "RGATCY_a_2", "CCGG_21839_0"] {
+            let motif: Motif = name.parse().unwrap();
+            assert_eq!(motif.to_string(), name);
+            assert_eq!(motif.to_string().parse::<Motif>().unwrap(), motif);
+        }
+    }
+
+    #[test]
+    fn test_motif_serde_round_trip() {
+        let motifs = vec![
+            Motif::new("GATC", "a", 1).unwrap(),
+            Motif::new("RGATCY", "a", 2).unwrap(),
+            Motif::new("CCGG", "21839", 0).unwrap(),
+        ];
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&motifs).unwrap();
+        let serialized = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(serialized, "GATC_a_1,RGATCY_a_2,CCGG_21839_0\n");
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(serialized.as_bytes());
+        let deserialized: Vec<Motif> = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(deserialized, motifs);
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader("GATC_a_3".as_bytes());
+        let error = reader
+            .deserialize::<Vec<Motif>>()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(error.to_string().contains("invalid for 6mA"));
+    }
+
+    #[test]
+    fn test_mod_type_and_base_serde_round_trip() {
+        let mod_types = vec![ModType::SixMA, ModType::FiveMC, ModType::FourMC];
+        let bases = parse_iupac_sequence("RGATCY");
+
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_writer(Vec::new());
+        writer.serialize(&mod_types).unwrap();
+        writer.serialize(&bases).unwrap();
+        let serialized = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(serialized, "a,m,21839\nR,G,A,T,C,Y\n");
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(serialized.as_bytes());
+        let mut records = reader.records();
+        let record = records.next().unwrap().unwrap();
+        assert_eq!(record.deserialize::<Vec<ModType>>(None).unwrap(), mod_types);
+        let record = records.next().unwrap().unwrap();
+        assert_eq!(record.deserialize::<Vec<IupacBase>>(None).unwrap(), bases);
+    }
+
+    #[test]
+    fn test_lowercase_mod_position_notation() {
+        let motif: Motif = "GaTC".parse().unwrap();
+        assert_eq!(motif.mod_position, 1);
+        assert_eq!(motif.mod_type, ModType::SixMA);
+        assert_eq!(motif.to_string(), "GATC_a_1");
+
+        assert_eq!(
+            "RGATcY".parse::<Motif>().unwrap(),
+            Motif::new("RGATCY", "m", 4).unwrap()
+        );
+        assert_eq!(
+            "GAC N{6} GtT".parse::<Motif>().unwrap_err().to_string(),
+            "Invalid motif 'GAC N{6} GtT': no mod type for the lowercase base 't'. Only a (6mA) and c (5mC) can be inferred, use '<sequence>_<mod_type>_<mod_position>' otherwise."
+        );
+        assert_eq!(
+            "GAc N{6} GTT".parse::<Motif>().unwrap(),
+            Motif::new("GACNNNNNNGTT", "m", 2).unwrap()
+        );
+        assert!("gaTC"
+            .parse::<Motif>()
+            .unwrap_err()
+            .to_string()
+            .contains("more than one base is lowercase"));
+    }
+
+    #[test]
+    fn test_rna_motif_creation() {
+        let motif = Motif::new("GAUC", "a", 1).unwrap();
+        assert_eq!(motif.sequence, parse_iupac_sequence("GATC"));
+        assert_eq!(motif.to_string(), "GATC_a_1");
+        assert!(Motif::new("GAUC", "a", 2).is_err());
+
+        let reverse = motif.reverse_complement();
+        assert_eq!(reverse.sequence_to_string(), "GATC");
+        assert_eq!(reverse.mod_position, 2);
+        assert_eq!(reverse.reverse_complement(), motif);
+    }
+
+    #[test]
+    fn test_is_palindromic() {
+        for motif in [
+            "GATC_a_1",
+            "GANTC_a_1",
+            "RGATCY_a_2",
+            "CCWGG_m_1",
+            "AANNNNNNTT_a_0+1",
+        ] {
+            assert!(
+                motif.parse::<Motif>().unwrap().is_palindromic(),
+                "{}",
+                motif
+            );
+        }
+        for motif in ["GAAC_a_1", "GAANTC_a_1", "RGATCR_a_2", "GATCC_a_1"] {
+            assert!(
+                !motif.parse::<Motif>().unwrap().is_palindromic(),
+                "{}",
+                motif
+            );
+        }
+    }
+
+    #[test]
+    fn test_multiple_mod_positions() {
+        let motif: Motif = "AANNNNNNTT_a_1+0".parse().unwrap();
+        assert_eq!(motif.mod_position, 0);
+        assert_eq!(motif.additional_mod_positions, vec![1]);
+        assert_eq!(motif.to_string(), "AANNNNNNTT_a_0+1");
+        assert_eq!(motif.to_string().parse::<Motif>().unwrap(), motif);
+
+        // The positions on the reverse strand are mirrored and sorted again.
+        let reverse = motif.reverse_complement();
+        assert_eq!(reverse.sequence_to_string(), "AANNNNNNTT");
+        assert_eq!(reverse.mod_positions(), vec![8, 9]);
+        assert!(reverse.with_mod_type(ModType::FiveMC).is_err());
+
+        assert_eq!(motif.with_mod_position(1).unwrap().mod_positions(), vec![1]);
+
+        let error = "AANNNNNNTT_a_0+2".parse::<Motif>().unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to create motif from 'AANNNNNNTT_a_0+2': mod_position 2 points to base 'N' which is invalid for 6mA."
+        );
+        assert!("AANNNNNNTT_a_0+".parse::<Motif>().is_err());
+    }
+
+    #[test]
+    fn test_mixed_case_motif() {
+        let motif = Motif::new("rGaTcY", "a", 2).unwrap();
+        assert_eq!(motif, Motif::new("RGATCY", "a", 2).unwrap());
+        assert_eq!(motif.sequence_to_string(), "RGATCY");
+        assert_eq!(
+            Motif::new("gac n{6} gtt", "a", 1).unwrap(),
+            Motif::new("GAC N{6} GTT", "a", 1).unwrap()
+        );
+        assert_eq!(
+            "gatc_m_3".parse::<Motif>().unwrap(),
+            Motif::new("GATC", "m", 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_too_long_motif() {
+        let result = Motif::new("GAC N{300} GTT", "a", 1);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Motif sequence of 306 bases is longer than the maximum of 255 bases."
+        );
+
+        // The longest motif still has a correct reverse complement.
+        let motif = Motif::new("GAC N{249} GTT", "a", 1).unwrap();
+        assert_eq!(motif.sequence.len(), MAX_MOTIF_LENGTH);
+        let reverse = motif.reverse_complement();
+        assert_eq!(reverse.mod_position, 253);
+        assert_eq!(reverse.sequence[253], IupacBase::T);
+    }
+
     #[test]
     fn test_out_of_bounds() {
         let result = Motif::new("GATC", "m", 4);
@@ -198,6 +836,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_empty_sequence() {
+        for sequence in ["", "  "] {
+            let result = Motif::new(sequence, "a", 0);
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "motif sequence cannot be empty"
+            );
+        }
+        assert!(Motif::from_bases(Vec::new(), ModType::SixMA, 0).is_err());
+        assert!("_a_0".parse::<Motif>().is_err());
+    }
+
     #[test]
     fn test_invalid_iupac_base() {
         let result = Motif::new("ATZG", "a", 0); // 'G' is invalid for 5mC
@@ -208,6 +859,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_bases() {
+        let motif = Motif::from_bases(parse_iupac_sequence("RGATCY"), ModType::SixMA, 2).unwrap();
+        assert_eq!(motif, Motif::new("RGATCY", "a", 2).unwrap());
+
+        let result = Motif::from_bases(parse_iupac_sequence("GATC"), ModType::FiveMC, 1);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "mod_position 1 points to base 'A' which is invalid for 5mC (m) modification type."
+        );
+
+        let result = Motif::from_bases(parse_iupac_sequence("GATC"), ModType::SixMA, 4);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_with_mod_position() {
+        let motif = Motif::new("GATGATC", "a", 1).unwrap();
+
+        let shifted = motif.with_mod_position(4).unwrap();
+        assert_eq!(shifted.sequence, motif.sequence);
+        assert_eq!(shifted.mod_type, ModType::SixMA);
+        assert_eq!(shifted.mod_position, 4);
+
+        assert_eq!(
+            motif.with_mod_position(2).unwrap_err().to_string(),
+            "mod_position 2 points to base 'T' which is invalid for 6mA."
+        );
+        assert!(motif.with_mod_position(7).is_err());
+    }
+
+    #[test]
+    fn test_with_mod_type() {
+        let motif = Motif::new("GATC", "m", 3).unwrap();
+
+        let motif_4mc = motif.with_mod_type(ModType::FourMC).unwrap();
+        assert_eq!(motif_4mc.mod_type, ModType::FourMC);
+        assert_eq!(motif_4mc.mod_position, 3);
+
+        assert_eq!(
+            motif.with_mod_type(ModType::SixMA).unwrap_err().to_string(),
+            "mod_position 3 points to base 'C' which is invalid for 6mA."
+        );
+    }
+
     #[test]
     fn test_motif_reverse_complement() {
         let motif1 = Motif::new("GATC", "m", 3).unwrap();
@@ -250,4 +946,31 @@ mod tests {
         assert_eq!(motif1.to_regex(), "GATC");
         assert_eq!(motif2.to_regex(), "[AG]GATC[CT]");
     }
+
+    #[test]
+    fn test_bipartite_motif() {
+        let motif = Motif::new("GAC N{6} GTT", "a", 1).unwrap();
+        assert_eq!(motif.sequence, parse_iupac_sequence("GACNNNNNNGTT"));
+        assert_eq!(motif, Motif::new("GACN{6}GTT", "a", 1).unwrap());
+        assert_eq!(motif.to_regex(), "GAC.{6}GTT");
+
+        // The mod position of the reverse complement counts through the gap.
+        let reverse = motif.reverse_complement();
+        assert_eq!(reverse.to_regex(), "AAC.{6}GTC");
+        assert_eq!(reverse.mod_position, 10);
+
+        assert_eq!(Motif::new("GGANTCC", "a", 2).unwrap().to_regex(), "GGA.TCC");
+    }
+
+    #[test]
+    fn test_invalid_gaps() {
+        assert_eq!(
+            Motif::new("GAC N{6,8} GTT", "a", 1).unwrap_err().to_string(),
+            "Variable-length gap N{6,8} in sequence 'GAC N{6,8} GTT' is not supported. Only fixed-length gaps N{k} are."
+        );
+        assert!(Motif::new("GAC N{6 GTT", "a", 1).is_err());
+        assert!(Motif::new("GAC A{6} GTT", "a", 1).is_err());
+        assert!(Motif::new("GAC N{x} GTT", "a", 1).is_err());
+        assert!(Motif::new("GAC N{0} GTT", "a", 1).is_err());
+    }
 }
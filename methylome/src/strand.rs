@@ -9,17 +9,45 @@ pub enum Strand {
     Negative,
 }
 
-impl Display for Strand {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+impl Strand {
+    /// Parses a strand character, `+` or `-`.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::Strand;
+    ///
+    /// assert_eq!(Strand::from_char('-').unwrap(), Strand::Negative);
+    /// assert!(Strand::from_char('.').is_err());
+    /// ```
+    pub fn from_char(c: char) -> Result<Self> {
+        match c {
+            '+' => Ok(Strand::Positive),
+            '-' => Ok(Strand::Negative),
+            _ => bail!("Could not parse '{}' to Strand", c),
+        }
+    }
+
+    /// Returns the opposite strand.
+    ///
+    /// # Examples
+    /// ```
+    /// use methylome::Strand;
+    ///
+    /// assert_eq!(Strand::Positive.complement(), Strand::Negative);
+    /// ```
+    pub fn complement(&self) -> Self {
+        match self {
+            Strand::Positive => Strand::Negative,
+            Strand::Negative => Strand::Positive,
+        }
     }
 }
 
-impl Strand {
-    pub fn to_string(&self) -> String {
+impl Display for Strand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Strand::Positive => "+".to_string(),
-            Strand::Negative => "-".to_string(),
+            Strand::Positive => write!(f, "+"),
+            Strand::Negative => write!(f, "-"),
         }
     }
 }
@@ -43,10 +71,40 @@ impl FromStr for Strand {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "+" => Ok(Strand::Positive),
-            "-" => Ok(Strand::Negative),
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Strand::from_char(c),
             _ => bail!("Could not parse '{}' to Strand", s),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_char() {
+        assert_eq!(Strand::from_char('+').unwrap(), Strand::Positive);
+        assert_eq!(Strand::from_char('-').unwrap(), Strand::Negative);
+        assert!(Strand::from_char('p').is_err());
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("+".parse::<Strand>().unwrap(), Strand::Positive);
+        assert_eq!(Strand::from_str("-").unwrap(), Strand::Negative);
+        assert!("".parse::<Strand>().is_err());
+        assert!("+-".parse::<Strand>().is_err());
+        assert!(".".parse::<Strand>().is_err());
+    }
+
+    #[test]
+    fn test_complement() {
+        assert_eq!(Strand::Positive.complement(), Strand::Negative);
+        assert_eq!(Strand::Negative.complement(), Strand::Positive);
+        for strand in [Strand::Positive, Strand::Negative] {
+            assert_eq!(strand.complement().complement(), strand);
+        }
+    }
+}
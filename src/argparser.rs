@@ -10,5 +10,7 @@ pub struct Args {
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    MethylationPattern(MethylationPatternArgs),
+    MethylationPattern(Box<MethylationPatternArgs>),
+    /// Run methylation-pattern on a small bundled dataset and check the medians.
+    SelfTest,
 }
@@ -0,0 +1,1093 @@
+use ahash::AHashMap;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use csv::{Reader, ReaderBuilder, StringRecord};
+use log::warn;
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::sync_channel,
+        Arc,
+    },
+    thread,
+};
+
+use crate::{
+    data::{
+        contig::Contig,
+        pileup::{ColumnLayout, FractionDenominator, ModCodeCase, PileupRecord},
+        GenomeWorkspace, GenomeWorkspaceBuilder,
+    },
+    data_load::IndexedFasta,
+    extract_methylation_pattern::{
+        coordinates_are_valid, percent_modified_matches, CoordinateBaseCheck,
+    },
+};
+
+/// Unit used when deciding if a batch is full.
+///
+/// Batches are always split at contig boundaries, so a batch can overshoot the
+/// batch size by up to one contig.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BatchBy {
+    /// Number of contigs in the batch.
+    Contigs,
+    /// Total length in base pairs of the contigs in the batch.
+    Bases,
+    /// Number of pileup records in the batch.
+    Records,
+}
+
+/// Estimated memory of one methylation record in a contig: the hash map key
+/// `(position, strand, mod_type)` and `MethylationCoverage`, padded to 24 bytes, plus
+/// the hash map's control bytes and spare capacity.
+const ESTIMATED_BYTES_PER_RECORD: usize = 40;
+
+/// Number of pileup records checked against the assembly for the coordinate base.
+const COORDINATE_BASE_SAMPLES: usize = 1000;
+
+/// Where the loader takes the contig sequences from.
+enum ContigSource<'a> {
+    /// An assembly loaded into memory up front.
+    Loaded(&'a AHashMap<String, Contig>),
+    /// An indexed FASTA read one contig at a time. The contig read last is kept for
+    /// the pileup records following it.
+    Indexed {
+        fasta: Box<IndexedFasta>,
+        current: Option<Contig>,
+    },
+}
+
+impl ContigSource<'_> {
+    fn get(&mut self, contig_id: &str) -> Result<Option<&Contig>> {
+        match self {
+            ContigSource::Loaded(assembly) => Ok(assembly.get(contig_id)),
+            ContigSource::Indexed { fasta, current } => {
+                read_contig(fasta, current, contig_id)?;
+                Ok(current.as_ref())
+            }
+        }
+    }
+
+    /// Returns the contig to add to a batch. An indexed FASTA hands over the contig
+    /// it read unless `keep_current`, so the sequence is not held twice.
+    fn take(&mut self, contig_id: &str, keep_current: bool) -> Result<Option<Contig>> {
+        match self {
+            ContigSource::Loaded(assembly) => Ok(assembly.get(contig_id).cloned()),
+            ContigSource::Indexed { fasta, current } => {
+                read_contig(fasta, current, contig_id)?;
+                Ok(if keep_current {
+                    current.clone()
+                } else {
+                    current.take()
+                })
+            }
+        }
+    }
+}
+
+/// Reads `contig_id` from the FASTA into `current`, unless it is already there.
+fn read_contig(
+    fasta: &mut IndexedFasta,
+    current: &mut Option<Contig>,
+    contig_id: &str,
+) -> Result<()> {
+    if current.as_ref().is_none_or(|contig| contig.id != contig_id) {
+        *current = fasta.fetch(contig_id)?;
+    }
+    Ok(())
+}
+
+/// Reads a pileup sorted by contig and yields `GenomeWorkspace` batches.
+///
+/// Each contig found in the pileup is looked up in the assembly, and its
+/// methylation records with at least `min_valid_read_coverage` (and, if set,
+/// `min_modified_reads`) are added to the current batch.
+pub struct BatchLoader<'a, R: Read> {
+    reader: Reader<R>,
+    assembly: ContigSource<'a>,
+    batch_size: usize,
+    batch_by: BatchBy,
+    min_valid_read_coverage: u32,
+    min_modified_reads: u32,
+    max_plausible_coverage: Option<u32>,
+    percent_tolerance: Option<f64>,
+    validate_coordinates: bool,
+    fraction_denominator: FractionDenominator,
+    mod_code_case: ModCodeCase,
+    layout: ColumnLayout,
+    swap_count_columns: bool,
+    max_memory_bytes: Option<usize>,
+    one_based: bool,
+    contig_limit: Option<usize>,
+
+    record: StringRecord,
+    builder: GenomeWorkspaceBuilder,
+    current_contig: Option<String>,
+    contigs_loaded_in_batch: usize,
+    contigs_loaded: usize,
+    batch_load: usize,
+    batch_bytes: usize,
+    memory_limited_batches: usize,
+    percent_mismatches: usize,
+    coordinate_violations: usize,
+    implausible_coverage_records: usize,
+    zero_coverage_records: usize,
+    coverage_sum: u64,
+    n_coverage_records: u64,
+    coordinate_base_check: CoordinateBaseCheck,
+    records_read: Arc<AtomicUsize>,
+    finished: bool,
+}
+
+impl<'a, R: Read> BatchLoader<'a, R> {
+    /// Creates a loader yielding batches of `batch_size` contigs, or of the unit set
+    /// with `with_batch_by`. A `batch_size` of 0 is treated as 1.
+    ///
+    /// Lines need at least the columns of the layout, 16 for a modkit pileup (up to
+    /// n_fail). Extra trailing columns, as written by some modkit versions, are
+    /// ignored.
+    pub fn new(
+        reader: R,
+        assembly: &'a AHashMap<String, Contig>,
+        batch_size: usize,
+        min_valid_read_coverage: u32,
+    ) -> Self {
+        Self::from_source(
+            reader,
+            ContigSource::Loaded(assembly),
+            batch_size,
+            min_valid_read_coverage,
+        )
+    }
+
+    /// Creates a loader reading the contigs from an indexed FASTA as the pileup
+    /// reaches them, yielding one contig per batch.
+    ///
+    /// Only the sequence and records of the contig being loaded and of the batch
+    /// being processed are held, whatever the size of the assembly. The batch size
+    /// and `with_max_memory` do not apply.
+    pub fn new_lockstep(reader: R, fasta: IndexedFasta, min_valid_read_coverage: u32) -> Self {
+        let source = ContigSource::Indexed {
+            fasta: Box::new(fasta),
+            current: None,
+        };
+        Self::from_source(reader, source, 1, min_valid_read_coverage)
+    }
+
+    fn from_source(
+        reader: R,
+        assembly: ContigSource<'a>,
+        batch_size: usize,
+        min_valid_read_coverage: u32,
+    ) -> Self {
+        let reader = ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'\t')
+            .flexible(true)
+            .from_reader(reader);
+
+        Self {
+            reader,
+            assembly,
+            batch_size: batch_size.max(1),
+            batch_by: BatchBy::Contigs,
+            min_valid_read_coverage,
+            min_modified_reads: 0,
+            max_plausible_coverage: None,
+            percent_tolerance: None,
+            validate_coordinates: false,
+            fraction_denominator: FractionDenominator::default(),
+            mod_code_case: ModCodeCase::default(),
+            layout: ColumnLayout::default(),
+            swap_count_columns: false,
+            max_memory_bytes: None,
+            one_based: false,
+            contig_limit: None,
+            record: StringRecord::with_capacity(100, 18),
+            builder: GenomeWorkspaceBuilder::new(),
+            current_contig: None,
+            contigs_loaded_in_batch: 0,
+            contigs_loaded: 0,
+            batch_load: 0,
+            batch_bytes: 0,
+            memory_limited_batches: 0,
+            percent_mismatches: 0,
+            coordinate_violations: 0,
+            implausible_coverage_records: 0,
+            zero_coverage_records: 0,
+            coverage_sum: 0,
+            n_coverage_records: 0,
+            coordinate_base_check: CoordinateBaseCheck::default(),
+            records_read: Arc::new(AtomicUsize::new(0)),
+            finished: false,
+        }
+    }
+
+    /// Sets the unit `batch_size` is measured in.
+    pub fn with_batch_by(mut self, batch_by: BatchBy) -> Self {
+        self.batch_by = batch_by;
+        self
+    }
+
+    /// Drops records with fewer than `min_modified_reads` modified reads.
+    ///
+    /// Dropped records are not observed sites: they count neither as methylated nor
+    /// as unmethylated, so the motif statistics describe only sites with evidence of
+    /// modification. Sites with high coverage but few modified reads are excluded
+    /// rather than counted as unmethylated, which raises the reported methylation.
+    pub fn with_min_modified_reads(mut self, min_modified_reads: u32) -> Self {
+        self.min_modified_reads = min_modified_reads;
+        self
+    }
+
+    /// Skips records with an n_valid_cov (or n_canonical) above `max_coverage`.
+    ///
+    /// Such values are usually sentinels for missing data, e.g. `u32::MAX`, or parsing
+    /// errors, and would dominate the coverage statistics.
+    pub fn with_max_plausible_coverage(mut self, max_coverage: u32) -> Self {
+        self.max_plausible_coverage = Some(max_coverage);
+        self
+    }
+
+    /// Number of records skipped because of an implausible coverage.
+    pub fn implausible_coverage_records(&self) -> usize {
+        self.implausible_coverage_records
+    }
+
+    /// Number of records skipped because the denominator of their fraction is zero,
+    /// e.g. with `CanonicalPlusMod` when all reads carry another modification.
+    pub fn zero_coverage_records(&self) -> usize {
+        self.zero_coverage_records
+    }
+
+    /// Mean n_valid_cov of the records read so far, including records below
+    /// `min_valid_read_coverage` but not records with an implausible coverage.
+    /// `None` before the first record.
+    pub fn mean_coverage(&self) -> Option<f64> {
+        (self.n_coverage_records > 0)
+            .then(|| self.coverage_sum as f64 / self.n_coverage_records as f64)
+    }
+
+    /// Reads the first pileup line as a header and resolves the columns by name.
+    pub fn with_pileup_header(mut self) -> Result<Self> {
+        let mut header = StringRecord::new();
+        if !self.reader.read_record(&mut header)? {
+            bail!("The pileup is empty, expected a header line.");
+        }
+        self.layout = ColumnLayout::from_header(&header)?;
+        if self.swap_count_columns {
+            self.layout.swap_count_columns();
+        }
+        Ok(self)
+    }
+
+    /// Reads n_valid_cov from the n_modified column and vice versa, for pileups that
+    /// write the modified count first. Records are still rejected if n_modified
+    /// exceeds n_valid_cov after the swap.
+    pub fn with_swapped_count_columns(mut self) -> Self {
+        if !self.swap_count_columns {
+            self.swap_count_columns = true;
+            self.layout.swap_count_columns();
+        }
+        self
+    }
+
+    /// Enables the percent_modified cross-check with the given tolerance in percentage points.
+    pub fn with_percent_check(mut self, tolerance: f64) -> Self {
+        self.percent_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Number of records where percent_modified disagreed with the counts.
+    pub fn percent_mismatches(&self) -> usize {
+        self.percent_mismatches
+    }
+
+    /// Sets the denominator of the methylation fraction. Records are still filtered on
+    /// n_valid_cov.
+    pub fn with_fraction_denominator(mut self, denominator: FractionDenominator) -> Self {
+        self.fraction_denominator = denominator;
+        self
+    }
+
+    /// Also accepts uppercase mod codes in the pileup.
+    pub fn with_case_insensitive_mod_codes(mut self) -> Self {
+        self.mod_code_case = ModCodeCase::Insensitive;
+        self
+    }
+
+    /// Enables checking that every record covers a single base.
+    pub fn with_coordinate_validation(mut self) -> Self {
+        self.validate_coordinates = true;
+        self
+    }
+
+    /// Number of records with reversed or multi-base coordinates.
+    pub fn coordinate_violations(&self) -> usize {
+        self.coordinate_violations
+    }
+
+    /// Reads the pileup positions as 1-based and converts them to 0-based.
+    pub fn with_one_based_positions(mut self) -> Self {
+        self.one_based = true;
+        self
+    }
+
+    /// Stops reading the pileup at the first record of the contig after
+    /// `max_contigs` contigs, giving partial results for quick test runs. Contigs
+    /// without any record passing the filters are not counted.
+    pub fn with_contig_limit(mut self, max_contigs: usize) -> Self {
+        self.contig_limit = Some(max_contigs);
+        self
+    }
+
+    /// Tally of the first pileup records' positions against the assembly bases, read
+    /// before any conversion from 1-based positions.
+    pub fn coordinate_base_check(&self) -> &CoordinateBaseCheck {
+        &self.coordinate_base_check
+    }
+
+    /// Caps the estimated memory of a batch, overriding the batch size if necessary.
+    ///
+    /// A contig is estimated as its sequence length plus
+    /// `ESTIMATED_BYTES_PER_RECORD` per methylation record. A batch is flushed before
+    /// the next contig's sequence would push it over the limit. Contigs are never
+    /// split, so a single dense contig can exceed the limit on its own.
+    pub fn with_max_memory(mut self, max_memory_bytes: usize) -> Self {
+        self.max_memory_bytes = Some(max_memory_bytes);
+        self
+    }
+
+    /// Number of batches flushed early because of the memory limit.
+    pub fn memory_limited_batches(&self) -> usize {
+        self.memory_limited_batches
+    }
+
+    /// Shared counter of pileup records read so far, including records below the
+    /// coverage threshold. It can be read while the loader runs on another thread.
+    pub fn records_read(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.records_read)
+    }
+
+    fn take_batch(&mut self) -> GenomeWorkspace {
+        self.contigs_loaded_in_batch = 0;
+        self.batch_load = 0;
+        self.batch_bytes = 0;
+        std::mem::replace(&mut self.builder, GenomeWorkspaceBuilder::new()).build()
+    }
+
+    fn load_next_batch(&mut self) -> Result<Option<GenomeWorkspace>> {
+        while self.reader.read_record(&mut self.record)? {
+            self.records_read.fetch_add(1, Ordering::Relaxed);
+
+            let mut pileup_record =
+                PileupRecord::from_string_record(&self.record, &self.layout, self.mod_code_case)?;
+
+            if self.validate_coordinates && !coordinates_are_valid(&pileup_record)? {
+                if self.coordinate_violations == 0 {
+                    warn!(
+                        "Record does not cover a single base (start < end, end - start == 1): {:?}",
+                        self.record
+                    );
+                }
+                self.coordinate_violations += 1;
+            }
+
+            if self.coordinate_base_check.n_sampled < COORDINATE_BASE_SAMPLES {
+                if let Some(contig) = self.assembly.get(&pileup_record.contig)? {
+                    self.coordinate_base_check.record(
+                        contig.sequence.as_bytes(),
+                        pileup_record.position,
+                        pileup_record.mod_type,
+                        pileup_record.strand,
+                    );
+                }
+            }
+
+            if self.one_based {
+                pileup_record.position = match pileup_record.position.checked_sub(1) {
+                    Some(position) => position,
+                    None => bail!(
+                        "Position 0 in a 1-based pileup for record: {:?}",
+                        self.record
+                    ),
+                };
+            }
+            if let Some(max_coverage) = self.max_plausible_coverage {
+                let coverage = pileup_record
+                    .n_valid_cov
+                    .max(pileup_record.n_canonical.unwrap_or(0));
+                if coverage > max_coverage {
+                    if self.implausible_coverage_records == 0 {
+                        warn!(
+                            "Skipping record with a coverage above {}: {:?}",
+                            max_coverage, self.record
+                        );
+                    }
+                    self.implausible_coverage_records += 1;
+                    continue;
+                }
+            }
+
+            let n_valid_cov = pileup_record.n_valid_cov;
+            self.coverage_sum += n_valid_cov as u64;
+            self.n_coverage_records += 1;
+            if n_valid_cov < self.min_valid_read_coverage
+                || pileup_record.n_modified < self.min_modified_reads
+            {
+                continue;
+            }
+            // The methylation fraction of such a site is undefined.
+            if pileup_record.coverage(self.fraction_denominator)? == 0 {
+                self.zero_coverage_records += 1;
+                continue;
+            }
+
+            let mut full_batch = None;
+            if self.current_contig.as_ref() != Some(&pileup_record.contig) {
+                if self
+                    .contig_limit
+                    .is_some_and(|limit| self.contigs_loaded >= limit)
+                {
+                    break;
+                }
+
+                let contig_id = &pileup_record.contig;
+                let still_sampling = self.coordinate_base_check.n_sampled < COORDINATE_BASE_SAMPLES;
+                let contig = match self.assembly.take(contig_id, still_sampling)? {
+                    Some(contig) => contig,
+                    None => bail!("Contig not found in assembly: {contig_id}"),
+                };
+                let contig_len = contig.sequence.len();
+
+                if self.contigs_loaded_in_batch > 0 {
+                    let exceeds_memory = self
+                        .max_memory_bytes
+                        .is_some_and(|max_bytes| self.batch_bytes + contig_len > max_bytes);
+                    if self.batch_load >= self.batch_size {
+                        full_batch = Some(self.take_batch());
+                    } else if exceeds_memory {
+                        self.memory_limited_batches += 1;
+                        full_batch = Some(self.take_batch());
+                    }
+                }
+
+                self.builder.add_contig(contig)?;
+                self.batch_bytes += contig_len;
+
+                self.current_contig = Some(contig_id.clone());
+                self.contigs_loaded_in_batch += 1;
+                self.contigs_loaded += 1;
+                match self.batch_by {
+                    BatchBy::Contigs => self.batch_load += 1,
+                    BatchBy::Bases => self.batch_load += contig_len,
+                    BatchBy::Records => {}
+                }
+            }
+
+            if let Some(tolerance) = self.percent_tolerance {
+                if !percent_modified_matches(&pileup_record, tolerance)? {
+                    if self.percent_mismatches == 0 {
+                        warn!(
+                            "percent_modified does not match n_modified / n_valid_cov for record: {:?}. Columns may be misaligned.",
+                            self.record
+                        );
+                    }
+                    self.percent_mismatches += 1;
+                }
+            }
+
+            let methylation_record =
+                pileup_record.into_methylation_record(self.fraction_denominator)?;
+            self.builder.add_record(methylation_record)?;
+            self.batch_bytes += ESTIMATED_BYTES_PER_RECORD;
+
+            if self.batch_by == BatchBy::Records {
+                self.batch_load += 1;
+            }
+
+            if full_batch.is_some() {
+                return Ok(full_batch);
+            }
+        }
+
+        self.finished = true;
+        if self.contigs_loaded_in_batch > 0 {
+            return Ok(Some(self.take_batch()));
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read + Send> BatchLoader<'_, R> {
+    /// Calls `process` on every batch.
+    ///
+    /// With `prefetch > 0` the loader runs on its own thread and sends batches to
+    /// `process` through a channel holding at most `prefetch` batches, so loading
+    /// the next batch (I/O bound) overlaps with processing the current one (CPU
+    /// bound). With `prefetch == 0` batches are loaded and processed in turn.
+    ///
+    /// Returns the loader so its counters can be read afterwards.
+    pub fn process_batches<F>(mut self, prefetch: usize, mut process: F) -> Result<Self>
+    where
+        F: FnMut(GenomeWorkspace) -> Result<()>,
+    {
+        if prefetch == 0 {
+            for workspace in self.by_ref() {
+                process(workspace?)?;
+            }
+            return Ok(self);
+        }
+
+        thread::scope(|scope| {
+            let (sender, receiver) = sync_channel(prefetch);
+
+            let loader = scope.spawn(move || -> Result<Self> {
+                for workspace in self.by_ref() {
+                    // The receiver is only dropped if processing failed.
+                    if sender.send(workspace?).is_err() {
+                        break;
+                    }
+                }
+                Ok(self)
+            });
+
+            for workspace in receiver {
+                process(workspace)?;
+            }
+
+            loader.join().expect("Batch loader thread panicked")
+        })
+    }
+}
+
+impl<R: Read> Iterator for BatchLoader<'_, R> {
+    type Item = Result<GenomeWorkspace>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        match self.load_next_batch() {
+            Ok(Some(workspace)) => Some(Ok(workspace)),
+            Ok(None) => None,
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::{calculate_contig_read_methylation_pattern, PatternOptions};
+    use methylome::{ModType, Strand};
+    use std::io::Cursor;
+
+    fn small_contigs_input() -> (AHashMap<String, Contig>, String) {
+        let mut assembly = AHashMap::new();
+        let mut pileup = String::new();
+        for i in 0..4 {
+            let id = format!("contig_{}", i);
+            assembly.insert(id.clone(), Contig::new(id.clone(), "GATCGATC".to_string()));
+            for pos in [1, 5] {
+                pileup.push_str(&format!(
+                    "{}\t{}\t{}\ta\t20\t+\t0\t1\t255,0,0\t20\t50.00\t10\t10\t0\t0\t0\t0\t0\n",
+                    id,
+                    pos,
+                    pos + 1
+                ));
+            }
+        }
+        (assembly, pileup)
+    }
+
+    fn batch_sizes(loader: BatchLoader<'_, Cursor<&[u8]>>) -> Vec<usize> {
+        loader.map(|ws| ws.unwrap().len()).collect()
+    }
+
+    #[test]
+    fn test_batch_by_contigs() {
+        let (assembly, pileup) = small_contigs_input();
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 3, 3);
+        let records_read = loader.records_read();
+
+        assert_eq!(batch_sizes(loader), vec![3, 1]);
+        assert_eq!(records_read.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn test_batch_by_bases_groups_small_contigs() {
+        let (assembly, pileup) = small_contigs_input();
+
+        // Each contig is 8 bp, so 16 bp fits two contigs per batch.
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 16, 3)
+            .with_batch_by(BatchBy::Bases);
+        assert_eq!(batch_sizes(loader), vec![2, 2]);
+
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1000, 3)
+            .with_batch_by(BatchBy::Bases);
+        assert_eq!(batch_sizes(loader), vec![4]);
+    }
+
+    #[test]
+    fn test_contig_limit() {
+        let (assembly, pileup) = small_contigs_input();
+
+        let loader =
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 3, 3).with_contig_limit(1);
+        let workspaces: Vec<GenomeWorkspace> = loader.map(|ws| ws.unwrap()).collect();
+        assert_eq!(workspaces.len(), 1);
+        let contig_ids: Vec<&String> = workspaces[0].contig_ids().collect();
+        assert_eq!(contig_ids, vec!["contig_0"]);
+
+        // The limit spans batches.
+        let loader =
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 2, 3).with_contig_limit(3);
+        assert_eq!(batch_sizes(loader), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_mean_coverage() {
+        let assembly: AHashMap<String, Contig> = [(
+            "contig_1".to_string(),
+            Contig::new("contig_1".to_string(), "GATCGATC".to_string()),
+        )]
+        .into_iter()
+        .collect();
+        let pileup = "contig_1\t1\t2\ta\t2\t+\t0\t1\t255,0,0\t2\t50.00\t1\t1\t0\t0\t0\t0\t0\n\
+                      contig_1\t5\t6\ta\t5\t+\t0\t1\t255,0,0\t5\t40.00\t2\t3\t0\t0\t0\t0\t0\n";
+
+        let mut loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 10, 3);
+        assert_eq!(loader.mean_coverage(), None);
+        for workspace in loader.by_ref() {
+            workspace.unwrap();
+        }
+
+        // The record below --min-valid-read-coverage still counts.
+        assert_eq!(loader.mean_coverage(), Some(3.5));
+    }
+
+    #[test]
+    fn test_batch_by_records() {
+        let (assembly, pileup) = small_contigs_input();
+
+        // Each contig has two records.
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 6, 3)
+            .with_batch_by(BatchBy::Records);
+        assert_eq!(batch_sizes(loader), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_one_based_positions() -> Result<()> {
+        let (assembly, pileup) = small_contigs_input();
+        let one_based_pileup: String = pileup
+            .lines()
+            .map(|line| {
+                let mut fields: Vec<String> = line.split('\t').map(String::from).collect();
+                for i in [1, 2] {
+                    fields[i] = (fields[i].parse::<usize>()? + 1).to_string();
+                }
+                Ok(fields.join("\t") + "\n")
+            })
+            .collect::<Result<_>>()?;
+
+        let mut loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 4, 3);
+        let zero_based: Vec<GenomeWorkspace> = loader.by_ref().collect::<Result<_>>()?;
+        assert!(loader.coordinate_base_check().looks_zero_based());
+
+        let mut loader =
+            BatchLoader::new(Cursor::new(one_based_pileup.as_bytes()), &assembly, 4, 3)
+                .with_one_based_positions();
+        let one_based: Vec<GenomeWorkspace> = loader.by_ref().collect::<Result<_>>()?;
+        assert!(loader.coordinate_base_check().looks_one_based());
+
+        let positions = |workspaces: &[GenomeWorkspace]| {
+            let mut positions: Vec<(String, usize)> = workspaces
+                .iter()
+                .flat_map(|ws| ws.contigs())
+                .flat_map(|contig| {
+                    contig
+                        .methylated_positions
+                        .keys()
+                        .map(|(position, _, _)| (contig.id.clone(), *position))
+                })
+                .collect();
+            positions.sort();
+            positions
+        };
+        assert_eq!(positions(&one_based), positions(&zero_based));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_modified_reads() -> Result<()> {
+        let assembly = AHashMap::from([(
+            "contig_0".to_string(),
+            Contig::new("contig_0".to_string(), "GATCGATC".to_string()),
+        )]);
+        // Both sites pass the coverage filter, only the first has 3 modified reads.
+        let pileup = "contig_0\t1\t2\ta\t20\t+\t0\t1\t255,0,0\t20\t15.00\t3\t17\t0\t0\t0\t0\t0\n\
+                      contig_0\t5\t6\ta\t20\t+\t0\t1\t255,0,0\t20\t10.00\t2\t18\t0\t0\t0\t0\t0\n";
+
+        let positions = |min_modified_reads: u32| -> Result<Vec<usize>> {
+            let workspaces: Vec<GenomeWorkspace> =
+                BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3)
+                    .with_min_modified_reads(min_modified_reads)
+                    .collect::<Result<_>>()?;
+            let mut positions: Vec<usize> = workspaces
+                .iter()
+                .flat_map(|ws| ws.contigs())
+                .flat_map(|contig| contig.methylated_positions.keys().map(|(p, _, _)| *p))
+                .collect();
+            positions.sort();
+            Ok(positions)
+        };
+
+        assert_eq!(positions(0)?, vec![1, 5]);
+        assert_eq!(positions(3)?, vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_memory_splits_batches() {
+        let (assembly, pileup) = small_contigs_input();
+
+        // Each contig is estimated at 8 + 2 * 40 = 88 bytes.
+        let loader =
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 4, 3).with_max_memory(150);
+        assert_eq!(batch_sizes(loader), vec![2, 2]);
+
+        let mut loader =
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 4, 3).with_max_memory(1);
+        let sizes: Vec<usize> = loader.by_ref().map(|ws| ws.unwrap().len()).collect();
+        assert_eq!(sizes, vec![1, 1, 1, 1]);
+        assert_eq!(loader.memory_limited_batches(), 3);
+
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 4, 3)
+            .with_max_memory(1_000);
+        assert_eq!(batch_sizes(loader), vec![4]);
+    }
+
+    #[test]
+    fn test_process_batches_prefetch_matches_sequential() -> Result<()> {
+        let (assembly, pileup) = small_contigs_input();
+        let motifs = vec![methylome::Motif::new("GATC", "a", 1)?];
+
+        let run = |prefetch: usize| -> Result<Vec<(String, f64, u32)>> {
+            let mut results = Vec::new();
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3).process_batches(
+                prefetch,
+                |workspace| {
+                    let pattern = calculate_contig_read_methylation_pattern(
+                        workspace,
+                        motifs.clone(),
+                        1,
+                        &PatternOptions::default(),
+                    )?;
+                    results.extend(
+                        pattern
+                            .into_iter()
+                            .map(|r| (r.contig, r.stats.median, r.n_motif_obs)),
+                    );
+                    Ok(())
+                },
+            )?;
+            results.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(results)
+        };
+
+        let sequential = run(0)?;
+        assert_eq!(sequential.len(), 4);
+        assert_eq!(run(1)?, sequential);
+        assert_eq!(run(3)?, sequential);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lockstep_matches_batches() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fasta_path = dir.path().join("assembly.fa");
+        let mut assembly = AHashMap::new();
+        let mut fasta = String::new();
+        let mut pileup = String::new();
+        for (i, sequence) in ["GATCGATC", "TTGATCAAGATCGATC", "GATC"].iter().enumerate() {
+            let id = format!("contig_{}", i);
+            assembly.insert(id.clone(), Contig::new(id.clone(), sequence.to_string()));
+            fasta.push_str(&format!(">{}\n{}\n", id, sequence));
+            for (j, position) in sequence
+                .match_indices("GATC")
+                .map(|(p, _)| p + 1)
+                .enumerate()
+            {
+                let n_modified = 5 * (i + j) as u32;
+                pileup.push_str(&format!(
+                    "{}\t{}\t{}\ta\t20\t+\t0\t1\t255,0,0\t20\t0.00\t{}\t{}\t0\t0\t0\t0\t0\n",
+                    id,
+                    position,
+                    position + 1,
+                    n_modified,
+                    20 - n_modified
+                ));
+            }
+        }
+        std::fs::write(&fasta_path, fasta)?;
+        let motifs = vec![methylome::Motif::new("GATC", "a", 1)?];
+
+        // The results and the largest number of contigs in a batch.
+        type Run = (Vec<(String, f64, u32)>, usize);
+        let run = |loader: BatchLoader<'_, Cursor<&[u8]>>| -> Result<Run> {
+            let mut results = Vec::new();
+            let mut max_contigs_held = 0;
+            loader.process_batches(0, |workspace| {
+                max_contigs_held = max_contigs_held.max(workspace.len());
+                let pattern = calculate_contig_read_methylation_pattern(
+                    workspace,
+                    motifs.clone(),
+                    1,
+                    &PatternOptions::default(),
+                )?;
+                results.extend(
+                    pattern
+                        .into_iter()
+                        .map(|r| (r.contig, r.stats.median, r.n_motif_obs)),
+                );
+                Ok(())
+            })?;
+            results.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok((results, max_contigs_held))
+        };
+
+        let batched = run(BatchLoader::new(
+            Cursor::new(pileup.as_bytes()),
+            &assembly,
+            10,
+            3,
+        ))?;
+        let lockstep = run(BatchLoader::new_lockstep(
+            Cursor::new(pileup.as_bytes()),
+            IndexedFasta::open(&fasta_path, false)?,
+            3,
+        ))?;
+
+        assert_eq!(batched.0.len(), 3);
+        assert_eq!(lockstep.0, batched.0);
+        // All contigs are in one batch, but lockstep holds a single contig at a time.
+        assert_eq!(batched.1, 3);
+        assert_eq!(lockstep.1, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_batches_propagates_errors() {
+        let (mut assembly, pileup) = small_contigs_input();
+        assembly.remove("contig_2");
+
+        for prefetch in [0, 1] {
+            let mut n_processed = 0;
+            let result = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3)
+                .process_batches(prefetch, |_| {
+                    n_processed += 1;
+                    Ok(())
+                });
+            assert!(result.is_err());
+            assert_eq!(n_processed, 1);
+        }
+    }
+
+    #[test]
+    fn test_pileup_with_reordered_header() -> Result<()> {
+        let mut assembly = AHashMap::new();
+        assembly.insert(
+            "contig_1".to_string(),
+            Contig::new("contig_1".to_string(), "GATC".to_string()),
+        );
+        let pileup = "#strand\tNmod\tstart\tcontig\tNvalid_cov\tmod_code\n\
+                      +\t5\t1\tcontig_1\t20\ta\n\
+                      -\t10\t2\tcontig_1\t20\ta\n\
+                      -\t1\t2\tcontig_1\t2\tm\n";
+
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3)
+            .with_pileup_header()?;
+        let workspaces: Vec<GenomeWorkspace> = loader.collect::<Result<_>>()?;
+        assert_eq!(workspaces.len(), 1);
+
+        let contig = workspaces[0].contigs().next().unwrap();
+        assert_eq!(contig.methylated_positions.len(), 2);
+        assert_eq!(
+            contig
+                .methylated_positions
+                .get(&(2, methylome::Strand::Negative, methylome::ModType::SixMA))
+                .unwrap()
+                .fraction_modified(),
+            0.5
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coordinate_validation_counts_reversed_records() -> Result<()> {
+        let (assembly, mut pileup) = small_contigs_input();
+        pileup.push_str(
+            "contig_3\t7\t6\ta\t20\t+\t0\t1\t255,0,0\t20\t50.00\t10\t10\t0\t0\t0\t0\t0\n",
+        );
+
+        let mut loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 10, 3)
+            .with_coordinate_validation();
+        for workspace in loader.by_ref() {
+            workspace?;
+        }
+        assert_eq!(loader.coordinate_violations(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_plausible_coverage_skips_sentinel() -> Result<()> {
+        let (assembly, mut pileup) = small_contigs_input();
+        pileup.push_str(&format!(
+            "contig_3\t3\t4\ta\t20\t+\t0\t1\t255,0,0\t{}\t0.00\t0\t0\t0\t0\t0\t0\t0\n",
+            u32::MAX
+        ));
+
+        let n_records = |loader: &mut BatchLoader<'_, Cursor<&[u8]>>| -> Result<usize> {
+            let workspaces: Vec<GenomeWorkspace> = loader.collect::<Result<_>>()?;
+            Ok(workspaces
+                .iter()
+                .flat_map(|ws| ws.contigs())
+                .map(|contig| contig.methylated_positions.len())
+                .sum())
+        };
+
+        let mut unchecked = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 10, 3);
+        assert_eq!(n_records(&mut unchecked)?, 9);
+
+        let mut loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 10, 3)
+            .with_max_plausible_coverage(1_000_000);
+        assert_eq!(n_records(&mut loader)?, 8);
+        assert_eq!(loader.implausible_coverage_records(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_coverage_records_are_skipped() -> Result<()> {
+        let assembly = AHashMap::from([(
+            "contig_0".to_string(),
+            Contig::new("contig_0".to_string(), "GATCGATC".to_string()),
+        )]);
+        // The A at 5 has n_modified + n_canonical == 0: all reads carry another
+        // modification.
+        let pileup = "contig_0\t1\t2\ta\t20\t+\t1\t2\t255,0,0\t20\t50.00\t10\t10\t0\t0\t0\t0\t0\n\
+                      contig_0\t5\t6\ta\t20\t+\t5\t6\t255,0,0\t20\t0.00\t0\t0\t20\t0\t0\t0\t0\n";
+
+        let mut loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 10, 3)
+            .with_fraction_denominator(FractionDenominator::CanonicalPlusMod);
+        let workspaces: Vec<GenomeWorkspace> = loader.by_ref().collect::<Result<_>>()?;
+        assert_eq!(loader.zero_coverage_records(), 1);
+
+        let motifs = vec![methylome::Motif::new("GATC", "a", 1)?];
+        let mut results = Vec::new();
+        for workspace in workspaces {
+            results.extend(calculate_contig_read_methylation_pattern(
+                workspace,
+                motifs.clone(),
+                1,
+                &PatternOptions::default(),
+            )?);
+        }
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].n_motif_obs, 1);
+        assert_eq!(results[0].stats.median, 0.5);
+
+        // With n_valid_cov as the denominator the site is kept, unmethylated.
+        let mut loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 10, 3);
+        let n_records: usize = loader
+            .by_ref()
+            .map(|ws| {
+                ws.map(|ws| {
+                    ws.contigs()
+                        .map(|c| c.methylated_positions.len())
+                        .sum::<usize>()
+                })
+            })
+            .sum::<Result<_>>()?;
+        assert_eq!(n_records, 2);
+        assert_eq!(loader.zero_coverage_records(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swapped_count_columns() -> Result<()> {
+        let assembly = AHashMap::from([(
+            "contig_0".to_string(),
+            Contig::new("contig_0".to_string(), "GATCGATC".to_string()),
+        )]);
+        // n_modified (5) is written where n_valid_cov (20) is expected.
+        let pileup = "contig_0\t1\t2\ta\t20\t+\t0\t1\t255,0,0\t5\t25.00\t20\t15\t0\t0\t0\t0\t0\n";
+
+        let workspaces: Vec<GenomeWorkspace> =
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3)
+                .with_swapped_count_columns()
+                .collect::<Result<_>>()?;
+        let contig = workspaces[0].contigs().next().unwrap();
+        let coverage = contig.methylated_positions[&(1, Strand::Positive, ModType::SixMA)];
+        assert_eq!(coverage.get_n_modified(), 5);
+        assert_eq!(coverage.get_n_valid_cov(), 20);
+
+        // Unswapped, n_modified exceeds n_valid_cov.
+        let unswapped: Result<Vec<GenomeWorkspace>> =
+            BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3).collect();
+        assert!(unswapped.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extra_trailing_columns() -> Result<()> {
+        let (assembly, pileup) = small_contigs_input();
+        // Lines of the same file with 18, 20 and 19 columns.
+        let pileup: String = pileup
+            .lines()
+            .enumerate()
+            .map(|(i, line)| match i % 3 {
+                0 => format!("{}\n", line),
+                1 => format!("{}\textra\t1\n", line),
+                _ => format!("{}\t0.5\n", line),
+            })
+            .collect();
+
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 4, 3);
+        let records_read = loader.records_read();
+        assert_eq!(batch_sizes(loader), vec![4]);
+        assert_eq!(records_read.load(Ordering::Relaxed), 8);
+
+        // Too few columns are still an error.
+        let pileup = "contig_0\t1\t2\ta\t20\t+\t0\t1\t255,0,0\t20\t50.00\t10\n";
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 4, 3);
+        let result: Result<Vec<GenomeWorkspace>> = loader.collect();
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_contig_in_assembly() {
+        let (mut assembly, pileup) = small_contigs_input();
+        assembly.remove("contig_2");
+
+        let loader = BatchLoader::new(Cursor::new(pileup.as_bytes()), &assembly, 1, 3);
+        let result: Result<Vec<GenomeWorkspace>> = loader.collect();
+        assert!(result.is_err());
+    }
+}
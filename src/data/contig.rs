@@ -2,13 +2,17 @@ use ahash::AHashMap;
 use anyhow::{bail, Result};
 
 use super::methylation::*;
-use methylome::{ModType, Strand};
+use methylome::{ModType, MotifSearcher, Strand};
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Contig {
     pub id: String,
     pub sequence: String,
     sequence_len: usize,
+    /// Text after the id in the FASTA header, e.g. `taxid=1234 bin=MAG_07`.
+    pub description: Option<String>,
+    /// Motif occurrences spanning the end to start junction are found as well.
+    pub circular: bool,
     pub methylated_positions: AHashMap<(usize, Strand, ModType), MethylationCoverage>,
 }
 
@@ -20,10 +24,36 @@ impl Contig {
             id,
             sequence,
             sequence_len: sequence_length,
+            description: None,
+            circular: false,
             methylated_positions: AHashMap::new(),
         }
     }
 
+    /// Indices of the modified base of each motif occurrence on `strand`, wrapping
+    /// around the junction if the contig is circular.
+    pub fn find_motif_indices(&self, searcher: &MotifSearcher, strand: Strand) -> Vec<usize> {
+        searcher.indices(&self.sequence, strand, self.circular)
+    }
+
+    /// Number of occurrences of the motif on `strand`, without collecting their
+    /// indices for linear contigs.
+    pub fn count_motif_occurrences(&self, searcher: &MotifSearcher, strand: Strand) -> usize {
+        searcher.count(&self.sequence, strand, self.circular)
+    }
+
+    /// Length of the contig sequence in bp.
+    pub fn len(&self) -> usize {
+        self.sequence_len
+    }
+
+    /// Value of a `key=value` pair in the header description.
+    pub fn header_field(&self, key: &str) -> Option<&str> {
+        header_fields(self.description.as_deref()?)
+            .find(|(field, _)| *field == key)
+            .map(|(_, value)| value)
+    }
+
     pub fn add_methylation(
         &mut self,
         position: usize,
@@ -31,11 +61,11 @@ impl Contig {
         mod_type: ModType,
         meth_coverage: MethylationCoverage,
     ) -> Result<()> {
-        if position as usize >= self.sequence_len {
+        if position >= self.sequence_len {
             bail!("Position out of bounds for '{}': Cannot insert key position ({}) longer than contig length ({})!", self.id, position, self.sequence_len)
         }
 
-        let key = (position, strand.clone(), mod_type.clone());
+        let key = (position, strand, mod_type);
 
         // if self.methylated_positions.contains_key(&key) {
         //     bail!("Methylation record already store for: {} - strand ({}) - modification type ({}) - position '{}'",self.id, strand,mod_type, position)
@@ -58,6 +88,70 @@ impl Contig {
     }
 }
 
+/// Summary statistics of an assembly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblyStats {
+    pub n_contigs: usize,
+    pub total_length: usize,
+    pub n50: usize,
+    /// Fraction of G and C among the A, C, G and T bases. Ambiguous bases such as N
+    /// are not counted. NaN if there are no such bases.
+    pub gc_content: f64,
+}
+
+impl AssemblyStats {
+    pub fn from_contigs<'a, I: IntoIterator<Item = &'a Contig>>(contigs: I) -> Self {
+        let mut lengths = Vec::new();
+        let mut n_gc: u64 = 0;
+        let mut n_acgt: u64 = 0;
+        for contig in contigs {
+            lengths.push(contig.sequence.len());
+            for base in contig.sequence.bytes() {
+                match base.to_ascii_uppercase() {
+                    b'G' | b'C' => {
+                        n_gc += 1;
+                        n_acgt += 1;
+                    }
+                    b'A' | b'T' => n_acgt += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        Self {
+            n_contigs: lengths.len(),
+            total_length: lengths.iter().sum(),
+            n50: n50(&mut lengths),
+            gc_content: n_gc as f64 / n_acgt as f64,
+        }
+    }
+}
+
+/// Length of the shortest contig among the longest contigs that together cover at
+/// least half of the total length. 0 for no contigs.
+pub fn n50(lengths: &mut [usize]) -> usize {
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let total: usize = lengths.iter().sum();
+
+    let mut covered = 0;
+    for &length in lengths.iter() {
+        covered += length;
+        // covered >= total / 2, without rounding down odd totals.
+        if 2 * covered >= total {
+            return length;
+        }
+    }
+    0
+}
+
+/// Parses the whitespace separated `key=value` pairs of a FASTA header description.
+/// Words without `=` are skipped.
+pub fn header_fields(description: &str) -> impl Iterator<Item = (&str, &str)> {
+    description
+        .split_whitespace()
+        .filter_map(|word| word.split_once('='))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,13 +166,13 @@ mod tests {
 
         // Insert 6mA records
         contig
-            .add_methylation(6, Strand::Positive, ModType::SixMA, meth_record1.clone())
+            .add_methylation(6, Strand::Positive, ModType::SixMA, meth_record1)
             .unwrap();
         contig
-            .add_methylation(12, Strand::Positive, ModType::SixMA, meth_record1.clone())
+            .add_methylation(12, Strand::Positive, ModType::SixMA, meth_record1)
             .unwrap();
         contig
-            .add_methylation(13, Strand::Negative, ModType::SixMA, meth_record1.clone())
+            .add_methylation(13, Strand::Negative, ModType::SixMA, meth_record1)
             .unwrap();
 
         // Insert 5mC record
@@ -88,7 +182,7 @@ mod tests {
 
         // Insert unused record that should not be returned
         contig
-            .add_methylation(6, Strand::Positive, ModType::FiveMC, meth_record2.clone())
+            .add_methylation(6, Strand::Positive, ModType::FiveMC, meth_record2)
             .unwrap();
 
         let positions: Vec<usize> = vec![6, 12];
@@ -125,4 +219,28 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_n50() {
+        // Total 100: 40 + 25 = 65 >= 50.
+        assert_eq!(n50(&mut [10, 40, 5, 25, 20]), 25);
+        // Total 20: 10 covers exactly half.
+        assert_eq!(n50(&mut [10, 5, 5]), 10);
+        assert_eq!(n50(&mut [7]), 7);
+        assert_eq!(n50(&mut []), 0);
+    }
+
+    #[test]
+    fn test_assembly_stats() {
+        let contigs = [
+            Contig::new("contig_1".to_string(), "GATCGATC".to_string()),
+            Contig::new("contig_2".to_string(), "ggNN".to_string()),
+        ];
+        let stats = AssemblyStats::from_contigs(&contigs);
+        assert_eq!(stats.n_contigs, 2);
+        assert_eq!(stats.total_length, 12);
+        assert_eq!(stats.n50, 8);
+        // 6 of the 10 A, C, G and T bases are G or C.
+        assert!((stats.gc_content - 0.6).abs() < 1e-12);
+    }
 }
@@ -1,4 +1,5 @@
 use anyhow::{bail, Result};
+use clap::ValueEnum;
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub struct MethylationCoverage {
@@ -22,9 +23,9 @@ impl MethylationCoverage {
         })
     }
 
-    // pub fn get_n_modified(&self) -> u32 {
-    //     self.n_modified
-    // }
+    pub fn get_n_modified(&self) -> u32 {
+        self.n_modified
+    }
 
     pub fn get_n_valid_cov(&self) -> u32 {
         self.n_valid_cov
@@ -35,6 +36,153 @@ impl MethylationCoverage {
     }
 }
 
+/// Which value is the median of an even number of fractions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MedianType {
+    /// The mean of the two central values.
+    #[default]
+    Average,
+    /// The lower of the two central values.
+    Lower,
+    /// The upper of the two central values.
+    Upper,
+}
+
+impl MedianType {
+    /// Median of the sorted, non-empty `fractions`.
+    pub fn median(self, fractions: &[f64]) -> f64 {
+        let n = fractions.len();
+        if !n.is_multiple_of(2) {
+            return fractions[n / 2];
+        }
+        let (lower, upper) = (fractions[n / 2 - 1], fractions[n / 2]);
+        match self {
+            MedianType::Average => (lower + upper) / 2.0,
+            MedianType::Lower => lower,
+            MedianType::Upper => upper,
+        }
+    }
+
+    /// Weighted median of the `(fraction, weight)` pairs sorted by fraction: the first
+    /// fraction at which the cumulative weight exceeds half of the total weight.
+    ///
+    /// If the cumulative weight is exactly half, the fractions on either side are the
+    /// two central values, so equal weights give the same value as `median`. NaN if
+    /// the total weight is zero.
+    pub fn weighted_median(self, sorted: &[(f64, u64)]) -> f64 {
+        let total: u64 = sorted.iter().map(|(_, weight)| weight).sum();
+
+        let mut cumulative = 0;
+        for (i, &(fraction, weight)) in sorted.iter().enumerate() {
+            cumulative += weight;
+            if 2 * cumulative > total {
+                return fraction;
+            }
+            if weight > 0 && 2 * cumulative == total {
+                let upper = sorted[i + 1..]
+                    .iter()
+                    .find(|(_, weight)| *weight > 0)
+                    .map_or(fraction, |(upper, _)| *upper);
+                return match self {
+                    MedianType::Average => (fraction + upper) / 2.0,
+                    MedianType::Lower => fraction,
+                    MedianType::Upper => upper,
+                };
+            }
+        }
+        f64::NAN
+    }
+}
+
+/// Summary statistics of the methylation fractions of a set of sites.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethylationStats {
+    /// Number of sites.
+    pub n: usize,
+    /// Median of the per-site fractions, see `MedianType`.
+    pub median: f64,
+    /// Median of the per-site fractions weighted by n_valid_cov, see
+    /// `MedianType::weighted_median`.
+    pub weighted_median: f64,
+    /// Mean of the per-site fractions.
+    pub mean: f64,
+    /// Pooled fraction `sum(n_modified) / sum(n_valid_cov)`, i.e. the mean weighted by
+    /// coverage.
+    pub weighted_mean: f64,
+    /// Pooled `sum(n_modified)` of the sites.
+    pub total_modified: u64,
+    /// Pooled `sum(n_valid_cov)` of the sites.
+    pub total_valid_cov: u64,
+    pub min: f64,
+    pub max: f64,
+    /// Population standard deviation of the per-site fractions.
+    pub stddev: f64,
+    /// Mean n_valid_cov of the sites.
+    pub mean_read_cov: f64,
+    /// Coefficient of variation of n_valid_cov, `stddev / mean` with the population
+    /// standard deviation. NaN if every site has zero coverage.
+    pub coverage_cv: f64,
+}
+
+impl MethylationStats {
+    /// Computes the statistics of `coverages`. Returns `None` if there are no
+    /// coverages.
+    ///
+    /// Sums of coverages are taken in `u64` and sums of squared coverages in `u128`, so
+    /// they cannot overflow.
+    pub fn from_coverages(
+        coverages: &[MethylationCoverage],
+        median_type: MedianType,
+    ) -> Option<Self> {
+        if coverages.is_empty() {
+            return None;
+        }
+        let n = coverages.len();
+
+        let mut fractions: Vec<f64> = coverages.iter().map(|c| c.fraction_modified()).collect();
+        fractions.sort_by(|a, b| a.total_cmp(b));
+
+        let median = median_type.median(&fractions);
+
+        let mut weighted_fractions: Vec<(f64, u64)> = coverages
+            .iter()
+            .filter(|c| c.n_valid_cov > 0)
+            .map(|c| (c.fraction_modified(), c.n_valid_cov as u64))
+            .collect();
+        weighted_fractions.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let weighted_median = median_type.weighted_median(&weighted_fractions);
+
+        let mean = fractions.iter().sum::<f64>() / n as f64;
+        let variance = fractions.iter().map(|f| (f - mean).powi(2)).sum::<f64>() / n as f64;
+
+        let total_modified: u64 = coverages.iter().map(|c| c.n_modified as u64).sum();
+        let total_valid_cov: u64 = coverages.iter().map(|c| c.n_valid_cov as u64).sum();
+        let squared_valid_cov: u128 = coverages
+            .iter()
+            .map(|c| (c.n_valid_cov as u128).pow(2))
+            .sum();
+        // n^2 * variance, exact in integers.
+        let scaled_coverage_variance =
+            n as u128 * squared_valid_cov - (total_valid_cov as u128).pow(2);
+        let mean_read_cov = total_valid_cov as f64 / n as f64;
+
+        Some(Self {
+            n,
+            median,
+            weighted_median,
+            mean,
+            weighted_mean: total_modified as f64 / total_valid_cov as f64,
+            total_modified,
+            total_valid_cov,
+            min: fractions[0],
+            max: fractions[n - 1],
+            stddev: variance.sqrt(),
+            mean_read_cov,
+            coverage_cv: (scaled_coverage_variance as f64).sqrt() / n as f64 / mean_read_cov,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -65,4 +213,97 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_methylation_stats() -> Result<()> {
+        assert_eq!(
+            MethylationStats::from_coverages(&[], MedianType::Average),
+            None
+        );
+
+        // Fractions 0.1, 0.5, 0.5 and 0.9.
+        let coverages = [
+            MethylationCoverage::new(1, 10)?,
+            MethylationCoverage::new(10, 20)?,
+            MethylationCoverage::new(45, 50)?,
+            MethylationCoverage::new(10, 20)?,
+        ];
+        let stats = MethylationStats::from_coverages(&coverages, MedianType::Average).unwrap();
+
+        assert_eq!(stats.n, 4);
+        assert_eq!(stats.median, 0.5);
+        assert!((stats.mean - 0.5).abs() < 1e-12);
+        // (1 + 10 + 45 + 10) / (10 + 20 + 50 + 20)
+        assert!((stats.weighted_mean - 0.66).abs() < 1e-12);
+        assert_eq!(stats.min, 0.1);
+        assert_eq!(stats.max, 0.9);
+        // sqrt((0.16 + 0 + 0.16 + 0) / 4)
+        assert!((stats.stddev - 0.08f64.sqrt()).abs() < 1e-12);
+        assert_eq!(stats.mean_read_cov, 25.0);
+        // Coverages 10, 20, 50 and 20: sqrt((225 + 25 + 625 + 25) / 4) / 25
+        assert!((stats.coverage_cv - 15.0 / 25.0).abs() < 1e-12);
+
+        let single = MethylationStats::from_coverages(
+            &[MethylationCoverage::new(3, 4)?],
+            MedianType::Average,
+        )
+        .unwrap();
+        assert_eq!(single.median, 0.75);
+        assert_eq!(single.min, single.max);
+        assert_eq!(single.stddev, 0.0);
+        assert_eq!(single.coverage_cv, 0.0);
+
+        // Coverage sums do not overflow u32.
+        let coverages = [MethylationCoverage::new(u32::MAX, u32::MAX)?; 2];
+        let stats = MethylationStats::from_coverages(&coverages, MedianType::Average).unwrap();
+        assert_eq!(stats.mean_read_cov, u32::MAX as f64);
+        assert_eq!(stats.weighted_mean, 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_median_type_even_length() {
+        let fractions = [0.1, 0.4, 0.6, 0.9];
+        assert_eq!(MedianType::Average.median(&fractions), 0.5);
+        assert_eq!(MedianType::Lower.median(&fractions), 0.4);
+        assert_eq!(MedianType::Upper.median(&fractions), 0.6);
+
+        // Odd lengths have a single central value.
+        for median_type in [MedianType::Average, MedianType::Lower, MedianType::Upper] {
+            assert_eq!(median_type.median(&fractions[..3]), 0.4);
+        }
+    }
+
+    #[test]
+    fn test_weighted_median() -> Result<()> {
+        // The 200x site dominates the two 2x sites.
+        let coverages = [
+            MethylationCoverage::new(0, 2)?,
+            MethylationCoverage::new(1, 2)?,
+            MethylationCoverage::new(180, 200)?,
+        ];
+        let stats = MethylationStats::from_coverages(&coverages, MedianType::Average).unwrap();
+        assert_eq!(stats.median, 0.5);
+        assert_eq!(stats.weighted_median, 0.9);
+
+        // Cumulative weight 10 of 20 is reached exactly at 0.2, so 0.2 and 0.6 are
+        // the central values.
+        let sorted = [(0.1, 4), (0.2, 6), (0.6, 5), (0.7, 5)];
+        assert!((MedianType::Average.weighted_median(&sorted) - 0.4).abs() < 1e-12);
+        assert_eq!(MedianType::Lower.weighted_median(&sorted), 0.2);
+        assert_eq!(MedianType::Upper.weighted_median(&sorted), 0.6);
+
+        // Equal weights give the plain median.
+        let fractions = [0.1, 0.2, 0.6, 0.7];
+        let equal: Vec<(f64, u64)> = fractions.iter().map(|&f| (f, 3)).collect();
+        for median_type in [MedianType::Average, MedianType::Lower, MedianType::Upper] {
+            assert_eq!(
+                median_type.weighted_median(&equal),
+                median_type.median(&fractions)
+            );
+        }
+        assert!(MedianType::Average.weighted_median(&[]).is_nan());
+        Ok(())
+    }
 }
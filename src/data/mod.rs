@@ -1,12 +1,17 @@
 pub mod contig;
 pub mod methylation;
+pub mod pileup;
 
-use crate::data::contig::Contig;
+use crate::{
+    data::contig::Contig,
+    processing::{contig_methylation_pattern, MotifMethylationDegree, PatternOptions},
+};
 use ahash::AHashMap;
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use methylation::MethylationCoverage;
-use methylome::{ModType, Strand};
+use methylome::{ModType, Motif, Strand};
 
+#[derive(Debug, PartialEq)]
 pub struct MethylationRecord {
     contig: String,
     position: usize,
@@ -89,8 +94,31 @@ impl GenomeWorkspace {
             contigs: AHashMap::new(),
         }
     }
-    pub fn get_workspace(&self) -> AHashMap<String, Contig> {
-        self.contigs.clone()
+    pub fn contigs(&self) -> impl Iterator<Item = &Contig> {
+        self.contigs.values()
+    }
+
+    pub fn contig_ids(&self) -> impl Iterator<Item = &String> {
+        self.contigs.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.contigs.len()
+    }
+
+    /// Calculates the methylation pattern of the motifs in a single contig of the
+    /// workspace.
+    pub fn contig_methylation_pattern(
+        &self,
+        contig_id: &str,
+        motifs: &[Motif],
+        options: &PatternOptions,
+    ) -> Result<Vec<MotifMethylationDegree>> {
+        let contig = self
+            .contigs
+            .get(contig_id)
+            .ok_or_else(|| anyhow!("Contig not found in workspace: {}", contig_id))?;
+        Ok(contig_methylation_pattern(contig, motifs, options))
     }
 
     fn get_mut_contig(&mut self, id: &str) -> Option<&mut Contig> {
@@ -100,7 +128,10 @@ impl GenomeWorkspace {
 
 #[cfg(test)]
 mod tests {
-    use crate::extract_methylation_pattern::parse_to_methylation_record;
+    use crate::data::{
+        pileup::{ColumnLayout, ModCodeCase, PileupRecord},
+        MethylationRecord,
+    };
 
     use super::*;
     use anyhow::Result;
@@ -115,13 +146,13 @@ mod tests {
     #[test]
     fn test_strand_from_str() -> Result<()> {
         // Mock pileup data lines
-        let pileup_data = vec![
+        let pileup_data = [
             "contig_3\t0\t1\tm\t133\t-\t0\t1\t255,0,0\t133\t0.00\t0\t133\t0\t0\t6\t0\t0",
             "contig_3\t1\t2\ta\t174\t+\t1\t2\t255,0,0\t174\t1.72\t3\t171\t0\t0\t3\t0\t0",
         ];
 
         // Expected results for the strand column
-        let expected_strands = vec![Strand::Negative, Strand::Positive];
+        let expected_strands = [Strand::Negative, Strand::Positive];
 
         // Iterate through pileup data and validate strand parsing
         for (line, &expected_strand) in pileup_data.iter().zip(expected_strands.iter()) {
@@ -169,14 +200,19 @@ mod tests {
         for res in rdr.records() {
             let record = res?;
 
-            let n_valid_cov_str = record.get(9).unwrap();
-            let n_valid_cov = n_valid_cov_str.parse().unwrap();
+            let n_valid_cov: u32 = record.get(9).unwrap().parse().unwrap();
 
             if n_valid_cov < 3 {
                 continue;
             }
-            let meth_record =
-                parse_to_methylation_record("contig_3".to_string(), n_valid_cov, &record).unwrap();
+            let meth_record: MethylationRecord = PileupRecord::from_string_record(
+                &record,
+                &ColumnLayout::default(),
+                ModCodeCase::Exact,
+            )
+            .unwrap()
+            .try_into()
+            .unwrap();
             workspace_builder.add_record(meth_record).unwrap();
         }
 
@@ -235,11 +271,14 @@ mod tests {
 
         for res in rdr.records() {
             let record = res.unwrap();
-
-            let n_valid_cov_str = record.get(9).unwrap();
-            let n_valid_cov = n_valid_cov_str.parse().unwrap();
-            let meth_record =
-                parse_to_methylation_record("contig_1".to_string(), n_valid_cov, &record).unwrap();
+            let meth_record: MethylationRecord = PileupRecord::from_string_record(
+                &record,
+                &ColumnLayout::default(),
+                ModCodeCase::Exact,
+            )
+            .unwrap()
+            .try_into()
+            .unwrap();
             let result = workspace_builder.add_record(meth_record);
             assert!(result.is_err());
         }
@@ -0,0 +1,513 @@
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use csv::StringRecord;
+use methylome::{ModType, Strand};
+
+use super::{methylation::MethylationCoverage, MethylationRecord};
+
+/// Column indices of the pileup fields used for calculating methylation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnLayout {
+    pub contig: usize,
+    pub position: usize,
+    pub end: Option<usize>,
+    pub mod_type: usize,
+    pub strand: usize,
+    pub n_valid_cov: usize,
+    pub percent_modified: Option<usize>,
+    pub n_modified: usize,
+    pub n_canonical: Option<usize>,
+    pub n_fail: Option<usize>,
+}
+
+impl Default for ColumnLayout {
+    /// The fixed column positions of a modkit pileup.
+    fn default() -> Self {
+        Self {
+            contig: 0,
+            position: 1,
+            end: Some(2),
+            mod_type: 3,
+            strand: 5,
+            n_valid_cov: 9,
+            percent_modified: Some(10),
+            n_modified: 11,
+            n_canonical: Some(12),
+            n_fail: Some(15),
+        }
+    }
+}
+
+impl ColumnLayout {
+    /// Resolves the column indices from a pileup header line.
+    ///
+    /// Required columns are `contig`, `start`, `mod_code`, `strand`, `Nvalid_cov` and
+    /// `Nmod`. `end`, `percent_modified`, `Ncanonical` and `Nfail` are optional. A
+    /// leading `#` on the first column name is ignored.
+    pub fn from_header(header: &StringRecord) -> Result<Self> {
+        let names: Vec<&str> = header
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let name = name.trim();
+                if i == 0 {
+                    name.trim_start_matches('#')
+                } else {
+                    name
+                }
+            })
+            .collect();
+
+        let find = |name: &str| names.iter().position(|n| *n == name);
+        let require = |name: &str| {
+            find(name).ok_or_else(|| {
+                anyhow!(
+                    "Pileup header is missing the '{}' column. Found: {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+        };
+
+        Ok(Self {
+            contig: require("contig")?,
+            position: require("start")?,
+            end: find("end"),
+            mod_type: require("mod_code")?,
+            strand: require("strand")?,
+            n_valid_cov: require("Nvalid_cov")?,
+            percent_modified: find("percent_modified"),
+            n_modified: require("Nmod")?,
+            n_canonical: find("Ncanonical"),
+            n_fail: find("Nfail"),
+        })
+    }
+
+    /// Swaps the `n_valid_cov` and `n_modified` columns, for pileups that write the
+    /// modified count first.
+    pub fn swap_count_columns(&mut self) {
+        std::mem::swap(&mut self.n_valid_cov, &mut self.n_modified);
+    }
+
+    /// Number of fields a line needs to hold every column of the layout.
+    pub fn n_fields(&self) -> usize {
+        [
+            Some(self.contig),
+            Some(self.position),
+            self.end,
+            Some(self.mod_type),
+            Some(self.strand),
+            Some(self.n_valid_cov),
+            self.percent_modified,
+            Some(self.n_modified),
+            self.n_canonical,
+            self.n_fail,
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or(0)
+            + 1
+    }
+}
+
+/// Denominator of the methylation fraction of a site.
+///
+/// modkit splits the valid coverage into modified, canonical and other-modification
+/// calls: `n_valid_cov = n_modified + n_canonical + n_other_mod`.
+///
+/// - `ValidCov`: `n_modified / n_valid_cov`, the share of all confident calls that
+///   carry this modification. Reads with another modification at the site (e.g. 5hmC
+///   when counting 5mC) lower the fraction.
+/// - `CanonicalPlusMod`: `n_modified / (n_modified + n_canonical)`, the share of
+///   modified calls among reads that are either modified with this mod type or
+///   unmodified. Other modifications are left out, so the fraction is not diluted
+///   when several modifications compete for the same base.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FractionDenominator {
+    #[default]
+    #[value(alias = "valid_cov")]
+    ValidCov,
+    #[value(alias = "canonical_plus_mod")]
+    CanonicalPlusMod,
+}
+
+/// How the mod code column of the pileup is matched.
+///
+/// `Insensitive` also accepts uppercase mod codes (`A`, `M`), see
+/// [`ModType::from_pileup_code_ignore_case`] for why this is ambiguous.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ModCodeCase {
+    #[default]
+    Exact,
+    Insensitive,
+}
+
+/// The typed fields of a pileup line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PileupRecord {
+    pub contig: String,
+    pub position: usize,
+    /// Only read if the layout has an `end` column.
+    pub end: Option<usize>,
+    pub mod_type: ModType,
+    pub strand: Strand,
+    pub n_valid_cov: u32,
+    /// Only read if the layout has a `percent_modified` column.
+    pub percent_modified: Option<f64>,
+    pub n_modified: u32,
+    /// Only read if the layout has an `Ncanonical` column.
+    pub n_canonical: Option<u32>,
+    /// Only read if the layout has an `Nfail` column.
+    pub n_fail: Option<u32>,
+}
+
+impl PileupRecord {
+    /// Parses the fields at the columns given by `layout`, matching the mod code
+    /// according to `mod_code_case`.
+    ///
+    /// Fails if the record has fewer fields than the layout needs or if a field does
+    /// not parse.
+    pub fn from_string_record(
+        record: &StringRecord,
+        layout: &ColumnLayout,
+        mod_code_case: ModCodeCase,
+    ) -> Result<Self> {
+        if record.len() < layout.n_fields() {
+            bail!(
+                "Expected at least {} fields in the pileup line, found {}.",
+                layout.n_fields(),
+                record.len()
+            );
+        }
+
+        let field = |index: usize, name: &str| {
+            record
+                .get(index)
+                .ok_or_else(|| anyhow!("Missing {} field.", name))
+        };
+        let number = |index: usize, name: &str| -> Result<u32> {
+            field(index, name)?
+                .parse()
+                .map_err(|_| anyhow!("Invalid {} field", name))
+        };
+        let optional_number = |index: Option<usize>, name: &str| -> Result<Option<u32>> {
+            index.map(|index| number(index, name)).transpose()
+        };
+
+        let contig = field(layout.contig, "contig")?.to_string();
+
+        let position: usize = field(layout.position, "position")?
+            .parse()
+            .map_err(|_| anyhow!("Invalid position field"))?;
+
+        let end: Option<usize> = layout
+            .end
+            .map(|index| {
+                field(index, "end")?
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid end field"))
+            })
+            .transpose()?;
+
+        let mod_code = field(layout.mod_type, "modification type")?;
+        let mod_type = match mod_code_case {
+            ModCodeCase::Exact => mod_code.parse()?,
+            ModCodeCase::Insensitive => ModType::from_pileup_code_ignore_case(mod_code)?,
+        };
+
+        let strand: Strand = field(layout.strand, "strand")?.parse()?;
+
+        let n_valid_cov = number(layout.n_valid_cov, "n_valid_coverage")?;
+
+        let percent_modified: Option<f64> = layout
+            .percent_modified
+            .map(|index| {
+                field(index, "percent_modified")?
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid percent_modified field"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            contig,
+            position,
+            end,
+            mod_type,
+            strand,
+            n_valid_cov,
+            percent_modified,
+            n_modified: number(layout.n_modified, "n_modified")?,
+            n_canonical: optional_number(layout.n_canonical, "n_canonical")?,
+            n_fail: optional_number(layout.n_fail, "n_fail")?,
+        })
+    }
+
+    /// Returns the coverage of the site with `denominator`: `n_valid_cov`, or
+    /// `n_modified + n_canonical` with `CanonicalPlusMod`.
+    pub fn coverage(&self, denominator: FractionDenominator) -> Result<u32> {
+        let coverage = match denominator {
+            FractionDenominator::ValidCov => self.n_valid_cov,
+            FractionDenominator::CanonicalPlusMod => {
+                let Some(n_canonical) = self.n_canonical else {
+                    bail!(
+                        "The pileup has no n_canonical column for the canonical-plus-mod fraction."
+                    );
+                };
+                self.n_modified.checked_add(n_canonical).ok_or_else(|| {
+                    anyhow!(
+                        "Coverage overflow: n_modified ({}) + n_canonical ({}) exceeds {}",
+                        self.n_modified,
+                        n_canonical,
+                        u32::MAX
+                    )
+                })?
+            }
+        };
+        Ok(coverage)
+    }
+
+    /// Converts the record using `denominator` as the coverage of the site.
+    ///
+    /// With `CanonicalPlusMod` the stored coverage is `n_modified + n_canonical`, so
+    /// the mean read coverage also reports that sum.
+    pub fn into_methylation_record(
+        self,
+        denominator: FractionDenominator,
+    ) -> Result<MethylationRecord> {
+        let coverage = self.coverage(denominator)?;
+        let methylation = MethylationCoverage::new(self.n_modified, coverage)?;
+
+        Ok(MethylationRecord::new(
+            self.contig,
+            self.position,
+            self.strand,
+            self.mod_type,
+            methylation,
+        ))
+    }
+}
+
+impl TryFrom<PileupRecord> for MethylationRecord {
+    type Error = anyhow::Error;
+
+    /// Converts with the `n_valid_cov` denominator. Fails if `n_modified` exceeds
+    /// `n_valid_cov`.
+    fn try_from(record: PileupRecord) -> Result<Self> {
+        record.into_methylation_record(FractionDenominator::ValidCov)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modkit_line() -> StringRecord {
+        StringRecord::from(vec![
+            "contig_3", "7", "8", "a", "20", "-", "7", "8", "255,0,0", "20", "25.00", "5", "15",
+            "0", "0", "0", "0", "0",
+        ])
+    }
+
+    #[test]
+    fn test_pileup_record_to_methylation_record() -> Result<()> {
+        let pileup_record = PileupRecord::from_string_record(
+            &modkit_line(),
+            &ColumnLayout::default(),
+            ModCodeCase::Exact,
+        )?;
+        assert_eq!(
+            pileup_record,
+            PileupRecord {
+                contig: "contig_3".to_string(),
+                position: 7,
+                end: Some(8),
+                mod_type: ModType::SixMA,
+                strand: Strand::Negative,
+                n_valid_cov: 20,
+                percent_modified: Some(25.0),
+                n_modified: 5,
+                n_canonical: Some(15),
+                n_fail: Some(0),
+            }
+        );
+
+        let expected = MethylationRecord::new(
+            "contig_3".to_string(),
+            7,
+            Strand::Negative,
+            ModType::SixMA,
+            MethylationCoverage::new(5, 20)?,
+        );
+        assert_eq!(
+            MethylationRecord::try_from(pileup_record.clone())?,
+            expected
+        );
+
+        let invalid = PileupRecord {
+            n_modified: 21,
+            ..pileup_record
+        };
+        assert!(MethylationRecord::try_from(invalid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_mod_codes() -> Result<()> {
+        let line = StringRecord::from(vec![
+            "contig_3", "7", "8", "A", "20", "-", "7", "8", "255,0,0", "20", "25.00", "5", "15",
+            "0", "0", "0", "0", "0",
+        ]);
+
+        assert!(PileupRecord::from_string_record(
+            &line,
+            &ColumnLayout::default(),
+            ModCodeCase::Exact
+        )
+        .is_err());
+
+        let record = PileupRecord::from_string_record(
+            &line,
+            &ColumnLayout::default(),
+            ModCodeCase::Insensitive,
+        )?;
+        assert_eq!(record.mod_type, ModType::SixMA);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_layout_from_header() -> Result<()> {
+        let header = StringRecord::from(vec![
+            "#strand",
+            "Nmod",
+            "start",
+            "contig",
+            "Nvalid_cov",
+            "mod_code",
+        ]);
+        let layout = ColumnLayout::from_header(&header)?;
+        assert_eq!(
+            layout,
+            ColumnLayout {
+                contig: 3,
+                position: 2,
+                end: None,
+                mod_type: 5,
+                strand: 0,
+                n_valid_cov: 4,
+                percent_modified: None,
+                n_modified: 1,
+                n_canonical: None,
+                n_fail: None,
+            }
+        );
+
+        let record = StringRecord::from(vec!["-", "5", "7", "contig_3", "20", "a"]);
+        let reordered: MethylationRecord =
+            PileupRecord::from_string_record(&record, &layout, ModCodeCase::Exact)?.try_into()?;
+
+        let record = StringRecord::from(vec![
+            "contig_3", "7", "8", "a", "20", "-", "7", "8", "255,0,0", "20", "25.00", "5", "15",
+            "0", "0", "0", "0", "0",
+        ]);
+        let positional: MethylationRecord = PileupRecord::from_string_record(
+            &record,
+            &ColumnLayout::default(),
+            ModCodeCase::Exact,
+        )?
+        .try_into()?;
+        assert_eq!(reordered, positional);
+
+        let header = StringRecord::from(vec!["contig", "start", "strand"]);
+        assert!(ColumnLayout::from_header(&header).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fraction_denominator() -> Result<()> {
+        // 5 modified, 10 canonical and 5 other-mod calls out of 20 valid.
+        let record = StringRecord::from(vec![
+            "contig_3", "7", "8", "m", "20", "+", "7", "8", "255,0,0", "20", "25.00", "5", "10",
+            "5", "0", "0", "0", "0",
+        ]);
+        let pileup_record = PileupRecord::from_string_record(
+            &record,
+            &ColumnLayout::default(),
+            ModCodeCase::Exact,
+        )?;
+
+        let valid_cov = pileup_record
+            .clone()
+            .into_methylation_record(FractionDenominator::ValidCov)?;
+        assert_eq!(valid_cov.methylation.fraction_modified(), 0.25);
+
+        let canonical_plus_mod =
+            pileup_record.into_methylation_record(FractionDenominator::CanonicalPlusMod)?;
+        assert_eq!(
+            canonical_plus_mod.methylation.fraction_modified(),
+            5.0 / 15.0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_plus_mod_overflow() {
+        let record = PileupRecord {
+            contig: "contig_3".to_string(),
+            position: 7,
+            end: None,
+            mod_type: ModType::SixMA,
+            strand: Strand::Positive,
+            n_valid_cov: u32::MAX,
+            percent_modified: None,
+            n_modified: u32::MAX - 1,
+            n_canonical: Some(2),
+            n_fail: None,
+        };
+        assert!(record
+            .into_methylation_record(FractionDenominator::CanonicalPlusMod)
+            .is_err());
+    }
+
+    fn parse_line(line: &str, layout: &ColumnLayout) -> Result<PileupRecord> {
+        let record = StringRecord::from(line.trim_end().split('\t').collect::<Vec<_>>());
+        PileupRecord::from_string_record(&record, layout, ModCodeCase::Exact)
+    }
+
+    #[test]
+    fn test_parse_pileup_line() -> Result<()> {
+        let line = "contig_3\t7\t8\ta\t20\t-\t7\t8\t255,0,0\t20\t25.00\t5\t15\t0\t0\t1\t0\t0\n";
+        let record = parse_line(line, &ColumnLayout::default())?;
+        assert_eq!(record.contig, "contig_3");
+        assert_eq!(record.position, 7);
+        assert_eq!(record.end, Some(8));
+        assert_eq!(record.mod_type, ModType::SixMA);
+        assert_eq!(record.strand, Strand::Negative);
+        assert_eq!(record.n_valid_cov, 20);
+        assert_eq!(record.percent_modified, Some(25.0));
+        assert_eq!(record.n_modified, 5);
+        assert_eq!(record.n_canonical, Some(15));
+        assert_eq!(record.n_fail, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_malformed_pileup_line() {
+        let layout = ColumnLayout::default();
+
+        let truncated = "contig_3\t7\t8\ta\t20\t-\t7\t8\t255,0,0\t20\t25.00\t5";
+        let err = parse_line(truncated, &layout).unwrap_err();
+        assert!(err.to_string().contains("Expected at least 16 fields"));
+
+        let bad_coverage = "contig_3\t7\t8\ta\t20\t-\t7\t8\t255,0,0\tx\t25.00\t5\t15\t0\t0\t0";
+        let err = parse_line(bad_coverage, &layout).unwrap_err();
+        assert!(err.to_string().contains("n_valid_coverage"));
+
+        let bad_percent = "contig_3\t7\t8\ta\t20\t-\t7\t8\t255,0,0\t20\tNaN%\t5\t15\t0\t0\t0";
+        assert!(parse_line(bad_percent, &layout).is_err());
+    }
+}
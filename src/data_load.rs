@@ -1,11 +1,31 @@
 use ahash::AHashMap;
-use anyhow::Context;
+use anyhow::{bail, Context};
+use log::warn;
+use methylome::IupacBase;
 use seq_io::fasta::{Reader, Record};
-use std::path::Path;
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
 use crate::data::contig::Contig;
 
-pub fn load_contigs<P: AsRef<Path>>(path: P) -> anyhow::Result<AHashMap<String, Contig>> {
+const FASTA_EXTENSIONS: [&str; 4] = ["fa", "fasta", "fna", "fas"];
+
+/// Loads all contigs in a FASTA file.
+///
+/// Sequences are validated against the IUPAC alphabet (case-insensitive). If
+/// `repair_sequence` is set, invalid characters such as gaps (`*`, `-`) are
+/// replaced with `N` instead of failing. Note that a repaired position no longer
+/// matches a motif unless the motif has an `N` at that position. Uracil (`U`) is
+/// read as `T`, as in motifs, so RNA sequences are searched like DNA.
+///
+/// Contig ids must be unique within the file.
+pub fn load_contigs<P: AsRef<Path>>(
+    path: P,
+    repair_sequence: bool,
+) -> anyhow::Result<AHashMap<String, Contig>> {
     let mut fasta_reader = Reader::from_path(&path)
         .with_context(|| format!("Failed to open FASTA at: {:?}", path.as_ref()))?;
 
@@ -22,8 +42,533 @@ pub fn load_contigs<P: AsRef<Path>>(path: P) -> anyhow::Result<AHashMap<String,
         let seq = String::from_utf8(record.owned_seq())
             .with_context(|| format!("Invalid UTF8 character in FASTA record: '{}'", id))?
             .to_string();
+        let seq = validate_sequence(&id, seq, repair_sequence)?;
+
+        let description = record
+            .desc()
+            .transpose()
+            .with_context(|| format!("Invalid UTF8 character in FASTA header: '{}'", id))?
+            .map(String::from);
+
+        if contigs.contains_key(&id) {
+            bail!("Duplicate contig id '{}' in FASTA {:?}", id, path.as_ref());
+        }
+        let mut contig = Contig::new(id.clone(), seq);
+        contig.description = description;
+        contigs.insert(id, contig);
+    }
+    Ok(contigs)
+}
+
+fn validate_sequence(id: &str, sequence: String, repair_sequence: bool) -> anyhow::Result<String> {
+    let sequence = if sequence.contains(['U', 'u']) {
+        sequence.replace('U', "T").replace('u', "t")
+    } else {
+        sequence
+    };
+    let is_valid = |c: char| IupacBase::parse_char(c).is_ok();
+
+    let Some((position, invalid)) = sequence.chars().enumerate().find(|(_, c)| !is_valid(*c))
+    else {
+        return Ok(sequence);
+    };
+
+    if !repair_sequence {
+        bail!(
+            "Contig '{}' contains invalid character '{}' at position {}. Use --repair-sequence to replace invalid characters with N.",
+            id,
+            invalid,
+            position
+        );
+    }
+
+    let mut n_repaired = 0;
+    let repaired: String = sequence
+        .chars()
+        .map(|c| {
+            if is_valid(c) {
+                c
+            } else {
+                n_repaired += 1;
+                'N'
+            }
+        })
+        .collect();
+    warn!(
+        "Replaced {} invalid characters with N in contig '{}'",
+        n_repaired, id
+    );
+
+    Ok(repaired)
+}
+
+/// Loads and merges contigs from several assembly paths.
+///
+/// Each path can either be a FASTA file or a directory. Directories are expanded to
+/// the FASTA files (.fa, .fasta, .fna, .fas) they contain, e.g. a directory of MAGs.
+/// Contig ids must be unique across all files.
+pub fn load_assembly<P: AsRef<Path>>(
+    paths: &[P],
+    repair_sequence: bool,
+) -> anyhow::Result<AHashMap<String, Contig>> {
+    let mut contigs: AHashMap<String, Contig> = AHashMap::new();
+    let mut contig_sources: AHashMap<String, PathBuf> = AHashMap::new();
+
+    for fasta_path in expand_assembly_paths(paths)? {
+        let file_contigs = load_contigs(&fasta_path, repair_sequence)?;
 
-        contigs.insert(id.clone(), Contig::new(id, seq));
+        for (id, contig) in file_contigs {
+            if let Some(previous_path) = contig_sources.get(&id) {
+                bail!(
+                    "Duplicate contig id '{}' found in both {:?} and {:?}",
+                    id,
+                    previous_path,
+                    fasta_path
+                );
+            }
+            contig_sources.insert(id.clone(), fasta_path.clone());
+            contigs.insert(id, contig);
+        }
     }
+
     Ok(contigs)
 }
+
+/// Marks the contigs listed in a file, one contig id per line, as circular.
+///
+/// Only the first whitespace separated field of a line is used, and empty lines and
+/// `#` lines are skipped. Returns the listed ids that are not in the assembly.
+pub fn mark_circular_contigs<P: AsRef<Path>>(
+    contigs: &mut AHashMap<String, Contig>,
+    path: P,
+) -> anyhow::Result<Vec<String>> {
+    let file = File::open(&path).with_context(|| {
+        format!(
+            "Failed to open circular contigs file at: {:?}",
+            path.as_ref()
+        )
+    })?;
+
+    let mut unknown = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some(contig_id) = line.split_whitespace().next() else {
+            continue;
+        };
+        match contigs.get_mut(contig_id) {
+            Some(contig) => contig.circular = true,
+            None => unknown.push(contig_id.to_string()),
+        }
+    }
+
+    Ok(unknown)
+}
+
+/// Location of a contig in a FASTA file, as in a samtools faidx index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FaiEntry {
+    length: usize,
+    /// Byte offset of the first base.
+    offset: u64,
+    line_bases: usize,
+    /// Bytes per line, including the line ending.
+    line_width: usize,
+}
+
+/// A FASTA file read one contig at a time through a samtools faidx index.
+///
+/// Only the index is held in memory, so the assembly can be larger than the memory.
+/// The contigs get no header description.
+pub struct IndexedFasta {
+    reader: BufReader<File>,
+    entries: AHashMap<String, FaiEntry>,
+    repair_sequence: bool,
+    /// Marks every fetched contig as circular.
+    pub circular: bool,
+}
+
+impl IndexedFasta {
+    /// Opens a FASTA file with its index `<path>.fai`. Without an index file, the
+    /// FASTA is indexed in one pass, which needs lines of equal length within each
+    /// contig, like `samtools faidx`. See `load_contigs` for `repair_sequence`.
+    pub fn open<P: AsRef<Path>>(path: P, repair_sequence: bool) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let open = |path: &Path| {
+            File::open(path).with_context(|| format!("Failed to open FASTA at: {:?}", path))
+        };
+
+        let mut fai_path = path.as_os_str().to_owned();
+        fai_path.push(".fai");
+        let fai_path = PathBuf::from(fai_path);
+        let entries = if fai_path.is_file() {
+            read_fai(BufReader::new(open(&fai_path)?))
+                .with_context(|| format!("Invalid FASTA index: {:?}", fai_path))?
+        } else {
+            index_fasta(BufReader::new(open(path)?))
+                .with_context(|| format!("Failed to index FASTA at: {:?}", path))?
+        };
+
+        Ok(Self {
+            reader: BufReader::new(open(path)?),
+            entries,
+            repair_sequence,
+            circular: false,
+        })
+    }
+
+    /// Number of contigs in the index.
+    pub fn n_contigs(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Reads a contig from the FASTA. Returns `None` if the contig is not in the index.
+    pub fn fetch(&mut self, id: &str) -> anyhow::Result<Option<Contig>> {
+        let Some(entry) = self.entries.get(id) else {
+            return Ok(None);
+        };
+
+        let n_bytes = if entry.length == 0 {
+            0
+        } else {
+            let n_full_lines = (entry.length - 1) / entry.line_bases;
+            n_full_lines * entry.line_width + entry.length - n_full_lines * entry.line_bases
+        };
+        let mut bytes = vec![0; n_bytes];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader
+            .read_exact(&mut bytes)
+            .with_context(|| format!("Failed to read contig '{}' from the FASTA", id))?;
+        bytes.retain(|byte| *byte != b'\n' && *byte != b'\r');
+        if bytes.len() != entry.length || bytes.contains(&b'>') {
+            bail!(
+                "Contig '{}' does not match its FASTA index entry. The index may be out of date.",
+                id
+            );
+        }
+
+        let seq = String::from_utf8(bytes)
+            .with_context(|| format!("Invalid UTF8 character in FASTA record: '{}'", id))?;
+        let seq = validate_sequence(id, seq, self.repair_sequence)?;
+        let mut contig = Contig::new(id.to_string(), seq);
+        contig.circular = self.circular;
+        Ok(Some(contig))
+    }
+}
+
+/// Reads a samtools faidx index: name, length, offset, line bases and line width.
+fn read_fai<R: BufRead>(reader: R) -> anyhow::Result<AHashMap<String, FaiEntry>> {
+    let mut entries = AHashMap::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parse = |index: usize| -> anyhow::Result<usize> {
+            fields
+                .get(index)
+                .and_then(|field| field.parse().ok())
+                .with_context(|| format!("Invalid index line {}: '{}'", line_number + 1, line))
+        };
+        let entry = FaiEntry {
+            length: parse(1)?,
+            offset: parse(2)? as u64,
+            line_bases: parse(3)?,
+            line_width: parse(4)?,
+        };
+        if entry.length > 0 && (entry.line_bases == 0 || entry.line_width < entry.line_bases) {
+            bail!("Invalid index line {}: '{}'", line_number + 1, line);
+        }
+        entries.insert(fields[0].to_string(), entry);
+    }
+    Ok(entries)
+}
+
+/// Indexes a FASTA file in one pass, as `samtools faidx` does.
+fn index_fasta<R: BufRead>(mut reader: R) -> anyhow::Result<AHashMap<String, FaiEntry>> {
+    let mut entries = AHashMap::new();
+    let mut current: Option<(String, FaiEntry)> = None;
+    // Set after a line shorter than the line length, which must be the last line.
+    let mut short_line_seen = false;
+    let mut offset: u64 = 0;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let line_width = reader.read_until(b'\n', &mut line)?;
+        if line_width == 0 {
+            break;
+        }
+        offset += line_width as u64;
+
+        if let Some(header) = line.strip_prefix(b">") {
+            if let Some((id, entry)) = current.take() {
+                entries.insert(id, entry);
+            }
+            let header = String::from_utf8_lossy(header);
+            let id = header
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            if entries.contains_key(&id) {
+                bail!("Duplicate contig id '{}' in FASTA", id);
+            }
+            let entry = FaiEntry {
+                length: 0,
+                offset,
+                line_bases: 0,
+                line_width: 0,
+            };
+            current = Some((id, entry));
+            short_line_seen = false;
+            continue;
+        }
+
+        let Some((id, entry)) = current.as_mut() else {
+            bail!("FASTA does not start with a '>' header line");
+        };
+        let line_bases = line
+            .iter()
+            .take_while(|byte| **byte != b'\n' && **byte != b'\r')
+            .count();
+        if entry.length == 0 {
+            entry.line_bases = line_bases;
+            entry.line_width = line_width;
+        } else if short_line_seen || line_bases > entry.line_bases {
+            bail!(
+                "Contig '{}' has lines of different lengths and cannot be indexed. Index it with samtools faidx or reformat the FASTA.",
+                id
+            );
+        }
+        short_line_seen = line_bases < entry.line_bases;
+        entry.length += line_bases;
+    }
+
+    if let Some((id, entry)) = current {
+        entries.insert(id, entry);
+    }
+    Ok(entries)
+}
+
+fn expand_assembly_paths<P: AsRef<Path>>(paths: &[P]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut fasta_paths = Vec::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut dir_paths: Vec<PathBuf> = fs::read_dir(path)
+                .with_context(|| format!("Failed to read assembly directory: {:?}", path))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && is_fasta_path(p))
+                .collect();
+
+            if dir_paths.is_empty() {
+                bail!("No FASTA files found in assembly directory: {:?}", path);
+            }
+
+            dir_paths.sort();
+            fasta_paths.append(&mut dir_paths);
+        } else {
+            fasta_paths.push(path.to_path_buf());
+        }
+    }
+
+    Ok(fasta_paths)
+}
+
+fn is_fasta_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| FASTA_EXTENSIONS.contains(&ext))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_fasta(path: &Path, records: &[(&str, &str)]) {
+        let mut file = File::create(path).unwrap();
+        for (id, seq) in records {
+            writeln!(file, ">{}\n{}", id, seq).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_assembly_multiple_files() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let bin_1 = dir.path().join("bin_1.fa");
+        let bin_2 = dir.path().join("bin_2.fasta");
+        write_fasta(&bin_1, &[("contig_1", "GATC"), ("contig_2", "GGATCC")]);
+        write_fasta(&bin_2, &[("contig_3", "TGGACGATCCCGATC")]);
+
+        let contigs = load_assembly(&[&bin_1, &bin_2], false)?;
+        assert_eq!(contigs.len(), 3);
+        assert_eq!(contigs.get("contig_3").unwrap().sequence, "TGGACGATCCCGATC");
+
+        // A directory expands to the FASTA files it contains.
+        File::create(dir.path().join("notes.txt"))?;
+        let contigs = load_assembly(&[dir.path()], false)?;
+        assert_eq!(contigs.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_circular_contigs() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let fasta = dir.path().join("assembly.fa");
+        // Both contigs have a GATC spanning the end to start junction.
+        write_fasta(
+            &fasta,
+            &[("plasmid", "TCAAAAGA"), ("chromosome", "TCAAAAGA")],
+        );
+        let circular_path = dir.path().join("circular.txt");
+        fs::write(&circular_path, "# circular replicons\nplasmid\nmissing\n\n")?;
+
+        let mut contigs = load_assembly(&[&fasta], false)?;
+        let unknown = mark_circular_contigs(&mut contigs, &circular_path)?;
+        assert_eq!(unknown, vec!["missing".to_string()]);
+
+        let motif = methylome::Motif::new("GATC", "a", 1)?;
+        let searcher = methylome::MotifSearcher::new(&motif);
+        let plasmid = contigs.get("plasmid").unwrap();
+        let chromosome = contigs.get("chromosome").unwrap();
+        assert!(plasmid.circular);
+        assert!(!chromosome.circular);
+        assert_eq!(
+            plasmid.find_motif_indices(&searcher, methylome::Strand::Positive),
+            vec![7]
+        );
+        assert!(chromosome
+            .find_motif_indices(&searcher, methylome::Strand::Positive)
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_assembly_duplicate_ids() {
+        let dir = tempdir().unwrap();
+        let bin_1 = dir.path().join("bin_1.fa");
+        let bin_2 = dir.path().join("bin_2.fa");
+        write_fasta(&bin_1, &[("contig_1", "GATC")]);
+        write_fasta(&bin_2, &[("contig_1", "GGATCC")]);
+
+        let result = load_assembly(&[&bin_1, &bin_2], false);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Duplicate contig id 'contig_1'"));
+
+        // A duplicate within a single file is rejected as well, instead of the later
+        // record replacing the earlier one.
+        write_fasta(&bin_1, &[("contig_1", "GATC"), ("contig_1", "GGATCC")]);
+        for result in [load_contigs(&bin_1, false), load_assembly(&[&bin_1], false)] {
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Duplicate contig id 'contig_1' in FASTA"));
+        }
+    }
+
+    #[test]
+    fn test_load_contigs_header_fields() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let fasta = dir.path().join("assembly.fa");
+        write_fasta(
+            &fasta,
+            &[
+                ("contig_3 taxid=1234 circular bin=MAG_07", "GATC"),
+                ("contig_4", "GATC"),
+            ],
+        );
+
+        let contigs = load_contigs(&fasta, false)?;
+        let contig_3 = contigs.get("contig_3").unwrap();
+        assert_eq!(
+            contig_3.description.as_deref(),
+            Some("taxid=1234 circular bin=MAG_07")
+        );
+        assert_eq!(contig_3.header_field("bin"), Some("MAG_07"));
+        assert_eq!(contig_3.header_field("taxid"), Some("1234"));
+        assert_eq!(contig_3.header_field("circular"), None);
+
+        let contig_4 = contigs.get("contig_4").unwrap();
+        assert_eq!(contig_4.description, None);
+        assert_eq!(contig_4.header_field("bin"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_indexed_fasta() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let fasta = dir.path().join("assembly.fa");
+        fs::write(
+            &fasta,
+            ">contig_1 bin=MAG_07\nGATCGA\nTCAAAA\nGA\n>empty\n>contig_2\r\nGGATCC\r\nA\r\n",
+        )?;
+
+        let mut indexed = IndexedFasta::open(&fasta, false)?;
+        assert_eq!(indexed.n_contigs(), 3);
+        let contigs = load_contigs(&fasta, false)?;
+        for id in ["contig_1", "contig_2", "contig_1"] {
+            let contig = indexed.fetch(id)?.unwrap();
+            assert_eq!(contig.sequence, contigs.get(id).unwrap().sequence);
+            assert!(!contig.circular);
+        }
+        assert_eq!(indexed.fetch("empty")?.unwrap().sequence, "");
+        assert!(indexed.fetch("missing")?.is_none());
+
+        // An existing index is read instead, here of samtools faidx.
+        fs::write(
+            dir.path().join("assembly.fa.fai"),
+            "contig_1\t14\t21\t6\t7\ncontig_2\t7\t56\t6\t8\n",
+        )?;
+        let mut indexed = IndexedFasta::open(&fasta, false)?;
+        assert_eq!(indexed.n_contigs(), 2);
+        assert_eq!(indexed.fetch("contig_2")?.unwrap().sequence, "GGATCCA");
+        assert!(indexed.fetch("empty")?.is_none());
+
+        let ragged = dir.path().join("ragged.fa");
+        fs::write(&ragged, ">contig_1\nGAT\nCGATC\n")?;
+        assert!(IndexedFasta::open(&ragged, false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_contigs_invalid_characters() {
+        let dir = tempdir().unwrap();
+        let fasta = dir.path().join("assembly.fa");
+        write_fasta(&fasta, &[("contig_1", "GAT*Cgatc-N")]);
+
+        let result = load_contigs(&fasta, false);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Contig 'contig_1' contains invalid character '*' at position 3. Use --repair-sequence to replace invalid characters with N."
+        );
+
+        let contigs = load_contigs(&fasta, true).unwrap();
+        assert_eq!(contigs.get("contig_1").unwrap().sequence, "GATNCgatcNN");
+    }
+
+    #[test]
+    fn test_load_contigs_rna() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let fasta = dir.path().join("transcripts.fa");
+        write_fasta(&fasta, &[("transcript_1", "GAUCgauc")]);
+
+        let contigs = load_contigs(&fasta, false)?;
+        assert_eq!(contigs.get("transcript_1").unwrap().sequence, "GATCgatc");
+        let mut indexed = IndexedFasta::open(&fasta, false)?;
+        assert_eq!(indexed.fetch("transcript_1")?.unwrap().sequence, "GATCgatc");
+
+        Ok(())
+    }
+}
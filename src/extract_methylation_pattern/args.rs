@@ -1,27 +1,100 @@
 use clap::Parser;
 
+use crate::{
+    batch_loader::BatchBy,
+    data::{methylation::MedianType, pileup::FractionDenominator},
+    extract_methylation_pattern::output::OutputFormat,
+    postprocessing::Normalization,
+    processing::{CoverageBins, StrandConvention},
+};
+
+/// Batch size without `--batches`.
+pub const DEFAULT_BATCHES: usize = 3000;
+
 #[derive(Parser, Debug, Clone)]
 pub struct MethylationPatternArgs {
     #[arg(short, long, required = true, help = "Path to pileup.")]
     pub pileup: String,
 
-    #[arg(short, long, required = true, help = "Path to assembly.")]
-    pub assembly: String,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "The first line of the pileup is a header. Columns are then found by name (contig, start, mod_code, strand, Nvalid_cov, Nmod and optionally percent_modified) instead of position."
+    )]
+    pub pileup_has_header: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Read n_valid_cov from the n_modified column and vice versa, for pileup dialects that write the modified count first. Records with n_modified > n_valid_cov are still rejected."
+    )]
+    pub swap_count_columns: bool,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        num_args(1..),
+        help = "Path to assembly. Supply several FASTA files or a directory of FASTA files to merge them."
+    )]
+    pub assembly: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Replace characters outside the IUPAC alphabet (e.g. '*' or '-') in the assembly with N instead of failing. Repaired positions only match N in motifs."
+    )]
+    pub repair_sequence: bool,
+
+    #[arg(
+        long,
+        help = "File with one contig id per line of circular contigs, e.g. plasmids. Motif occurrences spanning the end to start junction are found on these contigs. All other contigs are scanned linearly."
+    )]
+    pub circular_contigs: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "circular_contigs",
+        help = "Treat every contig as circular, e.g. for an assembly of closed bacterial genomes and plasmids. Use --circular-contigs to mark only some contigs."
+    )]
+    pub circular: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Search the motif occurrences of contigs with identical sequences only once, e.g. for redundant bins. The methylation is still calculated per contig id from its own pileup records."
+    )]
+    pub dedup_sequences: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Search each linear contig once for all motifs with an Aho-Corasick automaton of their concrete sequences, instead of once per motif with a regex. Faster for many motifs. Motifs with an N or more than 256 concrete sequences, and circular contigs, are still searched with the regex. The results are identical."
+    )]
+    pub aho_corasick: bool,
 
     #[arg(
         short,
         long,
         required = true,
-        help = "Path to output file. Must be .tsv."
+        help = "Path to output file. Must be .tsv, or .json with --format json-by-contig."
     )]
     pub output: String,
 
     #[arg(short, long, default_value_t = 1, help = "Number of parallel tasks.")]
     pub threads: usize,
 
-    #[arg(short, long, required = true, num_args(1..), help = "Supply chain of motifs as <motif>_<mod_type>_<mod_position>. Example: '-m GATC_a_1 RGATCY_a_2'")]
+    #[arg(short, long, required = true, num_args(1..), value_delimiter = ',', help = "Supply chain of motifs as <motif>_<mod_type>_<mod_position>, separated by spaces or commas. Example: '-m GATC_a_1 RGATCY_a_2' or '-m GATC_a_1,RGATCY_a_2'. Without the suffix, the modified base can be written in lowercase instead, e.g. 'GaTC' for GATC_a_1 (a is 6mA, c is 5mC). Several mod positions of one motif are joined by '+', e.g. 'GAAC_a_1+2', and the sites of all positions are aggregated. U is read as T, e.g. 'GAUC_a_1' is searched and reported as 'GATC_a_1'.")]
     pub motifs: Option<Vec<String>>,
 
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Log every invalid motif with the reason and continue with the valid ones instead of failing on the first invalid motif. Fails if no motif is valid."
+    )]
+    pub continue_on_bad_motif: bool,
+
     #[arg(
         long,
         default_value_t = 3,
@@ -31,8 +104,504 @@ pub struct MethylationPatternArgs {
 
     #[arg(
         long,
-        default_value_t = 3000,
-        help = "Number of contigs to process at a time. Higher number will use more RAM."
+        default_value_t = 0,
+        help = "Minimum number of modified reads for a site to be observed. Sites below it are excluded, not counted as unmethylated, so motif methylation is calculated over sites with evidence of modification only. 0 disables the filter."
+    )]
+    pub min_modified_reads: u32,
+
+    #[arg(
+        long,
+        default_value_t = 1_000_000,
+        help = "Skip pileup records with an n_valid_cov or n_canonical above this value. Such values are usually sentinels for missing data (e.g. 4294967295) or parsing errors."
+    )]
+    pub max_plausible_coverage: u32,
+
+    #[arg(
+        long,
+        default_value_t = 5.0,
+        help = "Warn after the run if the mean n_valid_cov of the pileup records is below this. Records skipped by --max-plausible-coverage are not counted."
+    )]
+    pub low_coverage_warn: f64,
+
+    #[arg(
+        long,
+        visible_alias = "batch-size",
+        value_parser = parse_positive_integer,
+        help = "Batch size: number of contigs to process at a time. Higher number will use more RAM. The unit is set by --batch-by. Must be at least 1. Defaults to 3000."
+    )]
+    pub batches: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = BatchBy::Contigs,
+        help = "Count batch size in contigs, total bases or pileup records. Batches are always split between contigs."
+    )]
+    pub batch_by: BatchBy,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Number of batches loaded ahead on a separate thread while the current batch is processed. Each prefetched batch uses additional RAM. 0 loads and processes batches in turn."
+    )]
+    pub prefetch_batches: usize,
+
+    #[arg(
+        long,
+        value_name = "MB",
+        help = "Cap the estimated memory of a batch in MB (1 MB = 1024 * 1024 bytes). A contig is estimated from its sequence length and methylation record count, and a batch is flushed early when the limit is approached, overriding --batches. The limit applies per batch: prefetched batches each use up to this much."
+    )]
+    pub max_memory: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = [
+            "batches",
+            "batch_by",
+            "prefetch_batches",
+            "max_memory",
+            "circular_contigs",
+            "dedup_sequences",
+            "min_genome_occurrences",
+            "check_contig_names",
+            "header_fields",
+            "uncovered_contigs",
+            "assembly_stats",
+        ],
+        help = "Process one contig at a time, reading its sequence from the assembly when the pileup reaches it instead of loading the whole assembly, so memory no longer grows with the assembly: only the contig being processed and the next one are held. The assembly must be a single FASTA file, read through its samtools faidx index (<assembly>.fai), which is built if missing. Options needing the whole assembly up front are not available."
+    )]
+    pub lockstep: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = parse_positive_integer,
+        help = "Stop after the first N contigs of the pileup, e.g. to quickly try motifs and thresholds on a large assembly. The results are partial. Batching is unaffected: the contigs are still split into batches by --batches."
+    )]
+    pub limit_contigs: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Cross-check n_modified / n_valid_cov against the pileup's percent_modified column and warn on disagreement."
+    )]
+    pub check_percent: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "Allowed difference in percentage points when using --check-percent."
+    )]
+    pub percent_tolerance: f64,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Check that every pileup record covers a single base (start < end and end - start == 1) and warn about the number of violations."
+    )]
+    pub validate_coordinates: bool,
+
+    #[arg(
+        long,
+        value_name = "MIN_OVERLAP",
+        num_args = 0..=1,
+        default_missing_value = "0.5",
+        value_parser = parse_fraction,
+        help = "Before processing, check that at least MIN_OVERLAP (default 0.5) of the first 100 distinct pileup contigs are in the assembly, and fail with examples of both naming schemes otherwise."
+    )]
+    pub check_contig_names: Option<f64>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "The pileup positions are 1-based. modkit pileups are 0-based. A warning is given if the first records' bases in the assembly suggest otherwise."
+    )]
+    pub one_based: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Match the pileup mod codes case-insensitively, so uppercase 'A' and 'M' parse as 6mA and 5mC. Only use this if the pileup is known to write uppercase mod codes: uppercase letters usually denote the canonical base or 'any modification' of it."
+    )]
+    pub case_insensitive_mod_codes: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = FractionDenominator::ValidCov,
+        help = "Denominator of the methylation fraction. 'valid-cov' uses n_modified / n_valid_cov. 'canonical-plus-mod' uses n_modified / (n_modified + n_canonical), leaving out reads with other modifications (e.g. 5hmC when counting 5mC) so they do not dilute the fraction. Records are still filtered on n_valid_cov, and records with a denominator of zero are skipped."
+    )]
+    pub fraction_denominator: FractionDenominator,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Normalize the median methylation and add a normalized_median column. 'global-mean' divides each median by the genome-wide mean methylation of the mod type. 'quantile' maps the motif medians of each contig onto a reference distribution shared by all contigs of the mod type. Both are computed after all contigs are processed."
+    )]
+    pub normalize: Option<Normalization>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Report the number of methylation records attributed to more than one motif occurrence (contig, strand and mod type). Helps interpreting inflated N_motif_obs."
+    )]
+    pub report_shared_sites: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Record the time spent scanning and aggregating each motif over all contigs and log a summary, slowest motif first. Times are summed over threads."
+    )]
+    pub timing: bool,
+
+    #[arg(
+        long,
+        help = "Drop motifs occurring fewer times than this across the assembly (both strands) before calculating methylation."
+    )]
+    pub min_genome_occurrences: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Control motif as <motif>_<mod_type>_<mod_position>, e.g. an unmethylated context. Adds a median_minus_control column with the difference between each median and the control motif's median in the same contig."
+    )]
+    pub control_motif: Option<String>,
+
+    #[arg(
+        long,
+        help = "TSV with the reference median methylation per motif (columns motif, mod_type, mod_position and median, e.g. the output rows of the host contig). Adds a median_minus_reference column and a deviating_contig column that is true if any motif of the contig differs from the reference by more than --deviation-threshold."
+    )]
+    pub reference_profile: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 0.3,
+        value_parser = parse_fraction,
+        requires = "reference_profile",
+        help = "Difference in median methylation from --reference-profile above which a contig deviates."
+    )]
+    pub deviation_threshold: f64,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "reference_profile",
+        help = "Only write the rows of contigs that deviate from --reference-profile."
+    )]
+    pub deviating_only: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Only write the rows where every occurrence of the motif in the contig passed the coverage filters, i.e. N_motif_obs equals motif_occurences_total."
+    )]
+    pub require_full_coverage: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Call whether each contig and motif is methylated with a one-sided binomial test of the pooled n_modified / n_valid_cov against --null-rate, corrected for multiple testing with Benjamini-Hochberg. Adds is_methylated and q_value columns."
+    )]
+    pub call_methylation: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0.05,
+        value_parser = parse_fraction,
+        requires = "call_methylation",
+        help = "Fraction of modified reads expected without methylation, e.g. the basecaller's false positive rate, used by --call-methylation."
+    )]
+    pub null_rate: f64,
+
+    #[arg(
+        long,
+        default_value_t = 0.05,
+        value_parser = parse_fraction,
+        requires = "call_methylation",
+        help = "False discovery rate for --call-methylation. Rows with a q_value at or below it are methylated."
+    )]
+    pub fdr: f64,
+
+    #[arg(
+        long,
+        help = "BED file of regions (e.g. genes). Only motif sites inside the regions are used for the methylation pattern."
+    )]
+    pub regions: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "regions",
+        help = "Calculate the methylation per region instead of per contig and add a feature column with the region name (BED column 4, or contig:start-end)."
+    )]
+    pub feature_column: bool,
+
+    #[arg(
+        long,
+        help = "File with one contig id per line. Output rows are ordered by it instead of alphabetically, with unlisted contigs appended alphabetically."
+    )]
+    pub contig_order: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = StrandConvention::Sense,
+        help = "Strand the pileup reports modifications on. 'sense' (modkit) reports the strand of the modified base, 'antisense' reports the opposite strand."
+    )]
+    pub strand_convention: StrandConvention,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MedianType::Average,
+        help = "Median of an even number of sites. 'average' takes the mean of the two central values, 'lower' and 'upper' take one of them."
+    )]
+    pub median_type: MedianType,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add a mode_methylation column with the most frequent methylation fraction (histogram bin center) of the motif sites."
+    )]
+    pub mode_methylation: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "Bin width of the methylation fraction histogram used for --mode-methylation, between 0.0001 and 1."
+    )]
+    pub mode_bin_width: f64,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add a weighted_median column: the median of the motif site methylation fractions with each site weighted by its n_valid_cov, so high coverage sites count more."
+    )]
+    pub weighted_median: bool,
+
+    #[arg(
+        long,
+        value_name = "BP",
+        help = "Add a heterogeneity column: the variance of the median methylation of the motif over windows of BP bases along the contig. High values flag contigs with regions of different methylation, e.g. chimeras. NA if fewer than two windows have sites."
+    )]
+    pub heterogeneity_window: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "EDGES",
+        help = "Stratify the sites of each motif by read coverage into bins given by comma separated edges, e.g. 0,10,30,inf for [0, 10), [10, 30) and [30, inf). Gives one row per contig, motif and bin with sites and adds a coverage_bin column. Sites outside the edges are left out."
+    )]
+    pub coverage_bins: Option<CoverageBins>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add quality control columns: coverage_cv, the coefficient of variation (stddev / mean) of the read coverage of the motif sites. A high value flags uneven coverage."
+    )]
+    pub qc_columns: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add a motif_vs_background column: the mean methylation at the motif sites minus the mean methylation of all other pileup positions with the same mod type in the contig."
+    )]
+    pub motif_score: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add a packed_sites column with n_modified/n_valid_cov of every motif site, separated by ';' (e.g. 15/20;5/20), to recompute any statistic from the output. Rows are capped at 10000 sites."
+    )]
+    pub pack_sites: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add an occurrence_density column with the motif occurrences on both strands per kb of contig (motif_occurences_total / contig length * 1000), to tell common from rare motifs."
+    )]
+    pub occurrence_density: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add a palindromic column that is true for motifs that are their own reverse complement, e.g. GATC, whose sites on the two strands are at the same genomic positions."
+    )]
+    pub palindromic: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Add a hash column with a stable hash of the inputs of every row: the contig sequence, the motif, the pileup counts of its sites and the parameters that change the values. Identical inputs give identical hashes, e.g. to skip recomputation in a pipeline."
+    )]
+    pub provenance_hash: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Write the output in long (tidy) format with one statistic per row: contig, motif, mod_type, mod_position, statistic, value."
+    )]
+    pub tidy: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Tsv,
+        help = "Format of the output file. 'json-by-contig' writes a JSON object with an array of motif objects per contig, and the output must then end in .json. It cannot be combined with --tidy or --split-by-mod-type."
+    )]
+    pub format: OutputFormat,
+
+    #[arg(
+        long,
+        help = "Prepend a sample column with this name to every output row, e.g. to concatenate the outputs of several samples."
+    )]
+    pub sample_name: Option<String>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args(1..),
+        help = "Keys of `key=value` fields in the assembly FASTA headers to write as columns, e.g. `--header-fields taxid,bin` for `>contig_3 taxid=1234 bin=MAG_07`. Contigs without the field get NA."
+    )]
+    pub header_fields: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Write the rows of each mod type to a separate file next to the output, e.g. out.a.tsv and out.m.tsv for -o out.tsv. Only mod types with results get a file."
+    )]
+    pub split_by_mod_type: bool,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(0..=12),
+        help = "Round the reported statistics to this many decimals before writing, using round-half-to-even. Makes the output stable across platforms and versions."
+    )]
+    pub round: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Write progress as JSON ({contigs_done, contigs_total, records_read, elapsed_secs}) to this file after every batch. The file is replaced atomically, so it can be polled safely."
+    )]
+    pub progress_to_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write all warnings of the run to this file as a JSON array of objects with a kind, the message and the fields of the warning, e.g. the contig or the number of records."
+    )]
+    pub warnings_json: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write the assembly contigs that received no methylation records from the pileup (contig, length) to this TSV file."
+    )]
+    pub uncovered_contigs: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write statistics of the assembly (n_contigs, total_length, N50, gc_content) to this TSV file."
+    )]
+    pub assembly_stats: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write every motif site with methylation data (contig, motif, mod_type, mod_position, position, strand, n_modified, n_valid_cov, fraction_modified) to this TSV file."
+    )]
+    pub per_site_output: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "per_site_output",
+        help = "Add a context column to the per-site output with the N bases on each side of the modified base. Contexts of negative strand occurrences are reverse complemented and positions beyond the contig ends are padded with '-'."
+    )]
+    pub context_window: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write one bedGraph per motif (<motif>_<mod_type>_<mod_position>.bedgraph) to this directory, with the methylation fraction of every motif site with methylation data."
     )]
-    pub batches: usize,
+    pub bedgraph_output: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write two BED tracks per motif to this directory, <motif>_<mod_type>_<mod_position>.methylated.bed and .unmethylated.bed, split by --site-bed-threshold. The score column is the methylation fraction scaled to 0-1000."
+    )]
+    pub site_bed_output: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 0.5,
+        value_parser = parse_fraction,
+        requires = "site_bed_output",
+        help = "Sites with a methylation fraction of at least this value are written to the methylated track of --site-bed-output."
+    )]
+    pub site_bed_threshold: f64,
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("{} is not in [0, 1]", fraction));
+    }
+    Ok(fraction)
+}
+
+fn parse_positive_integer(value: &str) -> Result<usize, String> {
+    let n: usize = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a positive integer", value))?;
+    if n == 0 {
+        return Err("the value must be at least 1".to_string());
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_motifs(motifs: &[&str]) -> Option<Vec<String>> {
+        let args = [
+            "epimetheus",
+            "-p",
+            "pileup.bed",
+            "-a",
+            "asm.fa",
+            "-o",
+            "out.tsv",
+            "-m",
+        ];
+        MethylationPatternArgs::try_parse_from(args.iter().chain(motifs))
+            .unwrap()
+            .motifs
+    }
+
+    #[test]
+    fn test_comma_separated_motifs() {
+        let expected = Some(vec!["GATC_a_1".to_string(), "RGATCY_a_2".to_string()]);
+        assert_eq!(parse_motifs(&["GATC_a_1", "RGATCY_a_2"]), expected);
+        assert_eq!(parse_motifs(&["GATC_a_1,RGATCY_a_2"]), expected);
+        assert_eq!(
+            parse_motifs(&["GATC_a_1,RGATCY_a_2", "CCWGG_m_1"]),
+            Some(vec![
+                "GATC_a_1".to_string(),
+                "RGATCY_a_2".to_string(),
+                "CCWGG_m_1".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_positive_integer() {
+        assert_eq!(parse_positive_integer("3000"), Ok(3000));
+        assert_eq!(
+            parse_positive_integer("0"),
+            Err("the value must be at least 1".to_string())
+        );
+        assert!(parse_positive_integer("-1").is_err());
+    }
 }
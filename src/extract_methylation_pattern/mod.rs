@@ -1,28 +1,61 @@
-use anyhow::{anyhow, bail, Context, Result};
-use csv::{ReaderBuilder, StringRecord};
+use ahash::{AHashMap, AHashSet};
+use anyhow::{bail, Context, Result};
 use humantime::format_duration;
 use indicatif::HumanDuration;
 use log::info;
+use methylome::MotifSearcher;
 use std::{
     fs::{self, File},
     io::{BufReader, BufWriter, Write},
     path::Path,
+    sync::{atomic::Ordering, Arc},
     time::Instant,
 };
 
 use crate::{
-    data::{GenomeWorkspaceBuilder, MethylationRecord},
-    data_load::load_contigs,
+    batch_loader::BatchLoader,
+    data::contig::{AssemblyStats, Contig},
+    data_load::{load_assembly, mark_circular_contigs, IndexedFasta},
+    postprocessing::{
+        call_methylation, compare_to_reference, drop_incomplete_rows, load_contig_order,
+        load_reference_profile, normalize_by_global_mean, quantile_normalize, round_statistics,
+        sort_by_contig_order, subtract_control, Normalization,
+    },
     processing::{
-        calculate_contig_read_methylation_pattern, create_motifs, MotifMethylationDegree,
+        calculate_contig_read_methylation_pattern, collect_motif_sites,
+        count_shared_methylation_records, create_motifs, filter_motifs_by_genome_occurrences,
+        find_motif_conflicts, find_uncovered_contigs, partition_motifs, DuplicateSequences,
+        MotifAutomaton, MotifMethylationDegree, MotifTimings, PatternOptions, MAX_PACKED_SITES,
+        MIN_MODE_BIN_WIDTH,
     },
+    provenance::file_hash,
+    regions::load_regions,
 };
 
 pub mod args;
+pub mod output;
+pub mod progress;
 pub mod utils;
+pub mod warnings;
 
 pub use args::MethylationPatternArgs;
-pub use utils::parse_to_methylation_record;
+use args::DEFAULT_BATCHES;
+use output::{
+    write_assembly_stats, write_bedgraph, write_methylation_pattern,
+    write_methylation_pattern_json_by_contig, write_methylation_pattern_tidy, write_motif_sites,
+    write_motif_sites_header, write_site_beds, write_uncovered_contigs, HeaderFields,
+    ModTypeSplitWriter, OutputColumns, OutputFormat, TrackSite,
+};
+use progress::ProgressFile;
+use utils::{
+    check_contig_name_overlap, check_output_directory_writable, check_output_writable,
+    low_coverage_warning, oversized_batch_warning, sample_pileup_contig_ids,
+};
+pub use utils::{coordinates_are_valid, percent_modified_matches, CoordinateBaseCheck};
+use warnings::{FieldValue, WarningCollector};
+
+/// Number of distinct pileup contigs compared to the assembly by --check-contig-names.
+const N_CONTIG_NAME_SAMPLES: usize = 100;
 
 pub fn extract_methylation_pattern(args: MethylationPatternArgs) -> Result<()> {
     info!(
@@ -32,174 +65,1047 @@ pub fn extract_methylation_pattern(args: MethylationPatternArgs) -> Result<()> {
 
     let outpath = Path::new(&args.output);
 
+    if args.format == OutputFormat::JsonByContig && (args.tidy || args.split_by_mod_type) {
+        bail!("--format json-by-contig cannot be combined with --tidy or --split-by-mod-type.");
+    }
+    let expected_extension = args.format.extension();
     if let Some(ext) = outpath.extension() {
-        if ext != "tsv" {
-            anyhow::bail!("Incorrect file extension {:?}. Should be tsv", ext);
+        if ext != expected_extension {
+            anyhow::bail!(
+                "Incorrect file extension {:?}. Should be {}",
+                ext,
+                expected_extension
+            );
         }
         if let Some(parent) = outpath.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("Could not create parent directory: {:?}", parent))?;
         }
     } else {
-        anyhow::bail!("No filename provided for output. Should be a .tsv file.");
+        anyhow::bail!(
+            "No filename provided for output. Should be a .{} file.",
+            expected_extension
+        );
+    }
+    // Fail before the work rather than when the results are written. The files of
+    // --split-by-mod-type are written next to the output path, and the temporary file
+    // of --progress-to-file next to it.
+    check_output_writable(outpath)?;
+    for path in [
+        &args.uncovered_contigs,
+        &args.warnings_json,
+        &args.assembly_stats,
+        &args.per_site_output,
+        &args.progress_to_file,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        check_output_writable(Path::new(path))?;
     }
+    for directory in [&args.bedgraph_output, &args.site_bed_output]
+        .into_iter()
+        .flatten()
+    {
+        check_output_directory_writable(Path::new(directory))?;
+    }
+
+    if !(MIN_MODE_BIN_WIDTH..=1.0).contains(&args.mode_bin_width) {
+        bail!(
+            "--mode-bin-width must be in [{}, 1], got {}",
+            MIN_MODE_BIN_WIDTH,
+            args.mode_bin_width
+        );
+    }
+
+    if args.heterogeneity_window == Some(0) {
+        bail!("--heterogeneity-window must be at least 1 bp");
+    }
+
+    let mut warnings = WarningCollector::new();
 
-    let motifs = match args.motifs {
+    let motifs = match &args.motifs {
         Some(motifs) => {
             info!("Motifs loaded");
-            motifs
+            motifs.clone()
         }
         _ => {
             anyhow::bail!("No motifs found");
         }
     };
 
-    let motifs = create_motifs(motifs).context("Failed to parse motifs")?;
-    info!("Successfully parsed motifs.");
+    let motifs = if args.continue_on_bad_motif {
+        let (valid, invalid) = partition_motifs(motifs);
+        for (motif, e) in &invalid {
+            warnings.warn(
+                "invalid_motif",
+                format!("Skipping invalid motif '{}': {:#}", motif, e),
+                vec![
+                    ("motif", motif.as_str().into()),
+                    ("error", format!("{:#}", e).into()),
+                ],
+            );
+        }
+        if valid.is_empty() {
+            bail!("None of the {} motifs are valid.", invalid.len());
+        }
+        info!(
+            "Parsed {} motifs, skipped {} invalid motifs.",
+            valid.len(),
+            invalid.len()
+        );
+        valid
+    } else {
+        let motifs = create_motifs(motifs).context("Failed to parse motifs")?;
+        info!("Successfully parsed motifs.");
+        motifs
+    };
+    for conflict in find_motif_conflicts(&motifs) {
+        warnings.warn("motif_conflict", conflict, Vec::new());
+    }
+
+    // With --lockstep the contigs are read from the index as the pileup reaches them,
+    // and `contigs` stays empty.
+    let (mut contigs, indexed_assembly) = if args.lockstep {
+        let [path] = args.assembly.as_slice() else {
+            bail!("--lockstep needs a single assembly FASTA file.");
+        };
+        let mut fasta = IndexedFasta::open(path, args.repair_sequence)
+            .with_context(|| format!("Error indexing assembly from path: '{}'", path))?;
+        if fasta.n_contigs() == 0 {
+            anyhow::bail!("No contigs are loaded!");
+        }
+        info!(
+            "Indexed {} contigs of the assembly, read one at a time",
+            fasta.n_contigs()
+        );
+        fasta.circular = args.circular;
+        (AHashMap::new(), Some(fasta))
+    } else {
+        info!("Loading assembly");
+        let contigs = load_assembly(&args.assembly, args.repair_sequence).with_context(|| {
+            format!(
+                "Error loading assembly from path: '{}'",
+                args.assembly.join(", ")
+            )
+        })?;
+
+        if contigs.is_empty() {
+            anyhow::bail!("No contigs are loaded!");
+        }
+        info!("Total contigs in assembly: {}", contigs.len());
+        (contigs, None)
+    };
+    let n_contigs = indexed_assembly
+        .as_ref()
+        .map_or(contigs.len(), IndexedFasta::n_contigs);
+
+    if let Some(path) = &args.assembly_stats {
+        let stats = AssemblyStats::from_contigs(contigs.values());
+        let file =
+            File::create(path).with_context(|| format!("Failed to create file at: {:?}", path))?;
+        write_assembly_stats(&mut BufWriter::new(file), &stats)?;
+        info!(
+            "Assembly: {} contigs, {} bp, N50 {} bp",
+            stats.n_contigs, stats.total_length, stats.n50
+        );
+    }
+
+    let header_fields = HeaderFields::from_contigs(&args.header_fields, contigs.values());
+    let missing_keys = header_fields.missing_keys();
+    if !missing_keys.is_empty() {
+        warnings.warn(
+            "missing_header_fields",
+            format!(
+                "Header fields not found in any contig header: {}. Their columns are NA.",
+                missing_keys.join(", ")
+            ),
+            vec![(
+                "keys",
+                FieldValue::List(missing_keys.iter().map(|key| key.to_string()).collect()),
+            )],
+        );
+    }
+
+    if args.circular {
+        for contig in contigs.values_mut() {
+            contig.circular = true;
+        }
+        info!("Scanning all {} contigs as circular", n_contigs);
+    }
+
+    if let Some(path) = &args.circular_contigs {
+        let unknown = mark_circular_contigs(&mut contigs, path)
+            .with_context(|| format!("Error loading circular contigs from path: '{}'", path))?;
+        for contig_id in &unknown {
+            warnings.warn(
+                "missing_contig",
+                format!("Circular contig '{}' is not in the assembly", contig_id),
+                vec![
+                    ("contig", contig_id.as_str().into()),
+                    ("source", "circular_contigs".into()),
+                ],
+            );
+        }
+        let n_circular = contigs.values().filter(|contig| contig.circular).count();
+        info!("Scanning {} contigs as circular", n_circular);
+    }
+
+    let mut motifs = match args.min_genome_occurrences {
+        Some(min_occurrences) => {
+            let (kept, dropped) =
+                filter_motifs_by_genome_occurrences(&contigs, motifs, min_occurrences);
+            for (motif, n) in &dropped {
+                info!(
+                    "Dropping motif {}: {} occurrences in assembly (< {})",
+                    motif, n, min_occurrences
+                );
+            }
+            if kept.is_empty() {
+                bail!(
+                    "No motifs occur at least {} times in the assembly.",
+                    min_occurrences
+                );
+            }
+            kept
+        }
+        None => motifs,
+    };
+
+    let control_motif = match &args.control_motif {
+        Some(control) => {
+            let control = create_motifs(vec![control.clone()])
+                .context("Failed to parse control motif")?
+                .remove(0);
+            if !motifs.contains(&control) {
+                motifs.push(control.clone());
+            }
+            Some(control)
+        }
+        None => None,
+    };
+
+    let contig_order = args
+        .contig_order
+        .as_ref()
+        .map(|path| {
+            load_contig_order(path)
+                .with_context(|| format!("Error loading contig order from path: '{}'", path))
+        })
+        .transpose()?;
 
-    info!("Loading assembly");
-    let contigs = load_contigs(&args.assembly)
-        .with_context(|| format!("Error loading assembly from path: '{}'", args.assembly))?;
+    let reference_profile = args
+        .reference_profile
+        .as_ref()
+        .map(|path| {
+            load_reference_profile(path)
+                .with_context(|| format!("Error loading reference profile from path: '{}'", path))
+        })
+        .transpose()?;
+    if let Some(profile) = &reference_profile {
+        info!("Loaded a reference profile of {} motifs", profile.len());
+    }
+
+    let regions = match &args.regions {
+        Some(path) => {
+            let regions = load_regions(path)
+                .with_context(|| format!("Error loading regions from path: '{}'", path))?;
+            info!("Loaded {} regions", regions.n_intervals());
+            Some(Arc::new(regions))
+        }
+        None => None,
+    };
+
+    if let Some(min_overlap) = args.check_contig_names {
+        let file = File::open(&args.pileup)?;
+        let pileup_ids =
+            sample_pileup_contig_ids(file, args.pileup_has_header, N_CONTIG_NAME_SAMPLES)?;
+        check_contig_name_overlap(&contigs, &pileup_ids, min_overlap)?;
+        info!("Contig names of the pileup and assembly match.");
+    }
 
-    if contigs.len() == 0 {
-        anyhow::bail!("No contigs are loaded!");
+    // Only an explicit --batches is checked: the default exceeds small assemblies,
+    // which are then simply loaded in one batch.
+    if let (None, Some(batches)) = (&indexed_assembly, args.batches) {
+        if let Some(warning) = oversized_batch_warning(&contigs, batches, args.batch_by) {
+            warnings.warn(
+                "oversized_batch",
+                warning,
+                vec![("batches", batches.into())],
+            );
+        }
     }
-    info!("Total contigs in assembly: {}", contigs.len());
 
     info!("Processing Pileup");
     let file = File::open(&args.pileup)?;
     let reader = BufReader::new(file);
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(false)
-        .delimiter(b'\t')
-        .flexible(false)
-        .from_reader(reader);
-    let mut record = StringRecord::with_capacity(100, 18);
 
-    let mut builder = GenomeWorkspaceBuilder::new();
+    let batch_loader = match indexed_assembly {
+        Some(fasta) => BatchLoader::new_lockstep(reader, fasta, args.min_valid_read_coverage),
+        None => BatchLoader::new(
+            reader,
+            &contigs,
+            args.batches.unwrap_or(DEFAULT_BATCHES),
+            args.min_valid_read_coverage,
+        )
+        .with_batch_by(args.batch_by),
+    };
+    let mut batch_loader = batch_loader
+        .with_fraction_denominator(args.fraction_denominator)
+        .with_min_modified_reads(args.min_modified_reads)
+        .with_max_plausible_coverage(args.max_plausible_coverage);
+    if args.swap_count_columns {
+        batch_loader = batch_loader.with_swapped_count_columns();
+    }
+    if args.pileup_has_header {
+        batch_loader = batch_loader.with_pileup_header()?;
+    }
+    if args.check_percent {
+        batch_loader = batch_loader.with_percent_check(args.percent_tolerance);
+    }
+    if args.validate_coordinates {
+        batch_loader = batch_loader.with_coordinate_validation();
+    }
+    if args.one_based {
+        batch_loader = batch_loader.with_one_based_positions();
+    }
+    if let Some(max_memory) = args.max_memory {
+        batch_loader = batch_loader.with_max_memory(max_memory.saturating_mul(1024 * 1024));
+    }
+    if args.case_insensitive_mod_codes {
+        batch_loader = batch_loader.with_case_insensitive_mod_codes();
+    }
+    if let Some(limit) = args.limit_contigs {
+        warnings.warn(
+            "partial_results",
+            format!(
+                "--limit-contigs {}: only the first {} contigs of the pileup are processed, so the results are partial.",
+                limit, limit
+            ),
+            vec![("limit_contigs", limit.into())],
+        );
+        batch_loader = batch_loader.with_contig_limit(limit);
+    }
+    let contigs_total = args
+        .limit_contigs
+        .map_or(n_contigs, |limit| limit.min(n_contigs));
 
-    let mut current_contig: Option<String> = None;
-    let mut contigs_loaded = 0;
-    let mut contigs_processed = 0;
+    let duplicate_sequences = args.dedup_sequences.then(|| {
+        let duplicates = DuplicateSequences::new(&contigs);
+        info!(
+            "Found {} contigs with the sequence of another contig. Their motif occurrences are searched once.",
+            duplicates.n_duplicates()
+        );
+        Arc::new(duplicates)
+    });
+
+    let motif_automaton = if args.aho_corasick {
+        let automaton = MotifAutomaton::new(&motifs)?;
+        info!(
+            "Searching {} of {} motifs in a single pass per contig.",
+            automaton.n_motifs(),
+            motifs.len()
+        );
+        Some(Arc::new(automaton))
+    } else {
+        None
+    };
+
+    let pattern_options = PatternOptions {
+        strand_convention: args.strand_convention,
+        mode_bin_width: args.mode_methylation.then_some(args.mode_bin_width),
+        regions,
+        per_feature: args.feature_column,
+        motif_score: args.motif_score,
+        pack_sites: args.pack_sites,
+        occurrence_density: args.occurrence_density,
+        provenance_parameters: args
+            .provenance_hash
+            .then(|| provenance_parameters(&args))
+            .transpose()?,
+        median_type: args.median_type,
+        heterogeneity_window: args.heterogeneity_window,
+        coverage_bins: args.coverage_bins.clone(),
+        timings: args.timing.then(|| Arc::new(MotifTimings::new(&motifs))),
+        duplicate_sequences,
+        motif_automaton,
+        motif_searchers: motifs.iter().map(MotifSearcher::new).collect(),
+    };
+
+    let records_read = batch_loader.records_read();
+    let progress_file = args.progress_to_file.as_ref().map(ProgressFile::new);
+    if let Some(progress_file) = &progress_file {
+        progress_file.update(0, contigs_total, 0)?;
+    }
+
+    let mut per_site_writer = match &args.per_site_output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create file at: {:?}", path))?;
+            let mut writer = BufWriter::new(file);
+            write_motif_sites_header(&mut writer, args.context_window.is_some())?;
+            Some(writer)
+        }
+        None => None,
+    };
+
+    // Sites for the per-motif bedGraph and BED tracks by motif index, written after all
+    // batches.
+    let mut track_sites: Option<Vec<Vec<TrackSite>>> = (args.bedgraph_output.is_some()
+        || args.site_bed_output.is_some())
+    .then(|| vec![Vec::new(); motifs.len()]);
 
-    let mut methylation_records: Vec<MethylationRecord> = Vec::new();
+    let mut contigs_processed = 0;
+    let mut shared_methylation_records: usize = 0;
+    let mut covered_contigs: AHashSet<String> = AHashSet::new();
     let mut methylation_pattern_results: Vec<MotifMethylationDegree> = Vec::new();
 
     let mut batch_loading_duration = Instant::now();
-    while rdr.read_record(&mut record)? {
-        let n_valid_cov: u32 = record
-            .get(9)
-            .ok_or_else(|| anyhow!("Missing n_valid_coverage field"))?
-            .parse()
-            .map_err(|_| anyhow!("Invalid coverage number."))?;
-        if n_valid_cov < args.min_valid_read_coverage {
-            continue;
-        }
-
-        let contig_id = record
-            .get(0)
-            .ok_or_else(|| anyhow!("Missing contig field"))?
-            .to_string();
-
-        if current_contig.as_ref() != Some(&contig_id) {
-            current_contig = Some(contig_id.clone());
-            contigs_loaded += 1;
-
-            if contigs_loaded > args.batches {
-                let elapsed_batch_loading_duration = batch_loading_duration.elapsed();
-                info!(
-                    "Loading {} contigs took: {}.",
-                    &args.batches,
-                    format_duration(elapsed_batch_loading_duration).to_string()
-                );
-                for meth_rec in methylation_records.drain(..) {
-                    builder.add_record(meth_rec)?;
+    // A prefetched batch would hold a second contig in lockstep.
+    let prefetch_batches = if args.lockstep {
+        0
+    } else {
+        args.prefetch_batches
+    };
+    let batch_loader = batch_loader.process_batches(prefetch_batches, |workspace| {
+        let contigs_in_batch = workspace.len();
+
+        let elapsed_batch_loading_duration = batch_loading_duration.elapsed();
+        info!(
+            "Waiting for {} contigs took: {}.",
+            contigs_in_batch,
+            format_duration(elapsed_batch_loading_duration).to_string()
+        );
+
+        if args.uncovered_contigs.is_some() {
+            covered_contigs.extend(workspace.contigs().map(|contig| contig.id.clone()));
+        }
+
+        if args.report_shared_sites {
+            shared_methylation_records += workspace
+                .contigs()
+                .map(|contig| count_shared_methylation_records(contig, &motifs, &pattern_options))
+                .sum::<usize>();
+        }
+
+        if per_site_writer.is_some() || track_sites.is_some() {
+            let mut batch_contigs: Vec<&Contig> = workspace.contigs().collect();
+            batch_contigs.sort_by(|a, b| a.id.cmp(&b.id));
+            for contig in batch_contigs {
+                let contig_id: Arc<str> = contig.id.as_str().into();
+                for (i, motif) in motifs.iter().enumerate() {
+                    let sites = collect_motif_sites(
+                        contig,
+                        std::slice::from_ref(motif),
+                        &pattern_options,
+                        args.context_window,
+                    );
+                    if let Some(writer) = &mut per_site_writer {
+                        write_motif_sites(writer, &sites, args.context_window.is_some())?;
+                    }
+                    if let Some(track_sites) = &mut track_sites {
+                        track_sites[i]
+                            .extend(sites.iter().map(|site| TrackSite::new(&contig_id, site)));
+                    }
                 }
+            }
+        }
 
-                let workspace = builder.build();
+        info!("Calculating methylation patten.");
+        let calculate_methylation_pattern_duration = Instant::now();
+        let mut methylation_pattern = calculate_contig_read_methylation_pattern(
+            workspace,
+            motifs.clone(),
+            args.threads,
+            &pattern_options,
+        )?;
+        let elapsed_calculate_methylation_pattern_duration =
+            calculate_methylation_pattern_duration.elapsed();
+        info!(
+            "Calculating methylation pattern took: {} - ({})",
+            HumanDuration(elapsed_calculate_methylation_pattern_duration).to_string(),
+            format_duration(elapsed_calculate_methylation_pattern_duration).to_string()
+        );
 
-                info!("Calculating methylation patten.");
-                let calculate_methylation_pattern_duration = Instant::now();
-                let mut methylation_pattern = calculate_contig_read_methylation_pattern(
-                    workspace,
-                    motifs.clone(),
-                    args.threads,
-                )?;
-                let elapsed_calculate_methylation_pattern_duration =
-                    calculate_methylation_pattern_duration.elapsed();
-                info!(
-                    "Calculating methylation pattern took: {} - ({})",
-                    HumanDuration(elapsed_calculate_methylation_pattern_duration).to_string(),
-                    format_duration(elapsed_calculate_methylation_pattern_duration).to_string()
-                );
+        methylation_pattern_results.append(&mut methylation_pattern);
 
-                methylation_pattern_results.append(&mut methylation_pattern);
+        contigs_processed += contigs_in_batch;
+        info!("Finished processing {}", contigs_processed);
+        if let Some(progress_file) = &progress_file {
+            progress_file.update(
+                contigs_processed,
+                contigs_total,
+                records_read.load(Ordering::Relaxed),
+            )?;
+        }
+
+        batch_loading_duration = Instant::now();
+        Ok(())
+    })?;
 
-                contigs_processed += contigs_loaded - 1;
-                info!("Finished processing {}", contigs_processed);
+    if let Some(mut writer) = per_site_writer {
+        writer.flush()?;
+    }
 
-                builder = GenomeWorkspaceBuilder::new();
-                batch_loading_duration = Instant::now();
-                contigs_loaded = 1;
+    if let Some(mut track_sites) = track_sites {
+        if let Some(dir) = &args.bedgraph_output {
+            for (motif, sites) in motifs.iter().zip(track_sites.iter_mut()) {
+                let path = Path::new(dir).join(format!("{}.bedgraph", motif));
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create file at: {:?}", path))?;
+                write_bedgraph(&mut BufWriter::new(file), motif, sites)?;
             }
+            info!("Wrote {} bedGraph tracks to {}", motifs.len(), dir);
+        }
 
-            let contig = match contigs.get(&contig_id) {
-                Some(contig) => contig,
-                None => bail!("Contig not found in assembly: {contig_id}"),
-            };
-            builder.add_contig(contig.clone())?;
+        if let Some(dir) = &args.site_bed_output {
+            for (motif, sites) in motifs.iter().zip(track_sites.iter_mut()) {
+                let create = |label: &str| -> Result<BufWriter<File>> {
+                    let path = Path::new(dir).join(format!("{}.{}.bed", motif, label));
+                    let file = File::create(&path)
+                        .with_context(|| format!("Failed to create file at: {:?}", path))?;
+                    Ok(BufWriter::new(file))
+                };
+                write_site_beds(
+                    &mut create("methylated")?,
+                    &mut create("unmethylated")?,
+                    motif,
+                    sites,
+                    args.site_bed_threshold,
+                )?;
+            }
+            info!("Wrote {} pairs of site BED tracks to {}", motifs.len(), dir);
         }
+    }
 
-        let methylation_record = parse_to_methylation_record(contig_id, n_valid_cov, &record)?;
+    let percent_mismatches = batch_loader.percent_mismatches();
+    if percent_mismatches > 0 {
+        warnings.warn(
+            "percent_mismatch",
+            format!(
+                "{} records had a percent_modified deviating more than {} percentage points from n_modified / n_valid_cov.",
+                percent_mismatches, args.percent_tolerance
+            ),
+            vec![
+                ("records", percent_mismatches.into()),
+                ("tolerance", args.percent_tolerance.into()),
+            ],
+        );
+    }
 
-        methylation_records.push(methylation_record);
+    let memory_limited_batches = batch_loader.memory_limited_batches();
+    if memory_limited_batches > 0 {
+        info!(
+            "{} batches were flushed early to stay within --max-memory.",
+            memory_limited_batches
+        );
     }
 
-    if !methylation_records.is_empty() {
-        for meth_rec in methylation_records.drain(..) {
-            builder.add_record(meth_rec)?;
+    let base_check = batch_loader.coordinate_base_check();
+    if base_check.n_sampled > 0 {
+        info!(
+        "{} of {} sampled pileup records match the mod type's base as 0-based positions, {} as 1-based positions.",
+        base_check.zero_based_matches, base_check.n_sampled, base_check.one_based_matches
+    );
+    }
+    if !args.one_based && base_check.looks_one_based() {
+        warnings.warn(
+            "positions_look_one_based",
+            "The pileup positions appear to be 1-based. Rerun with --one-based if so.".to_string(),
+            vec![
+                ("sampled", base_check.n_sampled.into()),
+                ("zero_based_matches", base_check.zero_based_matches.into()),
+                ("one_based_matches", base_check.one_based_matches.into()),
+            ],
+        );
+    } else if args.one_based && base_check.looks_zero_based() {
+        warnings.warn(
+            "positions_look_zero_based",
+            "The pileup positions appear to be 0-based, but --one-based was given.".to_string(),
+            vec![
+                ("sampled", base_check.n_sampled.into()),
+                ("zero_based_matches", base_check.zero_based_matches.into()),
+                ("one_based_matches", base_check.one_based_matches.into()),
+            ],
+        );
+    }
+
+    if let Some(mean_coverage) = batch_loader.mean_coverage() {
+        info!("Mean n_valid_cov of the pileup: {:.2}", mean_coverage);
+        if let Some(warning) = low_coverage_warning(mean_coverage, args.low_coverage_warn) {
+            warnings.warn(
+                "low_coverage",
+                warning,
+                vec![
+                    ("mean_coverage", mean_coverage.into()),
+                    ("threshold", args.low_coverage_warn.into()),
+                ],
+            );
         }
-        let workspace = builder.build();
+    }
+
+    let implausible_coverage_records = batch_loader.implausible_coverage_records();
+    if implausible_coverage_records > 0 {
+        warnings.warn(
+            "implausible_coverage",
+            format!(
+                "{} records were skipped for a coverage above --max-plausible-coverage ({}).",
+                implausible_coverage_records, args.max_plausible_coverage
+            ),
+            vec![
+                ("records", implausible_coverage_records.into()),
+                (
+                    "max_plausible_coverage",
+                    (args.max_plausible_coverage as usize).into(),
+                ),
+            ],
+        );
+    }
 
-        let mut methylation_pattern =
-            calculate_contig_read_methylation_pattern(workspace, motifs.clone(), args.threads)?;
+    let zero_coverage_records = batch_loader.zero_coverage_records();
+    if zero_coverage_records > 0 {
+        warnings.warn(
+            "zero_coverage",
+            format!(
+                "{} records were skipped because the denominator of their fraction (--fraction-denominator) is zero, e.g. all reads carry another modification.",
+                zero_coverage_records
+            ),
+            vec![("records", zero_coverage_records.into())],
+        );
+    }
 
-        methylation_pattern_results.append(&mut methylation_pattern);
-        contigs_processed += contigs_loaded;
-        info!("Finished loading {} contigs", contigs_processed);
+    let coordinate_violations = batch_loader.coordinate_violations();
+    if coordinate_violations > 0 {
+        warnings.warn(
+            "coordinate_violation",
+            format!(
+                "{} records did not cover exactly one base (start < end and end - start == 1).",
+                coordinate_violations
+            ),
+            vec![("records", coordinate_violations.into())],
+        );
+    }
+
+    if let Some(path) = &args.uncovered_contigs {
+        let uncovered = find_uncovered_contigs(&contigs, &covered_contigs);
+        info!(
+            "{} of {} contigs received no methylation records.",
+            uncovered.len(),
+            contigs.len()
+        );
+        let file =
+            File::create(path).with_context(|| format!("Failed to create file at: {:?}", path))?;
+        write_uncovered_contigs(&mut BufWriter::new(file), &uncovered)?;
+    }
+
+    for motif in &motifs {
+        if !methylation_pattern_results
+            .iter()
+            .any(|entry| entry.motif == *motif)
+        {
+            warnings.warn(
+                "motif_without_sites",
+                format!("Motif {} has no covered sites in any contig.", motif),
+                vec![("motif", motif.to_string().into())],
+            );
+        }
+    }
+
+    match &contig_order {
+        Some(order) => sort_by_contig_order(&mut methylation_pattern_results, order),
+        None => methylation_pattern_results.sort_by(|a, b| a.contig.cmp(&b.contig)),
+    }
+
+    if args.report_shared_sites {
+        info!(
+            "{} methylation records are attributed to more than one motif occurrence.",
+            shared_methylation_records
+        );
+    }
+
+    if let Some(timings) = &pattern_options.timings {
+        info!("Time spent per motif, summed over threads:");
+        for (motif, elapsed) in timings.summary() {
+            info!("  {}: {}", motif, format_duration(elapsed));
+        }
+    }
+
+    if args.require_full_coverage {
+        let n_dropped = drop_incomplete_rows(&mut methylation_pattern_results);
+        info!(
+            "Dropped {} rows where not every motif occurrence was covered.",
+            n_dropped
+        );
+    }
+
+    match args.normalize {
+        Some(Normalization::GlobalMean) => {
+            normalize_by_global_mean(&mut methylation_pattern_results)
+        }
+        Some(Normalization::Quantile) => quantile_normalize(&mut methylation_pattern_results),
+        None => {}
+    }
+
+    if let Some(control) = &control_motif {
+        subtract_control(&mut methylation_pattern_results, control);
+    }
+
+    if let Some(profile) = &reference_profile {
+        compare_to_reference(
+            &mut methylation_pattern_results,
+            profile,
+            args.deviation_threshold,
+        );
+        let mut deviating: Vec<&str> = methylation_pattern_results
+            .iter()
+            .filter(|entry| entry.deviating_contig == Some(true))
+            .map(|entry| entry.contig.as_str())
+            .collect();
+        deviating.dedup();
+        info!(
+            "{} contigs deviate from the reference profile by more than {}.",
+            deviating.len(),
+            args.deviation_threshold
+        );
+        if args.deviating_only {
+            methylation_pattern_results.retain(|entry| entry.deviating_contig == Some(true));
+        }
+    }
+
+    if args.call_methylation {
+        call_methylation(&mut methylation_pattern_results, args.null_rate, args.fdr);
+    }
+
+    if args.pack_sites {
+        let n_truncated = methylation_pattern_results
+            .iter()
+            .filter(|entry| entry.n_motif_obs as usize > MAX_PACKED_SITES)
+            .count();
+        if n_truncated > 0 {
+            warnings.warn(
+                "packed_sites_truncated",
+                format!(
+                    "{} rows have more than {} sites. Their packed_sites column is truncated.",
+                    n_truncated, MAX_PACKED_SITES
+                ),
+                vec![
+                    ("rows", n_truncated.into()),
+                    ("max_packed_sites", MAX_PACKED_SITES.into()),
+                ],
+            );
+        }
+    }
+
+    if let Some(decimals) = args.round {
+        round_statistics(&mut methylation_pattern_results, decimals);
+    }
+
+    if let Some(path) = &args.warnings_json {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create file at: {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        warnings.write_json(&mut writer)?;
+        writer.flush()?;
     }
 
-    methylation_pattern_results.sort_by(|a, b| a.contig.cmp(&b.contig));
+    let output_columns = OutputColumns {
+        sample: args.sample_name.clone(),
+        feature: args.feature_column,
+        coverage_bin: args.coverage_bins.is_some(),
+        header_fields,
+        palindromic: args.palindromic,
+        occurrence_density: args.occurrence_density,
+        normalized_median: args.normalize.is_some(),
+        mode_methylation: args.mode_methylation,
+        weighted_median: args.weighted_median,
+        control_difference: control_motif.is_some(),
+        reference: reference_profile.is_some(),
+        motif_vs_background: args.motif_score,
+        heterogeneity: args.heterogeneity_window.is_some(),
+        methylation_call: args.call_methylation,
+        qc: args.qc_columns,
+        packed_sites: args.pack_sites,
+        provenance_hash: args.provenance_hash,
+    };
+
+    if args.split_by_mod_type {
+        let mut writer = ModTypeSplitWriter::new(outpath, output_columns, args.tidy);
+        for entry in &methylation_pattern_results {
+            writer.write(entry)?;
+        }
+        for path in writer.finish()? {
+            info!("Wrote {:?}", path);
+        }
+        return Ok(());
+    }
 
     let outfile = std::fs::File::create(outpath)
         .with_context(|| format!("Failed to create file at: {:?}", outpath))?;
     let mut writer = BufWriter::new(outfile);
 
-    writeln!(
-        writer,
-        "contig\tmotif\tmod_type\tmod_position\tmedian\tmean_read_cov\tN_motif_obs\tmotif_occurences_total"
-    )?;
-
-    for entry in &methylation_pattern_results {
-        let motif_sequence = entry.motif.sequence_to_string();
-        let mod_type_str = entry.motif.mod_type.to_pileup_code();
-        let mod_position = entry.motif.mod_position;
-
-        writeln!(
-            writer,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
-            entry.contig,
-            motif_sequence,
-            mod_type_str,
-            mod_position,
-            entry.median,
-            entry.mean_read_cov,
-            entry.n_motif_obs,
-            entry.motif_occurences_total
+    if args.format == OutputFormat::JsonByContig {
+        write_methylation_pattern_json_by_contig(
+            &mut writer,
+            &methylation_pattern_results,
+            &output_columns,
         )?;
-
-        writer.flush()?;
+    } else if args.tidy {
+        write_methylation_pattern_tidy(&mut writer, &methylation_pattern_results, &output_columns)?;
+    } else {
+        write_methylation_pattern(&mut writer, &methylation_pattern_results, &output_columns)?;
     }
 
     Ok(())
 }
+
+/// The parameters that change the values of a row, in a fixed order, for the
+/// provenance hash. Input files other than the assembly and pileup are included by
+/// the hash of their content, so renaming or moving them keeps the hashes.
+fn provenance_parameters(args: &MethylationPatternArgs) -> Result<String> {
+    let content_hash = |path: &Option<String>| path.as_ref().map(file_hash).transpose();
+
+    Ok(format!(
+        "version={};min_valid_read_coverage={};min_modified_reads={};max_plausible_coverage={};\
+         swap_count_columns={};one_based={};case_insensitive_mod_codes={};\
+         fraction_denominator={:?};strand_convention={:?};median_type={:?};\
+         mode_bin_width={:?};heterogeneity_window={:?};coverage_bins={:?};regions={:?};\
+         motif_score={};normalize={:?};control_motif={:?};reference_profile={:?};\
+         deviation_threshold={};null_rate={};fdr={};round={:?};contig_order={:?}",
+        env!("CARGO_PKG_VERSION"),
+        args.min_valid_read_coverage,
+        args.min_modified_reads,
+        args.max_plausible_coverage,
+        args.swap_count_columns,
+        args.one_based,
+        args.case_insensitive_mod_codes,
+        args.fraction_denominator,
+        args.strand_convention,
+        args.median_type,
+        args.mode_methylation.then_some(args.mode_bin_width),
+        args.heterogeneity_window,
+        args.coverage_bins,
+        content_hash(&args.regions)?,
+        args.motif_score,
+        args.normalize,
+        args.control_motif,
+        content_hash(&args.reference_profile)?,
+        args.deviation_threshold,
+        args.null_rate,
+        args.fdr,
+        args.round,
+        content_hash(&args.contig_order)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_warnings_json() -> Result<()> {
+        let dir = tempdir()?;
+        let assembly = dir.path().join("assembly.fa");
+        let pileup = dir.path().join("pileup.bed");
+        let circular = dir.path().join("circular.txt");
+        let output = dir.path().join("motifs.tsv");
+        let warnings_json = dir.path().join("warnings.json");
+        fs::write(&assembly, ">contig_3\nTGGACGATCCCGATC\n")?;
+        fs::write(
+            &pileup,
+            "contig_3\t6\t7\ta\t133\t+\t6\t7\t255,0,0\t15\t100.00\t15\t0\t0\t0\t0\t0\t0\n",
+        )?;
+        fs::write(&circular, "contig_3\ncontig_9\n")?;
+
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let args = MethylationPatternArgs::try_parse_from([
+            "methylation-pattern",
+            "-p",
+            &path(&pileup),
+            "-a",
+            &path(&assembly),
+            "-o",
+            &path(&output),
+            "-m",
+            "GATC_a_1,CCWGG_m_1",
+            "--circular-contigs",
+            &path(&circular),
+            "--warnings-json",
+            &path(&warnings_json),
+        ])?;
+        extract_methylation_pattern(args)?;
+
+        // The default --batches exceeds the single contig without a warning.
+        let warnings = fs::read_to_string(&warnings_json)?;
+        assert_eq!(
+            warnings,
+            "[\n\
+             \x20 {\"kind\": \"missing_contig\", \"message\": \"Circular contig 'contig_9' is not in the assembly\", \"contig\": \"contig_9\", \"source\": \"circular_contigs\"},\n\
+             \x20 {\"kind\": \"motif_without_sites\", \"message\": \"Motif CCWGG_m_1 has no covered sites in any contig.\", \"motif\": \"CCWGG_m_1\"}\n\
+             ]\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rna_motif_matches_dna_sequence() -> Result<()> {
+        let dir = tempdir()?;
+        let assembly = dir.path().join("assembly.fa");
+        let pileup = dir.path().join("pileup.bed");
+        let output = dir.path().join("motifs.tsv");
+        fs::write(&assembly, ">contig_3\nTGGACGATCCCGATC\n")?;
+        fs::write(
+            &pileup,
+            "contig_3\t6\t7\ta\t133\t+\t6\t7\t255,0,0\t15\t100.00\t15\t0\t0\t0\t0\t0\t0\n",
+        )?;
+
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let args = MethylationPatternArgs::try_parse_from([
+            "methylation-pattern",
+            "-p",
+            &path(&pileup),
+            "-a",
+            &path(&assembly),
+            "-o",
+            &path(&output),
+            "-m",
+            "GAUC_a_1",
+        ])?;
+        extract_methylation_pattern(args)?;
+
+        // U is read as T, so the motif finds the GATC sites and is reported as GATC.
+        let output = fs::read_to_string(&output)?;
+        let row = output.lines().nth(1).unwrap();
+        assert!(row.starts_with("contig_3\tGATC\ta\t1\t"), "{}", row);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwritable_output_fails_at_startup() -> Result<()> {
+        let dir = tempdir()?;
+        // Neither input exists, so an error about the output shows that it is checked
+        // before any input is read.
+        let run = |output: &Path, extra_args: &[&str]| -> Result<()> {
+            let output = output.to_string_lossy();
+            let args = MethylationPatternArgs::try_parse_from(
+                [
+                    "methylation-pattern",
+                    "-p",
+                    "missing.bed",
+                    "-a",
+                    "missing.fa",
+                    "-o",
+                    &output,
+                    "-m",
+                    "GATC_a_1",
+                ]
+                .iter()
+                .chain(extra_args),
+            )?;
+            extract_methylation_pattern(args)
+        };
+
+        let output_dir = dir.path().join("motifs.tsv");
+        fs::create_dir(&output_dir)?;
+        let error = run(&output_dir, &[]).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Output path is a directory"));
+
+        // The other outputs are checked as well, and the track directories created.
+        let output = dir.path().join("out.tsv");
+        let output_dir = output_dir.to_string_lossy().into_owned();
+        for flag in [
+            "--per-site-output",
+            "--progress-to-file",
+            "--assembly-stats",
+        ] {
+            let error = run(&output, &[flag, &output_dir]).unwrap_err();
+            assert!(format!("{:#}", error).starts_with("Output path is a directory"));
+        }
+        fs::write(dir.path().join("tracks"), "")?;
+        let tracks = dir.path().join("tracks").to_string_lossy().into_owned();
+        let error = run(&output, &["--bedgraph-output", &tracks]).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Output directory is a file"));
+        let beds = dir.path().join("beds").to_string_lossy().into_owned();
+        run(&output, &["--site-bed-output", &beds]).unwrap_err();
+        assert!(dir.path().join("beds").is_dir());
+
+        let read_only = dir.path().join("read_only");
+        fs::create_dir(&read_only)?;
+        let mut permissions = fs::metadata(&read_only)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&read_only, permissions.clone())?;
+        // Permissions are not enforced for root, e.g. in containers.
+        if fs::write(read_only.join("probe"), "").is_err() {
+            let error = run(&read_only.join("motifs.tsv"), &[]).unwrap_err();
+            assert!(format!("{:#}", error).starts_with("Output directory is not writable"));
+        }
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&read_only, permissions)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode_bin_width_bounds() -> Result<()> {
+        let dir = tempdir()?;
+        let output = dir.path().join("motifs.tsv");
+        for bin_width in ["1e-12", "0", "1.5", "NaN"] {
+            let args = MethylationPatternArgs::try_parse_from([
+                "methylation-pattern",
+                "-p",
+                "missing.bed",
+                "-a",
+                "missing.fa",
+                "-o",
+                &output.to_string_lossy(),
+                "-m",
+                "GATC_a_1",
+                "--mode-bin-width",
+                bin_width,
+            ])?;
+            let error = extract_methylation_pattern(args).unwrap_err();
+            assert!(error
+                .to_string()
+                .starts_with("--mode-bin-width must be in [0.0001, 1]"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_provenance_parameters_hash_file_content() -> Result<()> {
+        let dir = tempdir()?;
+        let parameters = |regions: &Path| -> Result<String> {
+            let args = MethylationPatternArgs::try_parse_from([
+                "methylation-pattern",
+                "-p",
+                "pileup.bed",
+                "-a",
+                "assembly.fa",
+                "-o",
+                "motifs.tsv",
+                "-m",
+                "GATC_a_1",
+                "--regions",
+                &regions.to_string_lossy(),
+            ])?;
+            provenance_parameters(&args)
+        };
+
+        let [regions, moved, edited] =
+            ["regions.bed", "moved.bed", "edited.bed"].map(|name| dir.path().join(name));
+        fs::write(&regions, "contig_1\t0\t100\n")?;
+        fs::write(&moved, "contig_1\t0\t100\n")?;
+        fs::write(&edited, "contig_1\t0\t50\n")?;
+
+        assert_eq!(parameters(&regions)?, parameters(&moved)?);
+        assert_ne!(parameters(&regions)?, parameters(&edited)?);
+        assert!(parameters(&dir.path().join("missing.bed")).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1089 @@
+use ahash::AHashMap;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use methylome::{ModType, Motif, Strand};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    data::{
+        contig::{AssemblyStats, Contig},
+        methylation::MethylationCoverage,
+    },
+    processing::{MotifMethylationDegree, MotifSite},
+};
+
+/// Layout of the methylation pattern output file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab separated values with one row per contig and motif.
+    #[default]
+    Tsv,
+    /// A JSON object with an array of motif objects per contig.
+    JsonByContig,
+}
+
+impl OutputFormat {
+    /// Expected extension of the output path.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::JsonByContig => "json",
+        }
+    }
+}
+
+/// Columns written as JSON strings. All other columns are numbers, booleans or null.
+const TEXT_COLUMNS: [&str; 7] = [
+    "sample",
+    "motif",
+    "mod_type",
+    "feature",
+    "coverage_bin",
+    "packed_sites",
+    "hash",
+];
+
+/// Number of leading columns identifying a row (contig, motif, mod_type, mod_position).
+const N_ID_COLUMNS: usize = 4;
+
+/// Values of selected `key=value` fields of the assembly headers, one column per key.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderFields {
+    keys: Vec<String>,
+    values: AHashMap<String, Vec<Option<String>>>,
+}
+
+impl HeaderFields {
+    pub fn from_contigs<'a, I: IntoIterator<Item = &'a Contig>>(
+        keys: &[String],
+        contigs: I,
+    ) -> Self {
+        let values = contigs
+            .into_iter()
+            .filter(|contig| contig.description.is_some())
+            .map(|contig| {
+                let values = keys
+                    .iter()
+                    .map(|key| contig.header_field(key).map(String::from))
+                    .collect();
+                (contig.id.clone(), values)
+            })
+            .collect();
+        Self {
+            keys: keys.to_vec(),
+            values,
+        }
+    }
+
+    /// Keys that are not in the header of any contig.
+    pub fn missing_keys(&self) -> Vec<&str> {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.values.values().all(|values| values[*i].is_none()))
+            .map(|(_, key)| key.as_str())
+            .collect()
+    }
+
+    fn row_values(&self, contig: &str) -> impl Iterator<Item = String> + '_ {
+        let values = self.values.get(contig);
+        (0..self.keys.len()).map(move |i| {
+            values
+                .and_then(|values| values[i].clone())
+                .unwrap_or_else(|| "NA".to_string())
+        })
+    }
+}
+
+/// Optional columns of the methylation pattern output.
+///
+/// `sample` is a constant identifying column placed before contig, `feature`,
+/// `coverage_bin` and the header fields are identifying columns placed after
+/// mod_position, the others are appended after the statistics.
+#[derive(Debug, Clone, Default)]
+pub struct OutputColumns {
+    /// Sample name written in the sample column of every row.
+    pub sample: Option<String>,
+    pub feature: bool,
+    pub coverage_bin: bool,
+    pub header_fields: HeaderFields,
+    pub palindromic: bool,
+    pub occurrence_density: bool,
+    pub normalized_median: bool,
+    pub mode_methylation: bool,
+    pub weighted_median: bool,
+    pub control_difference: bool,
+    /// median_minus_reference and deviating_contig.
+    pub reference: bool,
+    pub motif_vs_background: bool,
+    pub heterogeneity: bool,
+    /// is_methylated and q_value.
+    pub methylation_call: bool,
+    /// Quality control columns: coverage_cv.
+    pub qc: bool,
+    pub packed_sites: bool,
+    pub provenance_hash: bool,
+}
+
+impl OutputColumns {
+    /// Number of leading columns identifying a row.
+    fn n_id_columns(&self) -> usize {
+        N_ID_COLUMNS
+            + self.sample.is_some() as usize
+            + self.feature as usize
+            + self.coverage_bin as usize
+            + self.header_fields.keys.len()
+    }
+
+    pub fn header(&self) -> Vec<&str> {
+        let mut header = Vec::new();
+        if self.sample.is_some() {
+            header.push("sample");
+        }
+        header.extend(["contig", "motif", "mod_type", "mod_position"]);
+        if self.feature {
+            header.push("feature");
+        }
+        if self.coverage_bin {
+            header.push("coverage_bin");
+        }
+        header.extend(self.header_fields.keys.iter().map(String::as_str));
+        header.extend([
+            "median",
+            "mean_read_cov",
+            "N_motif_obs",
+            "motif_occurences_total",
+        ]);
+        if self.palindromic {
+            header.push("palindromic");
+        }
+        if self.occurrence_density {
+            header.push("occurrence_density");
+        }
+        if self.normalized_median {
+            header.push("normalized_median");
+        }
+        if self.mode_methylation {
+            header.push("mode_methylation");
+        }
+        if self.weighted_median {
+            header.push("weighted_median");
+        }
+        if self.control_difference {
+            header.push("median_minus_control");
+        }
+        if self.reference {
+            header.extend(["median_minus_reference", "deviating_contig"]);
+        }
+        if self.motif_vs_background {
+            header.push("motif_vs_background");
+        }
+        if self.heterogeneity {
+            header.push("heterogeneity");
+        }
+        if self.methylation_call {
+            header.extend(["is_methylated", "q_value"]);
+        }
+        if self.qc {
+            header.push("coverage_cv");
+        }
+        if self.packed_sites {
+            header.push("packed_sites");
+        }
+        if self.provenance_hash {
+            header.push("hash");
+        }
+        header
+    }
+
+    pub fn row(&self, entry: &MotifMethylationDegree) -> Vec<String> {
+        let mut row = Vec::new();
+        if let Some(sample) = &self.sample {
+            row.push(sample.clone());
+        }
+        row.extend([
+            entry.contig.clone(),
+            entry.motif.sequence_to_string(),
+            entry.motif.mod_type.to_pileup_code().to_string(),
+            entry.motif.mod_positions_to_string(),
+        ]);
+        if self.feature {
+            row.push(entry.feature.clone().unwrap_or_else(|| "NA".to_string()));
+        }
+        if self.coverage_bin {
+            row.push(
+                entry
+                    .coverage_bin
+                    .clone()
+                    .unwrap_or_else(|| "NA".to_string()),
+            );
+        }
+        row.extend(self.header_fields.row_values(&entry.contig));
+        row.extend([
+            entry.stats.median.to_string(),
+            entry.stats.mean_read_cov.to_string(),
+            entry.n_motif_obs.to_string(),
+            entry.motif_occurences_total.to_string(),
+        ]);
+        if self.palindromic {
+            row.push(entry.motif.is_palindromic().to_string());
+        }
+        if self.occurrence_density {
+            row.push(optional_to_string(entry.occurrence_density));
+        }
+        if self.normalized_median {
+            row.push(optional_to_string(entry.normalized_median));
+        }
+        if self.mode_methylation {
+            row.push(optional_to_string(entry.mode));
+        }
+        if self.weighted_median {
+            row.push(entry.stats.weighted_median.to_string());
+        }
+        if self.control_difference {
+            row.push(optional_to_string(entry.control_difference));
+        }
+        if self.reference {
+            row.push(optional_to_string(entry.reference_difference));
+            row.push(
+                entry
+                    .deviating_contig
+                    .map_or_else(|| "NA".to_string(), |deviating| deviating.to_string()),
+            );
+        }
+        if self.motif_vs_background {
+            row.push(optional_to_string(entry.motif_vs_background));
+        }
+        if self.heterogeneity {
+            row.push(optional_to_string(entry.heterogeneity));
+        }
+        if self.methylation_call {
+            row.push(
+                entry
+                    .is_methylated
+                    .map_or_else(|| "NA".to_string(), |methylated| methylated.to_string()),
+            );
+            row.push(
+                entry
+                    .q_value
+                    .map_or_else(|| "NA".to_string(), format_p_value),
+            );
+        }
+        if self.qc {
+            row.push(entry.stats.coverage_cv.to_string());
+        }
+        if self.packed_sites {
+            row.push(
+                entry
+                    .packed_sites
+                    .clone()
+                    .unwrap_or_else(|| "NA".to_string()),
+            );
+        }
+        if self.provenance_hash {
+            row.push(
+                entry
+                    .provenance_hash
+                    .clone()
+                    .unwrap_or_else(|| "NA".to_string()),
+            );
+        }
+        row
+    }
+}
+
+/// Small p-values are written in scientific notation, e.g. `1.5e-40` instead of 40
+/// leading zeros.
+fn format_p_value(value: f64) -> String {
+    if value != 0.0 && value < 1e-4 {
+        format!("{:e}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional_to_string(value: Option<f64>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "NA".to_string())
+}
+
+pub fn write_methylation_pattern<W: Write>(
+    writer: &mut W,
+    results: &[MotifMethylationDegree],
+    columns: &OutputColumns,
+) -> Result<()> {
+    write_header(writer, columns, false)?;
+    for entry in results {
+        write_row(writer, entry, columns, false)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_header<W: Write>(writer: &mut W, columns: &OutputColumns, tidy: bool) -> Result<()> {
+    let header = columns.header();
+    if tidy {
+        let (id_header, _) = header.split_at(columns.n_id_columns());
+        writeln!(writer, "{}\tstatistic\tvalue", id_header.join("\t"))?;
+    } else {
+        writeln!(writer, "{}", header.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// Writes the line of `entry`, or one line per statistic in the tidy format.
+fn write_row<W: Write>(
+    writer: &mut W,
+    entry: &MotifMethylationDegree,
+    columns: &OutputColumns,
+    tidy: bool,
+) -> Result<()> {
+    let row = columns.row(entry);
+    if !tidy {
+        writeln!(writer, "{}", row.join("\t"))?;
+        return Ok(());
+    }
+
+    let header = columns.header();
+    let statistics = &header[columns.n_id_columns()..];
+    let (id_values, values) = row.split_at(columns.n_id_columns());
+    let id_values = id_values.join("\t");
+    for (statistic, value) in statistics.iter().zip(values) {
+        writeln!(writer, "{}\t{}\t{}", id_values, statistic, value)?;
+    }
+    Ok(())
+}
+
+/// Writes the results to one file per mod type, `<base>.<mod_code>.<ext>`, e.g.
+/// `out.a.tsv` and `out.m.tsv` for `out.tsv`.
+///
+/// A file is created when the first row of its mod type is written, so only the mod
+/// types present in the results get a file.
+pub struct ModTypeSplitWriter {
+    base: PathBuf,
+    columns: OutputColumns,
+    tidy: bool,
+    writers: Vec<(ModType, BufWriter<File>)>,
+}
+
+impl ModTypeSplitWriter {
+    pub fn new<P: AsRef<Path>>(base: P, columns: OutputColumns, tidy: bool) -> Self {
+        Self {
+            base: base.as_ref().to_path_buf(),
+            columns,
+            tidy,
+            writers: Vec::new(),
+        }
+    }
+
+    /// Path of the file holding the rows of `mod_type`.
+    pub fn path(&self, mod_type: ModType) -> PathBuf {
+        let stem = self.base.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match self.base.extension() {
+            Some(ext) => format!(
+                "{}.{}.{}",
+                stem,
+                mod_type.to_pileup_code(),
+                ext.to_string_lossy()
+            ),
+            None => format!("{}.{}", stem, mod_type.to_pileup_code()),
+        };
+        self.base.with_file_name(file_name)
+    }
+
+    pub fn write(&mut self, entry: &MotifMethylationDegree) -> Result<()> {
+        let mod_type = entry.motif.mod_type;
+        let index = match self.writers.iter().position(|(m, _)| *m == mod_type) {
+            Some(index) => index,
+            None => {
+                let path = self.path(mod_type);
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create file at: {:?}", path))?;
+                let mut writer = BufWriter::new(file);
+                write_header(&mut writer, &self.columns, self.tidy)?;
+                self.writers.push((mod_type, writer));
+                self.writers.len() - 1
+            }
+        };
+
+        write_row(&mut self.writers[index].1, entry, &self.columns, self.tidy)
+    }
+
+    /// Flushes the files and returns their paths in the order they were created.
+    pub fn finish(self) -> Result<Vec<PathBuf>> {
+        let paths = self
+            .writers
+            .iter()
+            .map(|(mod_type, _)| self.path(*mod_type))
+            .collect();
+        for (_, mut writer) in self.writers {
+            writer.flush()?;
+        }
+        Ok(paths)
+    }
+}
+
+/// Writes the assembly contigs without methylation records as `contig, length`.
+pub fn write_uncovered_contigs<W: Write>(
+    writer: &mut W,
+    uncovered: &[(&str, usize)],
+) -> Result<()> {
+    writeln!(writer, "contig\tlength")?;
+    for (contig, length) in uncovered {
+        writeln!(writer, "{}\t{}", contig, length)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the assembly statistics as a TSV with a header and a single row.
+pub fn write_assembly_stats<W: Write>(writer: &mut W, stats: &AssemblyStats) -> Result<()> {
+    writeln!(writer, "n_contigs\ttotal_length\tN50\tgc_content")?;
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}",
+        stats.n_contigs, stats.total_length, stats.n50, stats.gc_content
+    )?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the header of the per-site output. The `context` column is only written
+/// with `context`.
+pub fn write_motif_sites_header<W: Write>(writer: &mut W, context: bool) -> Result<()> {
+    let mut header = vec![
+        "contig",
+        "motif",
+        "mod_type",
+        "mod_position",
+        "position",
+        "strand",
+        "n_modified",
+        "n_valid_cov",
+        "fraction_modified",
+    ];
+    if context {
+        header.push("context");
+    }
+    writeln!(writer, "{}", header.join("\t"))?;
+    Ok(())
+}
+
+/// Writes one line per motif site. Sites without a context get `NA` if `context` is
+/// set.
+pub fn write_motif_sites<W: Write>(
+    writer: &mut W,
+    sites: &[MotifSite],
+    context: bool,
+) -> Result<()> {
+    for site in sites {
+        write!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            site.contig,
+            site.motif.sequence_to_string(),
+            site.motif.mod_type.to_pileup_code(),
+            site.motif.mod_positions_to_string(),
+            site.position,
+            site.strand,
+            site.methylation.get_n_modified(),
+            site.methylation.get_n_valid_cov(),
+            site.methylation.fraction_modified(),
+        )?;
+        if context {
+            write!(writer, "\t{}", site.context.as_deref().unwrap_or("NA"))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// A site of the bedGraph and BED tracks of a motif. The tracks keep the sites of
+/// the whole assembly until all batches are done, so a site holds no copy of its
+/// motif, and the sites of a contig share its id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackSite {
+    pub contig: Arc<str>,
+    pub position: usize,
+    pub strand: Strand,
+    pub methylation: MethylationCoverage,
+}
+
+impl TrackSite {
+    /// Returns the track site of `site`, whose contig id is `contig`.
+    pub fn new(contig: &Arc<str>, site: &MotifSite) -> Self {
+        Self {
+            contig: Arc::clone(contig),
+            position: site.position,
+            strand: site.strand,
+            methylation: site.methylation,
+        }
+    }
+}
+
+/// Writes the sites of `motif` as a bedGraph track of `contig, start, end, fraction`.
+///
+/// The sites are sorted by contig and start first, as bedGraph requires. Each site
+/// covers the single modified base.
+pub fn write_bedgraph<W: Write>(
+    writer: &mut W,
+    motif: &Motif,
+    sites: &mut [TrackSite],
+) -> Result<()> {
+    sort_sites(sites);
+
+    writeln!(writer, "track type=bedGraph name=\"{}\"", motif)?;
+    for site in sites.iter() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            site.contig,
+            site.position,
+            site.position + 1,
+            site.methylation.fraction_modified()
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the sites of `motif` as two BED tracks: sites with a methylation fraction of
+/// at least `threshold` to `methylated` and the other sites to `unmethylated`.
+///
+/// The rows are `contig, start, end, name, score, strand` sorted by contig and start.
+/// The score is the fraction scaled to 0-1000.
+pub fn write_site_beds<W: Write>(
+    methylated: &mut W,
+    unmethylated: &mut W,
+    motif: &Motif,
+    sites: &mut [TrackSite],
+    threshold: f64,
+) -> Result<()> {
+    sort_sites(sites);
+
+    let name = motif.to_string();
+    for (writer, label) in [
+        (&mut *methylated, "methylated"),
+        (&mut *unmethylated, "unmethylated"),
+    ] {
+        writeln!(writer, "track name=\"{} {}\" useScore=1", name, label)?;
+    }
+    for site in sites.iter() {
+        let fraction = site.methylation.fraction_modified();
+        let writer = if fraction >= threshold {
+            &mut *methylated
+        } else {
+            &mut *unmethylated
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            site.contig,
+            site.position,
+            site.position + 1,
+            name,
+            bed_score(fraction),
+            site.strand
+        )?;
+    }
+    methylated.flush()?;
+    unmethylated.flush()?;
+
+    Ok(())
+}
+
+/// Scales a methylation fraction to an integer BED score between 0 and 1000.
+fn bed_score(fraction: f64) -> u16 {
+    (fraction * 1000.0).round().clamp(0.0, 1000.0) as u16
+}
+
+fn sort_sites(sites: &mut [TrackSite]) {
+    sites.sort_by(|a, b| a.contig.cmp(&b.contig).then(a.position.cmp(&b.position)));
+}
+
+/// Writes the results as a JSON object keyed by contig, with one object per row of
+/// the contig holding the other columns:
+///
+/// `{"contig_3": [{"motif": "GATC", "mod_type": "a", "mod_position": 1, ...}], ...}`
+///
+/// Contigs are in the order they first occur in `results`. NA and non-finite values
+/// are written as null. The rows are written one at a time, but grouping them needs
+/// all results in memory, like the other formats.
+pub fn write_methylation_pattern_json_by_contig<W: Write>(
+    writer: &mut W,
+    results: &[MotifMethylationDegree],
+    columns: &OutputColumns,
+) -> Result<()> {
+    let mut contig_rows: Vec<(&str, Vec<&MotifMethylationDegree>)> = Vec::new();
+    let mut contig_index: AHashMap<&str, usize> = AHashMap::new();
+    for entry in results {
+        let index = *contig_index.entry(&entry.contig).or_insert_with(|| {
+            contig_rows.push((&entry.contig, Vec::new()));
+            contig_rows.len() - 1
+        });
+        contig_rows[index].1.push(entry);
+    }
+
+    let header = columns.header();
+    write!(writer, "{{")?;
+    for (i, (contig, entries)) in contig_rows.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(writer, "{}\n  {}: [", separator, json_string(contig))?;
+        for (j, entry) in entries.iter().enumerate() {
+            let fields: Vec<String> = header
+                .iter()
+                .zip(columns.row(entry))
+                .filter(|(column, _)| **column != "contig")
+                .map(|(column, value)| {
+                    let is_header_field =
+                        columns.header_fields.keys.iter().any(|key| key == column);
+                    let value = if is_header_field && value != "NA" {
+                        json_string(&value)
+                    } else {
+                        json_value(column, &value)
+                    };
+                    format!("{}: {}", json_string(column), value)
+                })
+                .collect();
+            let separator = if j == 0 { "" } else { "," };
+            write!(writer, "{}\n    {{{}}}", separator, fields.join(", "))?;
+        }
+        write!(writer, "\n  ]")?;
+    }
+    writeln!(writer, "\n}}")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn json_value(column: &str, value: &str) -> String {
+    if TEXT_COLUMNS.contains(&column) {
+        return json_string(value);
+    }
+    // Several mod positions, e.g. `0+1`, are kept as a string.
+    if column == "mod_position" && value.contains('+') {
+        return json_string(value);
+    }
+    match value {
+        "true" | "false" => value.to_string(),
+        _ => match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => value.to_string(),
+            _ => "null".to_string(),
+        },
+    }
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Writes the results in long (tidy) format with one statistic per row:
+/// `contig, motif, mod_type, mod_position, statistic, value`.
+pub fn write_methylation_pattern_tidy<W: Write>(
+    writer: &mut W,
+    results: &[MotifMethylationDegree],
+    columns: &OutputColumns,
+) -> Result<()> {
+    write_header(writer, columns, true)?;
+    for entry in results {
+        write_row(writer, entry, columns, true)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{contig::Contig, methylation::MethylationCoverage},
+        postprocessing::tests::result_row,
+        processing::{collect_motif_sites, PatternOptions},
+    };
+    use methylome::Strand;
+
+    #[test]
+    fn test_write_sample_column() -> Result<()> {
+        let results = [
+            result_row("contig_1", "GATC_a_1", 0.5),
+            result_row("contig_2", "GATC_a_1", 0.25),
+        ];
+        let columns = OutputColumns {
+            sample: Some("sample_1".to_string()),
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern(&mut buffer, &results, &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let mut lines = output.lines();
+        assert!(lines.next().unwrap().starts_with("sample\tcontig\tmotif"));
+        assert!(lines.all(|line| line.starts_with("sample_1\tcontig_")));
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern_tidy(&mut buffer, &results, &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "sample\tcontig\tmotif\tmod_type\tmod_position\tstatistic\tvalue"
+        );
+        assert!(lines.all(|line| line.starts_with("sample_1\tcontig_")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_json_by_contig() -> Result<()> {
+        let mut results = vec![
+            result_row("contig_3", "GATC_a_1", 0.5),
+            result_row("contig_3", "GATC_m_3", 0.25),
+            result_row("contig_\"4\"", "GATC_a_1", 1.0),
+        ];
+        results[1].control_difference = Some(-0.25);
+        let columns = OutputColumns {
+            control_difference: true,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern_json_by_contig(&mut buffer, &results, &columns)?;
+        assert_eq!(
+            String::from_utf8(buffer)?,
+            "{\n  \"contig_3\": [\n    \
+             {\"motif\": \"GATC\", \"mod_type\": \"a\", \"mod_position\": 1, \"median\": 0.5, \"mean_read_cov\": 20, \"N_motif_obs\": 4, \"motif_occurences_total\": 4, \"median_minus_control\": null},\n    \
+             {\"motif\": \"GATC\", \"mod_type\": \"m\", \"mod_position\": 3, \"median\": 0.25, \"mean_read_cov\": 20, \"N_motif_obs\": 4, \"motif_occurences_total\": 4, \"median_minus_control\": -0.25}\n  ],\n  \
+             \"contig_\\\"4\\\"\": [\n    \
+             {\"motif\": \"GATC\", \"mod_type\": \"a\", \"mod_position\": 1, \"median\": 1, \"mean_read_cov\": 20, \"N_motif_obs\": 4, \"motif_occurences_total\": 4, \"median_minus_control\": null}\n  ]\n}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_optional_columns() -> Result<()> {
+        let mut entry = result_row("contig_1", "GATC_a_1", 0.5);
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern(&mut buffer, &[entry.clone()], &OutputColumns::default())?;
+        assert_eq!(
+            String::from_utf8(buffer)?,
+            "contig\tmotif\tmod_type\tmod_position\tmedian\tmean_read_cov\tN_motif_obs\tmotif_occurences_total\n\
+             contig_1\tGATC\ta\t1\t0.5\t20\t4\t4\n"
+        );
+
+        entry.normalized_median = Some(1.25);
+        let columns = OutputColumns {
+            normalized_median: true,
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        write_methylation_pattern(&mut buffer, &[entry], &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].ends_with("\tnormalized_median"));
+        assert!(lines[1].ends_with("\t4\t1.25"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_tidy() -> Result<()> {
+        let mut entry = result_row("contig_1", "GATC_a_1", 0.5);
+        entry.mode = Some(0.45);
+        let columns = OutputColumns {
+            mode_methylation: true,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern_tidy(&mut buffer, &[entry], &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "contig\tmotif\tmod_type\tmod_position\tstatistic\tvalue",
+                "contig_1\tGATC\ta\t1\tmedian\t0.5",
+                "contig_1\tGATC\ta\t1\tmean_read_cov\t20",
+                "contig_1\tGATC\ta\t1\tN_motif_obs\t4",
+                "contig_1\tGATC\ta\t1\tmotif_occurences_total\t4",
+                "contig_1\tGATC\ta\t1\tmode_methylation\t0.45",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_feature_column() -> Result<()> {
+        let mut entry = result_row("contig_1", "GATC_a_1", 0.5);
+        entry.feature = Some("geneA".to_string());
+        let columns = OutputColumns {
+            feature: true,
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern(&mut buffer, &[entry.clone()], &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("contig\tmotif\tmod_type\tmod_position\tfeature\tmedian"));
+        assert!(lines[1].starts_with("contig_1\tGATC\ta\t1\tgeneA\t0.5"));
+
+        // The feature identifies the row in the tidy format.
+        let mut buffer = Vec::new();
+        write_methylation_pattern_tidy(&mut buffer, &[entry], &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[1], "contig_1\tGATC\ta\t1\tgeneA\tmedian\t0.5");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_header_fields() -> Result<()> {
+        let mut contig_3 = Contig::new("contig_3".to_string(), "GATC".to_string());
+        contig_3.description = Some("taxid=1234 bin=MAG_07".to_string());
+        let contig_4 = Contig::new("contig_4".to_string(), "GATC".to_string());
+        let keys = vec!["bin".to_string(), "depth".to_string()];
+        let header_fields = HeaderFields::from_contigs(&keys, [&contig_3, &contig_4]);
+        assert_eq!(header_fields.missing_keys(), vec!["depth"]);
+
+        let columns = OutputColumns {
+            header_fields,
+            ..Default::default()
+        };
+        let results = vec![
+            result_row("contig_3", "GATC_a_1", 0.5),
+            result_row("contig_3", "GATC_m_3", 0.2),
+            result_row("contig_4", "GATC_a_1", 0.6),
+        ];
+        let mut buffer = Vec::new();
+        write_methylation_pattern(&mut buffer, &results, &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("contig\tmotif\tmod_type\tmod_position\tbin\tdepth\tmedian"));
+        assert!(lines[1].starts_with("contig_3\tGATC\ta\t1\tMAG_07\tNA\t0.5"));
+        assert!(lines[2].starts_with("contig_3\tGATC\tm\t3\tMAG_07\tNA\t0.2"));
+        assert!(lines[3].starts_with("contig_4\tGATC\ta\t1\tNA\tNA\t0.6"));
+
+        // Header values are strings in JSON, even if they look like numbers.
+        let keys = vec!["taxid".to_string(), "depth".to_string()];
+        let columns = OutputColumns {
+            header_fields: HeaderFields::from_contigs(&keys, [&contig_3, &contig_4]),
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        write_methylation_pattern_json_by_contig(&mut buffer, &results[..1], &columns)?;
+        let output = String::from_utf8(buffer)?;
+        assert!(output.contains("\"taxid\": \"1234\", \"depth\": null"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_palindromic() -> Result<()> {
+        let columns = OutputColumns {
+            palindromic: true,
+            ..Default::default()
+        };
+        let results = vec![
+            result_row("contig_3", "GATC_a_1", 0.5),
+            result_row("contig_3", "GAAC_a_1", 0.2),
+        ];
+        let mut buffer = Vec::new();
+        write_methylation_pattern(&mut buffer, &results, &columns)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].ends_with("motif_occurences_total\tpalindromic"));
+        assert!(lines[1].ends_with("\ttrue"));
+        assert!(lines[2].ends_with("\tfalse"));
+
+        let mut buffer = Vec::new();
+        write_methylation_pattern_json_by_contig(&mut buffer, &results, &columns)?;
+        assert!(String::from_utf8(buffer)?.contains("\"palindromic\": true"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_motif_sites_per_strand() -> Result<()> {
+        // A hemimethylated GATC: the A of the positive strand is mostly methylated,
+        // the A of the negative strand mostly not.
+        let mut contig = Contig::new("contig_1".to_string(), "CCAGATCTCC".to_string());
+        contig.add_methylation(
+            4,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(18, 20)?,
+        )?;
+        contig.add_methylation(
+            5,
+            Strand::Negative,
+            ModType::SixMA,
+            MethylationCoverage::new(2, 20)?,
+        )?;
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let sites = collect_motif_sites(&contig, &motifs, &PatternOptions::default(), None);
+
+        let mut buffer = Vec::new();
+        write_motif_sites_header(&mut buffer, false)?;
+        write_motif_sites(&mut buffer, &sites, false)?;
+        assert_eq!(
+            String::from_utf8(buffer)?.lines().collect::<Vec<_>>(),
+            vec![
+                "contig\tmotif\tmod_type\tmod_position\tposition\tstrand\tn_modified\tn_valid_cov\tfraction_modified",
+                "contig_1\tGATC\ta\t1\t4\t+\t18\t20\t0.9",
+                "contig_1\tGATC\ta\t1\t5\t-\t2\t20\t0.1",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_bedgraph_is_sorted() -> Result<()> {
+        let motif = Motif::new("GATC", "a", 1)?;
+        let site = |contig: &str, position: usize, n_modified: u32| -> Result<TrackSite> {
+            Ok(TrackSite {
+                contig: contig.into(),
+                position,
+                strand: Strand::Positive,
+                methylation: MethylationCoverage::new(n_modified, 20)?,
+            })
+        };
+        let mut sites = [
+            site("contig_2", 4, 10)?,
+            site("contig_1", 12, 20)?,
+            site("contig_1", 5, 5)?,
+        ];
+
+        let mut buffer = Vec::new();
+        write_bedgraph(&mut buffer, &motif, &mut sites)?;
+        let output = String::from_utf8(buffer)?;
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "track type=bedGraph name=\"GATC_a_1\"",
+                "contig_1\t5\t6\t0.25",
+                "contig_1\t12\t13\t1",
+                "contig_2\t4\t5\t0.5",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_site_beds() -> Result<()> {
+        let motif = Motif::new("GATC", "a", 1)?;
+        let site = |position: usize, strand: Strand, n_modified: u32| -> Result<TrackSite> {
+            Ok(TrackSite {
+                contig: "contig_1".into(),
+                position,
+                strand,
+                methylation: MethylationCoverage::new(n_modified, 20)?,
+            })
+        };
+        let mut sites = [
+            site(12, Strand::Positive, 20)?,
+            site(5, Strand::Negative, 9)?,
+            site(4, Strand::Positive, 10)?,
+            site(13, Strand::Negative, 0)?,
+        ];
+
+        let mut methylated = Vec::new();
+        let mut unmethylated = Vec::new();
+        write_site_beds(&mut methylated, &mut unmethylated, &motif, &mut sites, 0.5)?;
+
+        assert_eq!(
+            String::from_utf8(methylated)?.lines().collect::<Vec<_>>(),
+            vec![
+                "track name=\"GATC_a_1 methylated\" useScore=1",
+                "contig_1\t4\t5\tGATC_a_1\t500\t+",
+                "contig_1\t12\t13\tGATC_a_1\t1000\t+",
+            ]
+        );
+        assert_eq!(
+            String::from_utf8(unmethylated)?.lines().collect::<Vec<_>>(),
+            vec![
+                "track name=\"GATC_a_1 unmethylated\" useScore=1",
+                "contig_1\t5\t6\tGATC_a_1\t450\t-",
+                "contig_1\t13\t14\tGATC_a_1\t0\t-",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bed_score_is_clamped() {
+        assert_eq!(bed_score(0.1234), 123);
+        assert_eq!(bed_score(1.5), 1000);
+        assert_eq!(bed_score(-0.1), 0);
+    }
+
+    #[test]
+    fn test_split_by_mod_type() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let results = vec![
+            result_row("contig_1", "GATC_a_1", 0.9),
+            result_row("contig_1", "CCWGG_m_1", 0.2),
+            result_row("contig_2", "GATC_a_1", 0.8),
+        ];
+
+        let mut writer =
+            ModTypeSplitWriter::new(dir.path().join("out.tsv"), OutputColumns::default(), false);
+        for entry in &results {
+            writer.write(entry)?;
+        }
+        let paths = writer.finish()?;
+        assert_eq!(
+            paths,
+            vec![dir.path().join("out.a.tsv"), dir.path().join("out.m.tsv")]
+        );
+
+        let six_ma = std::fs::read_to_string(&paths[0])?;
+        let five_mc = std::fs::read_to_string(&paths[1])?;
+        let rows = |output: &str| -> Vec<String> {
+            output
+                .lines()
+                .skip(1)
+                .map(|line| line.split('\t').take(3).collect::<Vec<_>>().join("_"))
+                .collect()
+        };
+        assert!(six_ma.starts_with("contig\tmotif"));
+        assert!(five_mc.starts_with("contig\tmotif"));
+        assert_eq!(rows(&six_ma), vec!["contig_1_GATC_a", "contig_2_GATC_a"]);
+        assert_eq!(rows(&five_mc), vec!["contig_1_CCWGG_m"]);
+        assert!(!dir.path().join("out.21839.tsv").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Writes machine-readable progress to a JSON file that an orchestrator can poll.
+///
+/// Every update rewrites the whole file atomically: the JSON is written to a
+/// temporary file next to the target, which is then renamed over it, so a reader
+/// never sees a partially written file.
+pub struct ProgressFile {
+    path: PathBuf,
+    start: Instant,
+}
+
+impl ProgressFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Writes `{contigs_done, contigs_total, records_read, elapsed_secs}`.
+    ///
+    /// `contigs_total` is the number of contigs in the assembly, which is an upper
+    /// bound of the contigs found in the pileup.
+    pub fn update(
+        &self,
+        contigs_done: usize,
+        contigs_total: usize,
+        records_read: usize,
+    ) -> Result<()> {
+        let json = format!(
+            "{{\"contigs_done\": {}, \"contigs_total\": {}, \"records_read\": {}, \"elapsed_secs\": {:.3}}}",
+            contigs_done,
+            contigs_total,
+            records_read,
+            self.start.elapsed().as_secs_f64()
+        );
+
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut temp_file = tempfile::NamedTempFile::new_in(directory).with_context(|| {
+            format!(
+                "Could not create temporary progress file in: {:?}",
+                directory
+            )
+        })?;
+        writeln!(temp_file, "{}", json)?;
+        temp_file
+            .persist(&self.path)
+            .with_context(|| format!("Could not write progress file: {:?}", self.path))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_progress_file_is_rewritten() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("progress.json");
+        let progress = ProgressFile::new(&path);
+
+        progress.update(0, 4, 0)?;
+        let first = fs::read_to_string(&path)?;
+        assert!(first.starts_with(
+            "{\"contigs_done\": 0, \"contigs_total\": 4, \"records_read\": 0, \"elapsed_secs\": "
+        ));
+
+        progress.update(2, 4, 130)?;
+        let second = fs::read_to_string(&path)?;
+        assert!(second.starts_with(
+            "{\"contigs_done\": 2, \"contigs_total\": 4, \"records_read\": 130, \"elapsed_secs\": "
+        ));
+        assert!(second.trim_end().ends_with('}'));
+
+        // Only the progress file is left behind, no temporary files.
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        Ok(())
+    }
+}
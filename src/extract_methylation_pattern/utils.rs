@@ -1,40 +1,413 @@
-use anyhow::{anyhow, Result};
-use csv::StringRecord;
-use methylome::{ModType, Strand};
-
-use crate::data::{methylation::MethylationCoverage, MethylationRecord};
-
-pub fn parse_to_methylation_record(
-    contig: String,
-    n_valid_cov: u32,
-    record: &StringRecord,
-) -> Result<MethylationRecord> {
-    let position: usize = record
-        .get(1)
-        .ok_or_else(|| anyhow!("Missing position field."))?
-        .parse()
-        .map_err(|_| anyhow!("Invalid position field"))?;
-
-    let mod_type: ModType = record
-        .get(3)
-        .ok_or_else(|| anyhow!("Missing modification type field."))?
-        .parse()?;
-
-    let strand: Strand = record
-        .get(5)
-        .ok_or_else(|| anyhow!("Missing strand field"))?
-        .parse()?;
-
-    let n_modified: u32 = record
-        .get(11)
-        .ok_or_else(|| anyhow!("Missing n_modified field."))?
-        .parse()
-        .map_err(|_| anyhow!("Invalid n_modified field"))?;
-
-    let methylation = MethylationCoverage::new(n_modified, n_valid_cov)?;
-
-    let methylation_record =
-        MethylationRecord::new(contig, position, strand, mod_type, methylation);
-
-    Ok(methylation_record)
+use ahash::{AHashMap, AHashSet};
+use anyhow::{anyhow, bail, Context, Result};
+use csv::{ReaderBuilder, StringRecord};
+use methylome::{IupacBase, ModType, Strand};
+use std::{
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+};
+
+use crate::{
+    batch_loader::BatchBy,
+    data::{
+        contig::Contig,
+        methylation::MethylationCoverage,
+        pileup::{ColumnLayout, PileupRecord},
+    },
+};
+
+/// Number of contig names of each set shown when the names do not overlap.
+const N_NAME_EXAMPLES: usize = 5;
+
+/// Checks that the pileup's reported percent_modified agrees with
+/// n_modified / n_valid_cov within `tolerance` percentage points.
+///
+/// A disagreement usually means the columns are misaligned or the pileup is not
+/// in the modkit format.
+pub fn percent_modified_matches(record: &PileupRecord, tolerance: f64) -> Result<bool> {
+    let Some(reported_percent) = record.percent_modified else {
+        bail!("The pileup has no percent_modified column to check.");
+    };
+
+    let computed_percent = MethylationCoverage::new(record.n_modified, record.n_valid_cov)?
+        .fraction_modified()
+        * 100.0;
+
+    Ok((computed_percent - reported_percent).abs() <= tolerance)
+}
+
+/// Checks that a record covers exactly one base, i.e. `end - start == 1`.
+///
+/// A record with `start >= end` or spanning several bases points to a corrupted
+/// pileup.
+pub fn coordinates_are_valid(record: &PileupRecord) -> Result<bool> {
+    let Some(end) = record.end else {
+        bail!("The pileup has no end column to validate.");
+    };
+
+    Ok(record.position < end && end - record.position == 1)
+}
+
+/// Returns true if the base at `position` of the forward strand `sequence` is the
+/// canonical base of `mod_type` on `strand`, e.g. `A` for 6mA on the positive strand
+/// and `T` for 6mA on the negative strand.
+pub fn base_matches_mod_type(
+    sequence: &[u8],
+    position: usize,
+    mod_type: ModType,
+    strand: Strand,
+) -> bool {
+    let base = mod_type.canonical_base();
+    let expected = match strand {
+        Strand::Positive => base,
+        Strand::Negative => IupacBase::to_complement_base(&base),
+    };
+
+    sequence
+        .get(position)
+        .is_some_and(|&b| IupacBase::parse_char(char::from(b)).is_ok_and(|b| b == expected))
+}
+
+/// Tallies whether sampled pileup positions fit a 0-based or a 1-based coordinate
+/// convention.
+///
+/// A position fits a convention if the assembly base at the position, read in that
+/// convention, is the canonical base of the record's mod type. modkit pileups are
+/// 0-based, so nearly every record fits 0-based. In 1-based data the base before the
+/// reported position fits instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoordinateBaseCheck {
+    pub n_sampled: usize,
+    pub zero_based_matches: usize,
+    pub one_based_matches: usize,
+}
+
+impl CoordinateBaseCheck {
+    pub fn record(&mut self, sequence: &[u8], position: usize, mod_type: ModType, strand: Strand) {
+        self.n_sampled += 1;
+        if base_matches_mod_type(sequence, position, mod_type, strand) {
+            self.zero_based_matches += 1;
+        }
+        if position > 0 && base_matches_mod_type(sequence, position - 1, mod_type, strand) {
+            self.one_based_matches += 1;
+        }
+    }
+
+    /// Returns true if clearly more sampled positions fit 1-based than 0-based
+    /// coordinates.
+    pub fn looks_one_based(&self) -> bool {
+        self.one_based_matches > 2 * self.zero_based_matches
+    }
+
+    /// Returns true if clearly more sampled positions fit 0-based than 1-based
+    /// coordinates.
+    pub fn looks_zero_based(&self) -> bool {
+        self.zero_based_matches > 2 * self.one_based_matches
+    }
+}
+
+/// Returns the first `n` distinct contig ids of the pileup, in the order they occur.
+///
+/// With `has_header`, the first line is used to find the contig column by name.
+pub fn sample_pileup_contig_ids<R: Read>(
+    reader: R,
+    has_header: bool,
+    n: usize,
+) -> Result<Vec<String>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(b'\t')
+        .flexible(true)
+        .from_reader(reader);
+
+    let mut record = StringRecord::new();
+    let layout = if has_header {
+        if !reader.read_record(&mut record)? {
+            bail!("The pileup is empty, expected a header line.");
+        }
+        ColumnLayout::from_header(&record)?
+    } else {
+        ColumnLayout::default()
+    };
+
+    let mut seen = AHashSet::new();
+    let mut ids = Vec::new();
+    while ids.len() < n && reader.read_record(&mut record)? {
+        let contig = record
+            .get(layout.contig)
+            .ok_or_else(|| anyhow!("Missing contig field."))?;
+        if seen.insert(contig.to_string()) {
+            ids.push(contig.to_string());
+        }
+    }
+    Ok(ids)
+}
+
+/// Fails if fewer than `min_overlap` of the `pileup_ids` are assembly contig ids.
+///
+/// The error shows examples of both naming schemes, since a mismatch is usually a
+/// prefix or suffix difference such as `contig_3` vs `3`.
+pub fn check_contig_name_overlap(
+    assembly: &AHashMap<String, Contig>,
+    pileup_ids: &[String],
+    min_overlap: f64,
+) -> Result<()> {
+    if pileup_ids.is_empty() {
+        return Ok(());
+    }
+
+    let n_shared = pileup_ids
+        .iter()
+        .filter(|id| assembly.contains_key(*id))
+        .count();
+    let overlap = n_shared as f64 / pileup_ids.len() as f64;
+    if overlap >= min_overlap {
+        return Ok(());
+    }
+
+    let mut assembly_examples: Vec<&str> = assembly.keys().map(String::as_str).collect();
+    assembly_examples.sort();
+    assembly_examples.truncate(N_NAME_EXAMPLES);
+    let pileup_examples: Vec<&str> = pileup_ids
+        .iter()
+        .take(N_NAME_EXAMPLES)
+        .map(String::as_str)
+        .collect();
+
+    bail!(
+        "Only {} of the first {} pileup contigs ({:.0}%) are in the assembly, expected at least {:.0}%. The contig names probably follow different naming schemes.\n  pileup:   {}\n  assembly: {}\nRename the contigs of the assembly or the pileup so the ids match.",
+        n_shared,
+        pileup_ids.len(),
+        overlap * 100.0,
+        min_overlap * 100.0,
+        pileup_examples.join(", "),
+        assembly_examples.join(", ")
+    )
+}
+
+/// Returns a warning if `batch_size` covers the whole assembly, in which case every
+/// contig ends up in a single batch and batching has no effect.
+///
+/// Batches counted in records are not checked, as the pileup size is not known up
+/// front.
+pub fn oversized_batch_warning(
+    assembly: &AHashMap<String, Contig>,
+    batch_size: usize,
+    batch_by: BatchBy,
+) -> Option<String> {
+    let (assembly_size, unit) = match batch_by {
+        BatchBy::Contigs => (assembly.len(), "contigs"),
+        BatchBy::Bases => (
+            assembly.values().map(|contig| contig.sequence.len()).sum(),
+            "bases",
+        ),
+        BatchBy::Records => return None,
+    };
+
+    (batch_size > assembly_size).then(|| {
+        format!(
+            "--batches {} exceeds the {} {} of the assembly, so all contigs are loaded in a single batch.",
+            batch_size, assembly_size, unit
+        )
+    })
+}
+
+/// Checks up front that an output file can be written at `path`, so a read-only
+/// directory or a full disk fails the run at startup instead of after all contigs
+/// are processed.
+///
+/// An existing file is opened for writing but not truncated, and a probe file is
+/// written and synced in the directory. The probe is removed again.
+pub fn check_output_writable(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        bail!("Output path is a directory: {:?}", path);
+    }
+    if path.exists() {
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Output file is not writable: {:?}", path))?;
+    }
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    probe_directory(directory)
+}
+
+/// Same as `check_output_writable`, but for a directory that output files are written
+/// to, which is created if it does not exist yet.
+pub fn check_output_directory_writable(directory: &Path) -> Result<()> {
+    if directory.exists() && !directory.is_dir() {
+        bail!("Output directory is a file: {:?}", directory);
+    }
+    fs::create_dir_all(directory)
+        .with_context(|| format!("Could not create output directory: {:?}", directory))?;
+    probe_directory(directory)
+}
+
+/// Writes, syncs and removes a probe file in `directory`.
+fn probe_directory(directory: &Path) -> Result<()> {
+    let mut probe = tempfile::NamedTempFile::new_in(directory)
+        .with_context(|| format!("Output directory is not writable: {:?}", directory))?;
+    probe
+        .write_all(b"epimetheus")
+        .and_then(|_| probe.as_file().sync_all())
+        .with_context(|| format!("Could not write to the output directory: {:?}", directory))?;
+
+    Ok(())
+}
+
+/// Returns a warning if the mean read coverage of the pileup is below `threshold`,
+/// in which case the methylation of single sites is unreliable.
+pub fn low_coverage_warning(mean_coverage: f64, threshold: f64) -> Option<String> {
+    (mean_coverage < threshold).then(|| {
+        format!(
+            "The mean n_valid_cov of the pileup is {:.2}, below --low-coverage-warn ({}). Methylation calls are unreliable at this coverage; consider sequencing deeper.",
+            mean_coverage, threshold
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pileup_record(line: &str) -> PileupRecord {
+        PileupRecord::from_string_record(
+            &StringRecord::from(line.split('\t').collect::<Vec<_>>()),
+            &ColumnLayout::default(),
+            crate::data::pileup::ModCodeCase::Exact,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_percent_modified_matches() -> Result<()> {
+        let record = pileup_record(
+            "contig_3\t1\t2\ta\t174\t+\t1\t2\t255,0,0\t174\t1.72\t3\t171\t0\t0\t3\t0\t0",
+        );
+        assert!(percent_modified_matches(&record, 0.1)?);
+
+        // 3 / 174 is 1.72%, not 50%.
+        let record = PileupRecord {
+            percent_modified: Some(50.0),
+            ..record
+        };
+        assert!(!percent_modified_matches(&record, 0.1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coordinates_are_valid() -> Result<()> {
+        let record = |start: &str, end: &str| {
+            pileup_record(&format!(
+                "contig_3\t{start}\t{end}\ta\t20\t+\t{start}\t{end}\t255,0,0\t20\t25.00\t5\t15\t0\t0\t0\t0\t0"
+            ))
+        };
+
+        assert!(coordinates_are_valid(&record("7", "8"))?);
+        // Reversed coordinates.
+        assert!(!coordinates_are_valid(&record("8", "7"))?);
+        assert!(!coordinates_are_valid(&record("7", "7"))?);
+        // Spans more than a single base.
+        assert!(!coordinates_are_valid(&record("7", "10"))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coordinate_base_check_flags_one_based() {
+        //               0123456789
+        let sequence = b"CCAGATCTCC";
+
+        // 0-based positions of the 6mA at 4 (+) and 5 (-), and of the 5mC at 0 (+).
+        let mut zero_based = CoordinateBaseCheck::default();
+        for (position, mod_type, strand) in [
+            (4, ModType::SixMA, Strand::Positive),
+            (5, ModType::SixMA, Strand::Negative),
+            (0, ModType::FiveMC, Strand::Positive),
+        ] {
+            zero_based.record(sequence, position, mod_type, strand);
+        }
+        assert_eq!(zero_based.zero_based_matches, 3);
+        assert!(zero_based.looks_zero_based());
+        assert!(!zero_based.looks_one_based());
+
+        // The same records shifted by one.
+        let mut one_based = CoordinateBaseCheck::default();
+        for (position, mod_type, strand) in [
+            (5, ModType::SixMA, Strand::Positive),
+            (6, ModType::SixMA, Strand::Negative),
+            (1, ModType::FiveMC, Strand::Positive),
+        ] {
+            one_based.record(sequence, position, mod_type, strand);
+        }
+        assert_eq!(one_based.one_based_matches, 3);
+        assert!(one_based.looks_one_based());
+        assert!(!one_based.looks_zero_based());
+    }
+
+    #[test]
+    fn test_check_contig_name_overlap() -> Result<()> {
+        let assembly: AHashMap<String, Contig> = ["contig_1", "contig_2", "contig_3"]
+            .into_iter()
+            .map(|id| {
+                (
+                    id.to_string(),
+                    Contig::new(id.to_string(), "GATC".to_string()),
+                )
+            })
+            .collect();
+
+        let pileup = "1\t1\t2\ta\n1\t3\t4\ta\n2\t1\t2\ta\n3\t1\t2\ta\n";
+        let pileup_ids = sample_pileup_contig_ids(pileup.as_bytes(), false, 2)?;
+        assert_eq!(pileup_ids, vec!["1", "2"]);
+
+        let e = check_contig_name_overlap(&assembly, &pileup_ids, 0.5).unwrap_err();
+        let message = e.to_string();
+        assert!(message.contains("Only 0 of the first 2 pileup contigs"));
+        assert!(message.contains("pileup:   1, 2"));
+        assert!(message.contains("assembly: contig_1, contig_2, contig_3"));
+
+        let pileup_ids = vec!["contig_1".to_string(), "contig_9".to_string()];
+        assert!(check_contig_name_overlap(&assembly, &pileup_ids, 0.5).is_ok());
+        assert!(check_contig_name_overlap(&assembly, &pileup_ids, 0.9).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_batch_warning() {
+        let assembly: AHashMap<String, Contig> = [("contig_1", "GATCGATC"), ("contig_2", "GATC")]
+            .into_iter()
+            .map(|(id, sequence)| {
+                (
+                    id.to_string(),
+                    Contig::new(id.to_string(), sequence.to_string()),
+                )
+            })
+            .collect();
+
+        assert!(oversized_batch_warning(&assembly, 2, BatchBy::Contigs).is_none());
+        assert_eq!(
+            oversized_batch_warning(&assembly, 3000, BatchBy::Contigs).unwrap(),
+            "--batches 3000 exceeds the 2 contigs of the assembly, so all contigs are loaded in a single batch."
+        );
+        assert!(oversized_batch_warning(&assembly, 12, BatchBy::Bases).is_none());
+        assert!(oversized_batch_warning(&assembly, 13, BatchBy::Bases).is_some());
+        assert!(oversized_batch_warning(&assembly, 3000, BatchBy::Records).is_none());
+    }
+
+    #[test]
+    fn test_low_coverage_warning() {
+        assert_eq!(
+            low_coverage_warning(3.5, 5.0).unwrap(),
+            "The mean n_valid_cov of the pileup is 3.50, below --low-coverage-warn (5). Methylation calls are unreliable at this coverage; consider sequencing deeper."
+        );
+        assert!(low_coverage_warning(5.0, 5.0).is_none());
+    }
 }
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use log::warn;
+use std::io::Write;
+
+use super::output::json_string;
+
+/// Value of a field of a structured warning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    Count(usize),
+    Number(f64),
+    List(Vec<String>),
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<usize> for FieldValue {
+    fn from(value: usize) -> Self {
+        Self::Count(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<Vec<String>> for FieldValue {
+    fn from(value: Vec<String>) -> Self {
+        Self::List(value)
+    }
+}
+
+impl FieldValue {
+    fn to_json(&self) -> String {
+        match self {
+            Self::Text(value) => json_string(value),
+            Self::Count(value) => value.to_string(),
+            Self::Number(value) if value.is_finite() => value.to_string(),
+            Self::Number(_) => "null".to_string(),
+            Self::List(values) => {
+                let values: Vec<String> = values.iter().map(|value| json_string(value)).collect();
+                format!("[{}]", values.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: &'static str,
+    pub message: String,
+    pub fields: Vec<(&'static str, FieldValue)>,
+}
+
+/// Logs warnings and keeps them for `--warnings-json`.
+#[derive(Debug, Default)]
+pub struct WarningCollector {
+    warnings: Vec<Warning>,
+}
+
+impl WarningCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs `message` with `warn!` and stores it with its `kind` and `fields`.
+    pub fn warn(
+        &mut self,
+        kind: &'static str,
+        message: String,
+        fields: Vec<(&'static str, FieldValue)>,
+    ) {
+        warn!("{}", message);
+        self.warnings.push(Warning {
+            kind,
+            message,
+            fields,
+        });
+    }
+
+    /// Writes the warnings as a JSON array of `{"kind", "message", <fields>...}`
+    /// objects, in the order they were raised.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(writer, "[")?;
+        for (i, warning) in self.warnings.iter().enumerate() {
+            let mut entries = vec![
+                format!("\"kind\": {}", json_string(warning.kind)),
+                format!("\"message\": {}", json_string(&warning.message)),
+            ];
+            for (name, value) in &warning.fields {
+                entries.push(format!("{}: {}", json_string(name), value.to_json()));
+            }
+            let separator = if i + 1 < self.warnings.len() { "," } else { "" };
+            writeln!(writer, "  {{{}}}{}", entries.join(", "), separator)?;
+        }
+        writeln!(writer, "]")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_json() -> Result<()> {
+        let mut collector = WarningCollector::new();
+        let mut output = Vec::new();
+        collector.write_json(&mut output)?;
+        assert_eq!(String::from_utf8(output)?, "[\n]\n");
+
+        collector.warn(
+            "missing_contig",
+            "Circular contig 'contig_9' is not in the assembly".to_string(),
+            vec![
+                ("contig", "contig_9".into()),
+                ("source", "circular_contigs".into()),
+            ],
+        );
+        collector.warn(
+            "low_coverage",
+            "The mean n_valid_cov is low".to_string(),
+            vec![
+                ("mean_coverage", 2.5.into()),
+                ("threshold", f64::NAN.into()),
+                ("keys", vec!["a\"b".to_string()].into()),
+                ("records", 3usize.into()),
+            ],
+        );
+
+        let mut output = Vec::new();
+        collector.write_json(&mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "[\n\
+             \x20 {\"kind\": \"missing_contig\", \"message\": \"Circular contig 'contig_9' is not in the assembly\", \"contig\": \"contig_9\", \"source\": \"circular_contigs\"},\n\
+             \x20 {\"kind\": \"low_coverage\", \"message\": \"The mean n_valid_cov is low\", \"mean_coverage\": 2.5, \"threshold\": null, \"keys\": [\"a\\\"b\"], \"records\": 3}\n\
+             ]\n"
+        );
+
+        Ok(())
+    }
+}
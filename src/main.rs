@@ -6,10 +6,15 @@ use log::info;
 use std::time::Instant;
 
 mod argparser;
+mod batch_loader;
 mod data;
 mod data_load;
 mod extract_methylation_pattern;
+mod postprocessing;
 mod processing;
+mod provenance;
+mod regions;
+mod self_test;
 
 use crate::extract_methylation_pattern::extract_methylation_pattern;
 use argparser::Args;
@@ -23,9 +28,10 @@ fn main() -> Result<()> {
 
     match &args.command {
         argparser::Commands::MethylationPattern(methyl_args) => {
-            let methyl_args = methyl_args.clone();
+            let methyl_args = *methyl_args.clone();
             extract_methylation_pattern(methyl_args)?;
         }
+        argparser::Commands::SelfTest => self_test::self_test()?,
     }
 
     let elapsed_total_duration = total_duration.elapsed();
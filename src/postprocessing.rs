@@ -0,0 +1,847 @@
+use ahash::AHashMap;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use methylome::{ModType, Motif};
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use crate::processing::MotifMethylationDegree;
+
+/// Normalization applied to the median methylation after all contigs are processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Normalization {
+    /// Divide each median by the genome-wide mean methylation of its mod type.
+    GlobalMean,
+    /// Map the motif medians of each contig onto a reference distribution shared by
+    /// all contigs, per mod type.
+    Quantile,
+}
+
+/// Computes the genome-wide mean methylation for each mod type.
+///
+/// The global mean is the mean of the per-site methylation fractions over every
+/// motif site observed in the sample:
+///
+/// `global_mean(mod_type) = sum(mean * n_motif_obs) / sum(n_motif_obs)`
+///
+/// where the sums run over all rows with that mod type. Sites matched by several
+/// motifs are counted once per motif.
+pub fn global_mean_methylation(results: &[MotifMethylationDegree]) -> AHashMap<ModType, f64> {
+    let mut sums: AHashMap<ModType, (f64, u64)> = AHashMap::new();
+
+    for entry in results {
+        let (fraction_sum, n_obs) = sums.entry(entry.motif.mod_type).or_insert((0.0, 0));
+        *fraction_sum += entry.stats.mean * entry.n_motif_obs as f64;
+        *n_obs += entry.n_motif_obs as u64;
+    }
+
+    sums.into_iter()
+        .map(|(mod_type, (fraction_sum, n_obs))| (mod_type, fraction_sum / n_obs as f64))
+        .collect()
+}
+
+/// Sets `normalized_median = median / global_mean(mod_type)` for every row.
+///
+/// Rows whose mod type has a global mean of zero get a normalized median of NaN.
+pub fn normalize_by_global_mean(results: &mut [MotifMethylationDegree]) {
+    let global_means = global_mean_methylation(results);
+
+    for (mod_type, global_mean) in &global_means {
+        info!("Global mean methylation for {}: {}", mod_type, global_mean);
+        if *global_mean == 0.0 {
+            warn!(
+                "Global mean methylation for {} is zero. Normalized median is set to NaN.",
+                mod_type
+            );
+        }
+    }
+
+    for entry in results.iter_mut() {
+        let global_mean = global_means[&entry.motif.mod_type];
+        entry.normalized_median = if global_mean == 0.0 {
+            Some(f64::NAN)
+        } else {
+            Some(entry.stats.median / global_mean)
+        };
+    }
+}
+
+/// Sets `normalized_median` to the quantile normalized median of every row.
+///
+/// For each mod type, the motif medians of each contig (and feature and coverage
+/// bin) form one distribution. The reference distribution is the mean of these
+/// distributions at each quantile, and every median is replaced by the reference
+/// value at its quantile within its own contig. Tied medians get the mean reference
+/// value of their ranks. Contigs with different numbers of motifs are compared by
+/// linear interpolation between ranks.
+pub fn quantile_normalize(results: &mut [MotifMethylationDegree]) {
+    let mut groups: AHashMap<ModType, AHashMap<ControlKey, Vec<usize>>> = AHashMap::new();
+    for (index, entry) in results.iter().enumerate() {
+        groups
+            .entry(entry.motif.mod_type)
+            .or_default()
+            .entry(control_key(entry))
+            .or_default()
+            .push(index);
+    }
+
+    for contig_groups in groups.into_values() {
+        // The row indices of each contig with their medians, sorted by median.
+        let sorted_groups: Vec<(Vec<usize>, Vec<f64>)> = contig_groups
+            .into_values()
+            .map(|mut indices| {
+                indices
+                    .sort_by(|&a, &b| results[a].stats.median.total_cmp(&results[b].stats.median));
+                let medians = indices.iter().map(|&i| results[i].stats.median).collect();
+                (indices, medians)
+            })
+            .collect();
+
+        let n_quantiles = sorted_groups
+            .iter()
+            .map(|(indices, _)| indices.len())
+            .max()
+            .unwrap_or(0);
+        let reference: Vec<f64> = (0..n_quantiles)
+            .map(|rank| {
+                let quantile = rank_quantile(rank, n_quantiles);
+                let sum: f64 = sorted_groups
+                    .iter()
+                    .map(|(_, medians)| interpolated_quantile(medians, quantile))
+                    .sum();
+                sum / sorted_groups.len() as f64
+            })
+            .collect();
+
+        for (indices, medians) in sorted_groups {
+            let mut start = 0;
+            while start < medians.len() {
+                let end = medians[start..]
+                    .iter()
+                    .position(|&median| median != medians[start])
+                    .map_or(medians.len(), |offset| start + offset);
+
+                let normalized = (start..end)
+                    .map(|rank| {
+                        interpolated_quantile(&reference, rank_quantile(rank, indices.len()))
+                    })
+                    .sum::<f64>()
+                    / (end - start) as f64;
+                for &i in &indices[start..end] {
+                    results[i].normalized_median = Some(normalized);
+                }
+                start = end;
+            }
+        }
+    }
+}
+
+/// Quantile of the value at `rank` among `n` sorted values. A single value is the
+/// median.
+fn rank_quantile(rank: usize, n: usize) -> f64 {
+    if n <= 1 {
+        0.5
+    } else {
+        rank as f64 / (n - 1) as f64
+    }
+}
+
+/// Linearly interpolated value at `quantile` (0 to 1) of the sorted `values`.
+fn interpolated_quantile(sorted: &[f64], quantile: f64) -> f64 {
+    let position = quantile * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let weight = position - lower as f64;
+    sorted[lower] * (1.0 - weight) + sorted[upper] * weight
+}
+
+/// Sets `control_difference = median - median(control)` for every row.
+///
+/// The control median is taken from the row of the control motif with the same
+/// contig (and feature and coverage bin). Rows without a control row and the control rows themselves
+/// are left as `None`.
+pub fn subtract_control(results: &mut [MotifMethylationDegree], control: &Motif) {
+    let control_medians: AHashMap<ControlKey, f64> = results
+        .iter()
+        .filter(|entry| entry.motif == *control)
+        .map(|entry| (control_key(entry), entry.stats.median))
+        .collect();
+
+    for entry in results.iter_mut() {
+        if entry.motif == *control {
+            continue;
+        }
+        entry.control_difference = control_medians
+            .get(&control_key(entry))
+            .map(|control_median| entry.stats.median - control_median);
+    }
+}
+
+/// A user-given contig order, e.g. the order of the contigs in a circular map.
+#[derive(Debug, Clone, Default)]
+pub struct ContigOrder {
+    index: AHashMap<String, usize>,
+}
+
+impl ContigOrder {
+    pub fn new<I: IntoIterator<Item = String>>(contig_ids: I) -> Self {
+        let mut index = AHashMap::new();
+        for contig_id in contig_ids {
+            let next = index.len();
+            index.entry(contig_id).or_insert(next);
+        }
+        Self { index }
+    }
+
+    /// Orders listed contigs by their position in the list, followed by the unlisted
+    /// contigs alphabetically.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match (self.index.get(a), self.index.get(b)) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.cmp(b),
+        }
+    }
+}
+
+/// Loads a contig order file with one contig id per line.
+///
+/// Only the first whitespace separated field of a line is used. Empty lines and `#`
+/// lines are skipped, and repeated ids keep their first position.
+pub fn load_contig_order<P: AsRef<Path>>(path: P) -> Result<ContigOrder> {
+    let file = File::open(&path)
+        .with_context(|| format!("Failed to open contig order file at: {:?}", path.as_ref()))?;
+
+    let mut contig_ids = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some(contig_id) = line.split_whitespace().next() {
+            contig_ids.push(contig_id.to_string());
+        }
+    }
+
+    Ok(ContigOrder::new(contig_ids))
+}
+
+/// Sorts the rows by contig in the given order. The sort is stable, so rows of the
+/// same contig keep their order.
+pub fn sort_by_contig_order(results: &mut [MotifMethylationDegree], order: &ContigOrder) {
+    results.sort_by(|a, b| order.compare(&a.contig, &b.contig));
+}
+
+/// Identifies the rows of one contig: contig, feature and coverage bin.
+type ControlKey = (String, Option<String>, Option<String>);
+
+fn control_key(entry: &MotifMethylationDegree) -> ControlKey {
+    (
+        entry.contig.clone(),
+        entry.feature.clone(),
+        entry.coverage_bin.clone(),
+    )
+}
+
+/// Reference median methylation per motif, e.g. of the host genome.
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceProfile {
+    medians: AHashMap<(String, ModType, Vec<u8>), f64>,
+}
+
+impl ReferenceProfile {
+    pub fn insert(&mut self, motif: &Motif, median: f64) -> Result<()> {
+        if self.medians.insert(profile_key(motif), median).is_some() {
+            bail!(
+                "Motif {} occurs more than once in the reference profile.",
+                motif
+            );
+        }
+        Ok(())
+    }
+
+    pub fn median(&self, motif: &Motif) -> Option<f64> {
+        self.medians.get(&profile_key(motif)).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.medians.len()
+    }
+}
+
+fn profile_key(motif: &Motif) -> (String, ModType, Vec<u8>) {
+    (
+        motif.sequence_to_string(),
+        motif.mod_type,
+        motif.mod_positions(),
+    )
+}
+
+/// Loads a reference profile from a TSV with the columns `motif`, `mod_type`,
+/// `mod_position` and `median`, found by name, e.g. the output rows of a reference
+/// contig. Other columns are ignored and every motif may occur only once.
+pub fn load_reference_profile<P: AsRef<Path>>(path: P) -> Result<ReferenceProfile> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(&path)
+        .with_context(|| format!("Failed to open reference profile at: {:?}", path.as_ref()))?;
+
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| -> Result<usize> {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| anyhow!("The reference profile has no '{}' column.", name))
+    };
+    let (motif_column, mod_type_column, mod_position_column, median_column) = (
+        column("motif")?,
+        column("mod_type")?,
+        column("mod_position")?,
+        column("median")?,
+    );
+
+    let mut profile = ReferenceProfile::default();
+    for (line_number, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |index: usize| record.get(index).unwrap_or_default();
+        let parse_row = || -> Result<(Motif, f64)> {
+            let motif: Motif = format!(
+                "{}_{}_{}",
+                field(motif_column),
+                field(mod_type_column),
+                field(mod_position_column)
+            )
+            .parse()?;
+            let median: f64 = field(median_column)
+                .parse()
+                .map_err(|_| anyhow!("Invalid median field"))?;
+            Ok((motif, median))
+        };
+        // The header is line 1.
+        let (motif, median) = parse_row()
+            .with_context(|| format!("Invalid reference profile line {}", line_number + 2))?;
+        profile.insert(&motif, median)?;
+    }
+
+    Ok(profile)
+}
+
+/// Sets `reference_difference = median - median(reference)` for every row whose motif
+/// is in the profile, and flags `deviating_contig` on all rows of a contig (and
+/// feature) where any motif differs from the reference by more than `threshold`.
+///
+/// Rows of contigs without any motif in the profile are left as `None`.
+pub fn compare_to_reference(
+    results: &mut [MotifMethylationDegree],
+    profile: &ReferenceProfile,
+    threshold: f64,
+) {
+    let mut deviating: AHashMap<(String, Option<String>), bool> = AHashMap::new();
+
+    for entry in results.iter_mut() {
+        entry.reference_difference = profile
+            .median(&entry.motif)
+            .map(|reference_median| entry.stats.median - reference_median);
+
+        if let Some(difference) = entry.reference_difference {
+            let contig_deviates = deviating
+                .entry((entry.contig.clone(), entry.feature.clone()))
+                .or_insert(false);
+            *contig_deviates |= difference.abs() > threshold;
+        }
+    }
+
+    for entry in results.iter_mut() {
+        let key = (entry.contig.clone(), entry.feature.clone());
+        entry.deviating_contig = deviating.get(&key).copied();
+    }
+}
+
+/// Drops the rows where not every occurrence of the motif in the contig had a
+/// methylation observation, and returns the number of dropped rows.
+pub fn drop_incomplete_rows(results: &mut Vec<MotifMethylationDegree>) -> usize {
+    let n_rows = results.len();
+    results.retain(|entry| entry.n_motif_obs == entry.motif_occurences_total);
+    n_rows - results.len()
+}
+
+/// Rounds `value` to `decimals` decimals using round-half-to-even (banker's rounding).
+///
+/// The scaled value is first snapped to 6 decimals, so floating point noise such as
+/// `0.12349999999999` and `0.12350000000001` rounds the same way.
+pub fn round_half_even(value: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    let scaled = ((value * scale) * 1e6).round() / 1e6;
+    scaled.round_ties_even() / scale
+}
+
+/// Calls whether each row is methylated with a one-sided binomial test of its
+/// pooled counts against `null_rate`, e.g. the background error rate of the
+/// basecaller.
+///
+/// The p-value is `P(X >= total_modified)` for `X ~ Binomial(total_valid_cov,
+/// null_rate)`. The p-values of all rows are adjusted with Benjamini-Hochberg to
+/// `q_value`, and a row is methylated if `q_value <= fdr`.
+///
+/// The pooled counts treat the reads of all sites as independent, so the test is
+/// strongly powered at high coverage.
+pub fn call_methylation(results: &mut [MotifMethylationDegree], null_rate: f64, fdr: f64) {
+    let p_values: Vec<f64> = results
+        .iter()
+        .map(|entry| {
+            binomial_upper_tail(
+                entry.stats.total_modified,
+                entry.stats.total_valid_cov,
+                null_rate,
+            )
+        })
+        .collect();
+
+    for (entry, q_value) in results.iter_mut().zip(benjamini_hochberg(&p_values)) {
+        entry.q_value = Some(q_value);
+        entry.is_methylated = Some(q_value <= fdr);
+    }
+}
+
+/// `P(X >= k)` for `X ~ Binomial(n, p)`, from the regularized incomplete beta
+/// function `I_p(k, n - k + 1)`.
+fn binomial_upper_tail(k: u64, n: u64, p: f64) -> f64 {
+    if k == 0 {
+        1.0
+    } else if k > n {
+        0.0
+    } else {
+        regularized_incomplete_beta(k as f64, (n - k + 1) as f64, p)
+    }
+}
+
+/// Benjamini-Hochberg adjusted p-values, in the order of `p_values`.
+fn benjamini_hochberg(p_values: &[f64]) -> Vec<f64> {
+    let n = p_values.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| p_values[a].total_cmp(&p_values[b]));
+
+    let mut q_values = vec![0.0; n];
+    let mut running_min: f64 = 1.0;
+    for (rank, &index) in order.iter().enumerate().rev() {
+        running_min = running_min.min(p_values[index] * n as f64 / (rank + 1) as f64);
+        q_values[index] = running_min;
+    }
+    q_values
+}
+
+/// Natural log of the gamma function, with the Lanczos approximation (g = 7).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        // Reflection formula.
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+/// Regularized incomplete beta function `I_x(a, b)`, evaluated with the continued
+/// fraction of Numerical Recipes (betai and betacf).
+fn regularized_incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let ln_front = ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln();
+    // The continued fraction converges fast below the mean of the beta distribution,
+    // the symmetry I_x(a, b) = 1 - I_(1 - x)(b, a) covers the rest.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        ln_front.exp() * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - ln_front.exp() * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 100_000;
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+
+    let clamp = |value: f64| if value.abs() < TINY { TINY } else { value };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut fraction = d;
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+
+        let even = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 / clamp(1.0 + even * d);
+        c = clamp(1.0 + even / c);
+        fraction *= d * c;
+
+        let odd = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 / clamp(1.0 + odd * d);
+        c = clamp(1.0 + odd / c);
+        let delta = d * c;
+        fraction *= delta;
+
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    fraction
+}
+
+/// Rounds every reported statistic of the rows to `decimals` decimals.
+pub fn round_statistics(results: &mut [MotifMethylationDegree], decimals: u32) {
+    let round = |value: f64| round_half_even(value, decimals);
+
+    for entry in results.iter_mut() {
+        entry.stats.median = round(entry.stats.median);
+        entry.stats.weighted_median = round(entry.stats.weighted_median);
+        entry.stats.mean = round(entry.stats.mean);
+        entry.stats.weighted_mean = round(entry.stats.weighted_mean);
+        entry.stats.min = round(entry.stats.min);
+        entry.stats.max = round(entry.stats.max);
+        entry.stats.stddev = round(entry.stats.stddev);
+        entry.stats.mean_read_cov = round(entry.stats.mean_read_cov);
+        entry.stats.coverage_cv = round(entry.stats.coverage_cv);
+        entry.occurrence_density = entry.occurrence_density.map(round);
+        entry.normalized_median = entry.normalized_median.map(round);
+        entry.mode = entry.mode.map(round);
+        entry.control_difference = entry.control_difference.map(round);
+        entry.motif_vs_background = entry.motif_vs_background.map(round);
+        entry.heterogeneity = entry.heterogeneity.map(round);
+        entry.reference_difference = entry.reference_difference.map(round);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::data::methylation::MethylationStats;
+    use methylome::Motif;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    pub(crate) fn result_row(contig: &str, motif: &str, median: f64) -> MotifMethylationDegree {
+        let motif: Vec<&str> = motif.split('_').collect();
+        MotifMethylationDegree {
+            contig: contig.to_string(),
+            motif: Motif::new(motif[0], motif[1], motif[2].parse().unwrap()).unwrap(),
+            stats: MethylationStats {
+                n: 4,
+                median,
+                weighted_median: median,
+                mean: median,
+                weighted_mean: median,
+                total_modified: (median * 80.0).round() as u64,
+                total_valid_cov: 80,
+                min: median,
+                max: median,
+                stddev: 0.0,
+                mean_read_cov: 20.0,
+                coverage_cv: 0.0,
+            },
+            n_motif_obs: 4,
+            motif_occurences_total: 4,
+            occurrence_density: None,
+            normalized_median: None,
+            mode: None,
+            control_difference: None,
+            reference_difference: None,
+            deviating_contig: None,
+            motif_vs_background: None,
+            heterogeneity: None,
+            is_methylated: None,
+            q_value: None,
+            packed_sites: None,
+            provenance_hash: None,
+            feature: None,
+            coverage_bin: None,
+        }
+    }
+
+    #[test]
+    fn test_drop_incomplete_rows() {
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.8),
+            result_row("contig_2", "GATC_a_1", 0.4),
+            result_row("contig_1", "GATC_m_3", 0.5),
+        ];
+        results[1].n_motif_obs = 3;
+
+        assert_eq!(drop_incomplete_rows(&mut results), 1);
+        let kept: Vec<(&str, ModType)> = results
+            .iter()
+            .map(|entry| (entry.contig.as_str(), entry.motif.mod_type))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![("contig_1", ModType::SixMA), ("contig_1", ModType::FiveMC)]
+        );
+    }
+
+    #[test]
+    fn test_normalize_by_global_mean() {
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.8),
+            result_row("contig_2", "GATC_a_1", 0.4),
+            result_row("contig_1", "GATC_m_3", 0.5),
+        ];
+        results[0].stats.mean = 0.9;
+        results[0].n_motif_obs = 2;
+        results[1].stats.mean = 0.3;
+        results[1].n_motif_obs = 6;
+
+        // 6mA: (0.9 * 2 + 0.3 * 6) / 8 = 0.45
+        let global_means = global_mean_methylation(&results);
+        assert!((global_means[&ModType::SixMA] - 0.45).abs() < 1e-12);
+        assert!((global_means[&ModType::FiveMC] - 0.5).abs() < 1e-12);
+
+        normalize_by_global_mean(&mut results);
+        let normalized: Vec<f64> = results
+            .iter()
+            .map(|r| r.normalized_median.unwrap())
+            .collect();
+        assert!((normalized[0] - 0.8 / 0.45).abs() < 1e-12);
+        assert!((normalized[1] - 0.4 / 0.45).abs() < 1e-12);
+        assert!((normalized[2] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_normalize_zero_global_mean() {
+        let mut results = vec![result_row("contig_1", "GATC_a_1", 0.0)];
+        normalize_by_global_mean(&mut results);
+        assert!(results[0].normalized_median.unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_quantile_normalize() {
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.2),
+            result_row("contig_1", "GANTC_a_1", 0.8),
+            result_row("contig_1", "TCGA_a_3", 0.5),
+            result_row("contig_2", "GATC_a_1", 0.4),
+            result_row("contig_2", "GANTC_a_1", 0.6),
+            result_row("contig_2", "TCGA_a_3", 1.0),
+            // Other mod types have their own reference distribution.
+            result_row("contig_1", "GATC_m_3", 0.1),
+        ];
+        quantile_normalize(&mut results);
+
+        // Reference: mean of (0.2, 0.5, 0.8) and (0.4, 0.6, 1.0) per rank.
+        let expected = [0.3, 0.9, 0.55, 0.3, 0.55, 0.9, 0.1];
+        for (entry, expected) in results.iter().zip(expected) {
+            assert!((entry.normalized_median.unwrap() - expected).abs() < 1e-12);
+        }
+
+        // Tied medians share the mean reference value of their ranks, and a contig
+        // with fewer motifs is interpolated onto the reference.
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.2),
+            result_row("contig_1", "GANTC_a_1", 0.8),
+            result_row("contig_1", "TCGA_a_3", 0.8),
+            result_row("contig_2", "GATC_a_1", 0.4),
+            result_row("contig_2", "GANTC_a_1", 1.0),
+        ];
+        quantile_normalize(&mut results);
+
+        // contig_2 at quantiles 0, 0.5 and 1 is (0.4, 0.7, 1.0), so the reference is
+        // (0.3, 0.75, 0.9).
+        let expected = [0.3, 0.825, 0.825, 0.3, 0.9];
+        for (entry, expected) in results.iter().zip(expected) {
+            assert!((entry.normalized_median.unwrap() - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_binomial_upper_tail() {
+        // P(X >= 3) for X ~ Binomial(5, 0.5) = (10 + 5 + 1) / 32.
+        assert!((binomial_upper_tail(3, 5, 0.5) - 0.5).abs() < 1e-12);
+        assert!((binomial_upper_tail(1, 10, 0.1) - (1.0 - 0.9f64.powi(10))).abs() < 1e-12);
+        assert_eq!(binomial_upper_tail(0, 10, 0.1), 1.0);
+        assert!((binomial_upper_tail(10, 10, 0.1) - 1e-10).abs() < 1e-20);
+    }
+
+    #[test]
+    fn test_benjamini_hochberg() {
+        let q_values = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.5]);
+        let expected = [0.04, 0.04 * 4.0 / 3.0, 0.04 * 4.0 / 3.0, 0.5];
+        for (q, expected) in q_values.iter().zip(expected) {
+            assert!((q - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_call_methylation() {
+        let mut results = vec![
+            // 60 of 80 reads modified.
+            result_row("contig_1", "GATC_a_1", 0.75),
+            // 4 of 80 reads modified, at the 5% error rate.
+            result_row("contig_1", "GATC_m_3", 0.05),
+            // 8 of 80 reads modified, within reach of the error rate.
+            result_row("contig_2", "GATC_a_1", 0.1),
+        ];
+        call_methylation(&mut results, 0.05, 0.05);
+
+        let calls: Vec<bool> = results.iter().map(|r| r.is_methylated.unwrap()).collect();
+        assert_eq!(calls, vec![true, false, false]);
+        assert!(results[0].q_value.unwrap() < 1e-30);
+        assert!(results[1].q_value.unwrap() > 0.5);
+    }
+
+    #[test]
+    fn test_subtract_control() {
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.9),
+            result_row("contig_1", "CCWGG_m_1", 0.2),
+            result_row("contig_2", "GATC_a_1", 0.8),
+        ];
+        let control = Motif::new("CCWGG", "m", 1).unwrap();
+
+        subtract_control(&mut results, &control);
+        assert!((results[0].control_difference.unwrap() - 0.7).abs() < 1e-12);
+        // The control row itself is passed through.
+        assert_eq!(results[1].control_difference, None);
+        // contig_2 has no control row.
+        assert_eq!(results[2].control_difference, None);
+    }
+
+    #[test]
+    fn test_round_half_even() {
+        assert_eq!(round_half_even(0.1235, 3), 0.124);
+        assert_eq!(round_half_even(0.1245, 3), 0.124);
+        assert_eq!(round_half_even(2.5, 0), 2.0);
+        assert_eq!(round_half_even(1.0 / 3.0, 3), 0.333);
+    }
+
+    #[test]
+    fn test_round_statistics_is_stable_under_float_noise() {
+        let mut noisy = vec![
+            result_row("contig_1", "GATC_a_1", 0.1235 + 1e-13),
+            result_row("contig_1", "GATC_a_1", 0.1235 - 1e-13),
+            result_row("contig_1", "GATC_a_1", 0.1 + 0.2),
+        ];
+        noisy[2].mode = Some(0.30000000000000004);
+
+        round_statistics(&mut noisy, 3);
+        assert_eq!(noisy[0].stats.median, 0.124);
+        assert_eq!(noisy[1].stats.median, 0.124);
+        assert_eq!(noisy[2].stats.median, 0.3);
+        assert_eq!(noisy[2].mode, Some(0.3));
+        assert_eq!(noisy[2].normalized_median, None);
+    }
+
+    #[test]
+    fn test_sort_by_contig_order() -> Result<()> {
+        let mut order_file = NamedTempFile::new()?;
+        writeln!(order_file, "contig_3")?;
+        writeln!(order_file, "contig_1\tplasmid")?;
+        writeln!(order_file)?;
+        writeln!(order_file, "contig_2")?;
+        let order = load_contig_order(order_file.path())?;
+
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.1),
+            result_row("contig_5", "GATC_a_1", 0.2),
+            result_row("contig_2", "GATC_a_1", 0.3),
+            result_row("contig_1", "CCWGG_m_1", 0.4),
+            result_row("contig_4", "GATC_a_1", 0.5),
+            result_row("contig_3", "GATC_a_1", 0.6),
+        ];
+        sort_by_contig_order(&mut results, &order);
+
+        let rows: Vec<(&str, f64)> = results
+            .iter()
+            .map(|r| (r.contig.as_str(), r.stats.median))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("contig_3", 0.6),
+                ("contig_1", 0.1),
+                ("contig_1", 0.4),
+                ("contig_2", 0.3),
+                // Unlisted contigs follow alphabetically.
+                ("contig_4", 0.5),
+                ("contig_5", 0.2),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_to_reference_flags_deviating_contig() -> Result<()> {
+        let mut profile_file = NamedTempFile::new()?;
+        writeln!(
+            profile_file,
+            "contig\tmotif\tmod_type\tmod_position\tmedian"
+        )?;
+        writeln!(profile_file, "host\tGATC\ta\t1\t0.9")?;
+        writeln!(profile_file, "host\tCCWGG\tm\t1\t0.8")?;
+        let profile = load_reference_profile(profile_file.path())?;
+        assert_eq!(profile.len(), 2);
+
+        let mut results = vec![
+            result_row("contig_1", "GATC_a_1", 0.85),
+            result_row("contig_1", "CCWGG_m_1", 0.75),
+            // contig_2 lacks CCWGG methylation.
+            result_row("contig_2", "GATC_a_1", 0.9),
+            result_row("contig_2", "CCWGG_m_1", 0.05),
+            // Not in the profile.
+            result_row("contig_3", "GANTC_a_1", 0.5),
+        ];
+        compare_to_reference(&mut results, &profile, 0.3);
+
+        let flags: Vec<Option<bool>> = results.iter().map(|r| r.deviating_contig).collect();
+        assert_eq!(
+            flags,
+            vec![Some(false), Some(false), Some(true), Some(true), None]
+        );
+        assert!((results[3].reference_difference.unwrap() + 0.75).abs() < 1e-12);
+        assert_eq!(results[4].reference_difference, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_profile_rejects_duplicates() -> Result<()> {
+        let mut profile_file = NamedTempFile::new()?;
+        writeln!(profile_file, "motif\tmod_type\tmod_position\tmedian")?;
+        writeln!(profile_file, "GATC\ta\t1\t0.9")?;
+        writeln!(profile_file, "GATC\ta\t1\t0.8")?;
+        assert!(load_reference_profile(profile_file.path()).is_err());
+
+        let mut profile_file = NamedTempFile::new()?;
+        writeln!(profile_file, "motif\tmod_type\tmedian")?;
+        assert!(load_reference_profile(profile_file.path()).is_err());
+        Ok(())
+    }
+}
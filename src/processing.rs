@@ -1,134 +1,1133 @@
-use anyhow::{Context, Result};
-use methylome::{find_motif_indices_in_contig, motif::Motif};
+use ahash::{AHashMap, AHashSet};
+use aho_corasick::AhoCorasick;
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use log::debug;
+use methylome::{complement, motif::Motif, IupacBase, ModType, MotifSearcher, Strand};
 use rayon::prelude::*;
 use std::{
-    sync::Arc,
+    borrow::Cow,
+    collections::BTreeMap,
     str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use crate::data::{methylation::MethylationCoverage, GenomeWorkspace};
+use crate::{
+    data::{
+        contig::Contig,
+        methylation::{MedianType, MethylationCoverage, MethylationStats},
+        GenomeWorkspace,
+    },
+    provenance::provenance_hash,
+    regions::Regions,
+};
 
+#[derive(Debug, Clone)]
 pub struct MotifMethylationDegree {
     pub contig: String,
     pub motif: Motif,
-    pub median: f64,
-    pub mean_read_cov: f64,
+    pub stats: MethylationStats,
     pub n_motif_obs: u32,
     pub motif_occurences_total: u32,
+    /// Motif occurrences per kb of contig. See `occurrence_density`.
+    pub occurrence_density: Option<f64>,
+    pub normalized_median: Option<f64>,
+    pub mode: Option<f64>,
+    /// Median minus the median of the control motif in the same contig.
+    pub control_difference: Option<f64>,
+    /// Median minus the median of the motif in the reference profile.
+    pub reference_difference: Option<f64>,
+    /// Whether any motif of the contig deviates from the reference profile.
+    pub deviating_contig: Option<bool>,
+    /// Mean at the motif sites minus the mean of the background in the contig.
+    pub motif_vs_background: Option<f64>,
+    /// Variance of the window medians along the contig. See `heterogeneity`.
+    pub heterogeneity: Option<f64>,
+    /// Whether the pooled counts are significantly above the null rate. See
+    /// `call_methylation`.
+    pub is_methylated: Option<bool>,
+    /// Benjamini-Hochberg adjusted p-value of the methylation call.
+    pub q_value: Option<f64>,
+    /// `n_modified/n_valid_cov` of every site, separated by `;`. See `pack_sites`.
+    pub packed_sites: Option<String>,
+    /// Name of the region the sites fell in, when aggregating per feature.
+    pub feature: Option<String>,
+    /// Label of the coverage bin of the sites, when stratifying by coverage.
+    pub coverage_bin: Option<String>,
+    /// Hash of the inputs of the row. See `provenance_hash`.
+    pub provenance_hash: Option<String>,
+}
+
+/// Strand the pileup reports a modification on.
+///
+/// ```text
+///            position: 0 1 2 3
+///                 (+) 5'-G A T C-3'
+///                        | | | |
+///                 (-) 3'-C T A G-5'
+/// ```
+///
+/// For `GATC_a_1` the methylated A of the forward occurrence sits on the (+) strand
+/// at position 1, and the A of the reverse occurrence sits on the (-) strand at
+/// position 2.
+///
+/// - `Sense`: modifications are reported on the strand where the modified base
+///   physically sits, i.e. `(1, +)` and `(2, -)`. This is what modkit does.
+/// - `Antisense`: modifications are reported on the opposite strand, at the
+///   complement base, i.e. `(1, -)` and `(2, +)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StrandConvention {
+    #[default]
+    Sense,
+    Antisense,
+}
+
+impl StrandConvention {
+    /// Returns the pileup strand on which a modified base on `strand` is reported.
+    fn reported_strand(&self, strand: Strand) -> Strand {
+        match self {
+            StrandConvention::Sense => strand,
+            StrandConvention::Antisense => strand.complement(),
+        }
+    }
+}
+
+/// Options for calculating the methylation pattern.
+#[derive(Debug, Clone, Default)]
+pub struct PatternOptions {
+    pub strand_convention: StrandConvention,
+    /// Bin width of the fraction histogram used for the modal methylation.
+    pub mode_bin_width: Option<f64>,
+    /// Only sites inside these regions are used.
+    pub regions: Option<Arc<Regions>>,
+    /// Aggregate per region instead of per contig. Requires `regions`.
+    pub per_feature: bool,
+    /// Compare the motif sites to the background of the same mod type.
+    pub motif_score: bool,
+    /// Keep the coverage of every site in `packed_sites`.
+    pub pack_sites: bool,
+    /// Report the motif occurrences per kb of contig.
+    pub occurrence_density: bool,
+    /// Hash the inputs of every row together with these run parameters.
+    pub provenance_parameters: Option<String>,
+    /// Median of an even number of sites.
+    pub median_type: MedianType,
+    /// Window size in bp for the heterogeneity score.
+    pub heterogeneity_window: Option<usize>,
+    /// Summarize the sites per coverage bin instead of all together.
+    pub coverage_bins: Option<CoverageBins>,
+    /// Accumulates the time spent on each motif.
+    pub timings: Option<Arc<MotifTimings>>,
+    /// Scans contigs with identical sequences only once.
+    pub duplicate_sequences: Option<Arc<DuplicateSequences>>,
+    /// Scans linear contigs once for all motifs of the automaton.
+    pub motif_automaton: Option<Arc<MotifAutomaton>>,
+    /// The motifs of the run with their regexes compiled once. Other motifs are
+    /// compiled each time they are searched.
+    pub motif_searchers: Arc<[MotifSearcher]>,
+}
+
+impl PatternOptions {
+    /// Returns the searcher of `motif` from `motif_searchers`, or a new one.
+    fn motif_searcher(&self, motif: &Motif) -> Cow<'_, MotifSearcher> {
+        match self.motif_searchers.iter().find(|s| s.motif() == motif) {
+            Some(searcher) => Cow::Borrowed(searcher),
+            None => Cow::Owned(MotifSearcher::new(motif)),
+        }
+    }
+}
+
+/// Motif occurrences of a contig: forward and reverse strand indices.
+type MotifIndices = (Vec<usize>, Vec<usize>);
+
+/// Representative contig id, motif sequence and mod positions.
+type MotifIndicesKey = (String, String, Vec<u8>);
+
+/// Contigs of the assembly with identical sequences, so their motif occurrences are
+/// only searched once.
+///
+/// Only the scanning is shared: the methylation of each contig id still comes from
+/// its own pileup records. The motif occurrences of the first contig of a group are
+/// kept for the other contigs of the group, for as long as the run lasts.
+#[derive(Debug, Default)]
+pub struct DuplicateSequences {
+    /// Representative contig id of every contig whose sequence occurs more than once,
+    /// including the representatives themselves.
+    representatives: AHashMap<String, String>,
+    /// Motif indices by representative contig and motif.
+    indices: Mutex<AHashMap<MotifIndicesKey, Arc<MotifIndices>>>,
+}
+
+impl DuplicateSequences {
+    /// Groups the contigs by sequence. The representative of a group is its
+    /// alphabetically first contig id. Circular and linear contigs are not grouped,
+    /// as their motif occurrences differ.
+    pub fn new(contigs: &AHashMap<String, Contig>) -> Self {
+        let mut groups: AHashMap<(&str, bool), Vec<&str>> = AHashMap::new();
+        for contig in contigs.values() {
+            groups
+                .entry((contig.sequence.as_str(), contig.circular))
+                .or_default()
+                .push(contig.id.as_str());
+        }
+
+        let mut representatives = AHashMap::new();
+        for mut ids in groups.into_values().filter(|ids| ids.len() > 1) {
+            ids.sort_unstable();
+            for id in &ids {
+                representatives.insert(id.to_string(), ids[0].to_string());
+            }
+        }
+
+        Self {
+            representatives,
+            indices: Mutex::new(AHashMap::new()),
+        }
+    }
+
+    /// Number of contigs whose sequence is a copy of another contig's.
+    pub fn n_duplicates(&self) -> usize {
+        self.representatives
+            .iter()
+            .filter(|(id, representative)| id != representative)
+            .count()
+    }
+
+    fn motif_indices(&self, contig: &Contig, searcher: &MotifSearcher) -> MotifIndices {
+        let Some(representative) = self.representatives.get(&contig.id) else {
+            return scan_motif(contig, searcher);
+        };
+
+        let motif = searcher.motif();
+        let key = (
+            representative.clone(),
+            motif.sequence_to_string(),
+            motif.mod_positions(),
+        );
+        if let Some(indices) = self.indices.lock().unwrap().get(&key) {
+            return indices.as_ref().clone();
+        }
+
+        // Scanned outside the lock. Two contigs of a group scanned at the same time
+        // both scan, and the results are identical.
+        let indices = scan_motif(contig, searcher);
+        self.indices
+            .lock()
+            .unwrap()
+            .insert(key, Arc::new(indices.clone()));
+        indices
+    }
+}
+
+/// Indices of a motif on the forward and reverse strand. Linear contigs are scanned
+/// in one pass over both strands.
+fn scan_motif(contig: &Contig, searcher: &MotifSearcher) -> MotifIndices {
+    if contig.circular {
+        (
+            contig.find_motif_indices(searcher, Strand::Positive),
+            contig.find_motif_indices(searcher, Strand::Negative),
+        )
+    } else {
+        searcher.combined_indices(&contig.sequence)
+    }
+}
+
+/// Maximum number of concrete sequences of a motif searched by `MotifAutomaton`.
+/// More degenerate motifs are searched with their regex.
+pub const MAX_MOTIF_EXPANSIONS: usize = 256;
+
+/// Searches a linear contig for many motifs in a single pass.
+///
+/// The motifs are expanded into their concrete sequences on both strands, e.g.
+/// `RGATCY` into `AGATCC`, `AGATCT`, `GGATCC` and `GGATCT`, which are matched by one
+/// Aho-Corasick automaton. The indices are the same as those of the regex search.
+/// Motifs with an `N`, which the regex matches to any contig character, or with more
+/// than `MAX_MOTIF_EXPANSIONS` concrete sequences are not part of the automaton.
+#[derive(Debug)]
+pub struct MotifAutomaton {
+    motifs: Vec<Motif>,
+    /// Mod positions of the forward and reverse motif, for each motif of the
+    /// automaton.
+    mod_positions: Vec<Option<[Vec<u8>; 2]>>,
+    automaton: Option<AhoCorasick>,
+    /// Motif (index in `motifs`) and strand of every pattern of the automaton.
+    targets: Vec<Vec<(usize, Strand)>>,
+}
+
+impl MotifAutomaton {
+    pub fn new(motifs: &[Motif]) -> Result<Self> {
+        let mut mod_positions = Vec::with_capacity(motifs.len());
+        let mut patterns: Vec<String> = Vec::new();
+        let mut pattern_ids: AHashMap<String, usize> = AHashMap::new();
+        let mut targets: Vec<Vec<(usize, Strand)>> = Vec::new();
+
+        for (i, motif) in motifs.iter().enumerate() {
+            let reverse = motif.reverse_complement();
+            let (Some(fwd_sequences), Some(rev_sequences)) =
+                (expand_motif(motif), expand_motif(&reverse))
+            else {
+                mod_positions.push(None);
+                continue;
+            };
+
+            for (strand, sequences) in [
+                (Strand::Positive, fwd_sequences),
+                (Strand::Negative, rev_sequences),
+            ] {
+                for sequence in sequences {
+                    let id = match pattern_ids.get(&sequence) {
+                        Some(&id) => id,
+                        None => {
+                            pattern_ids.insert(sequence.clone(), patterns.len());
+                            patterns.push(sequence);
+                            targets.push(Vec::new());
+                            patterns.len() - 1
+                        }
+                    };
+                    targets[id].push((i, strand));
+                }
+            }
+            mod_positions.push(Some([motif.mod_positions(), reverse.mod_positions()]));
+        }
+
+        let automaton = if patterns.is_empty() {
+            None
+        } else {
+            Some(
+                AhoCorasick::builder()
+                    .ascii_case_insensitive(true)
+                    .build(&patterns)?,
+            )
+        };
+
+        Ok(Self {
+            motifs: motifs.to_vec(),
+            mod_positions,
+            automaton,
+            targets,
+        })
+    }
+
+    /// Number of motifs searched by the automaton.
+    pub fn n_motifs(&self) -> usize {
+        self.mod_positions.iter().flatten().count()
+    }
+
+    /// Forward and reverse strand indices of every motif, as `scan_motif` returns
+    /// them for a linear contig, or None for the motifs not in the automaton.
+    fn scan(&self, sequence: &str) -> Vec<Option<MotifIndices>> {
+        let mut indices: Vec<Option<MotifIndices>> = self
+            .mod_positions
+            .iter()
+            .map(|positions| positions.as_ref().map(|_| MotifIndices::default()))
+            .collect();
+        let Some(automaton) = &self.automaton else {
+            return indices;
+        };
+
+        // Matches come in the order of their end, which for the sequences of one
+        // motif, all of the same length, is the order of their start.
+        for m in automaton.find_overlapping_iter(sequence) {
+            for &(i, strand) in &self.targets[m.pattern().as_usize()] {
+                let (Some([fwd_positions, rev_positions]), Some((fwd, rev))) =
+                    (&self.mod_positions[i], &mut indices[i])
+                else {
+                    continue;
+                };
+                let (positions, strand_indices) = match strand {
+                    Strand::Positive => (fwd_positions, fwd),
+                    Strand::Negative => (rev_positions, rev),
+                };
+                strand_indices.extend(positions.iter().map(|&p| m.start() + p as usize));
+            }
+        }
+
+        // Overlapping matches of a motif with several mod positions can share a base.
+        for (positions, motif_indices) in self.mod_positions.iter().zip(indices.iter_mut()) {
+            if let (Some([fwd_positions, _]), Some((fwd, rev))) = (positions, motif_indices) {
+                if fwd_positions.len() > 1 {
+                    for strand_indices in [fwd, rev] {
+                        strand_indices.sort_unstable();
+                        strand_indices.dedup();
+                    }
+                }
+            }
+        }
+
+        indices
+    }
+}
+
+/// The concrete sequences a motif stands for, or None if it contains an `N` or has
+/// more than `MAX_MOTIF_EXPANSIONS` of them.
+fn expand_motif(motif: &Motif) -> Option<Vec<String>> {
+    if motif.sequence.contains(&IupacBase::N) {
+        return None;
+    }
+    let n_sequences = motif
+        .sequence
+        .iter()
+        .try_fold(1usize, |n, base| n.checked_mul(base.degeneracy()))?;
+    if n_sequences > MAX_MOTIF_EXPANSIONS {
+        return None;
+    }
+
+    let mut sequences = vec![String::new()];
+    for base in &motif.sequence {
+        sequences = sequences
+            .iter()
+            .flat_map(|prefix| {
+                base.concrete_bases()
+                    .iter()
+                    .map(move |concrete| format!("{}{}", prefix, concrete))
+            })
+            .collect();
+    }
+    Some(sequences)
+}
+
+/// Wall time spent scanning and aggregating each motif, summed over all contigs.
+///
+/// The times are summed over threads, so their total can exceed the elapsed time.
+#[derive(Debug)]
+pub struct MotifTimings {
+    motifs: Vec<Motif>,
+    nanos: Vec<AtomicU64>,
+}
+
+impl MotifTimings {
+    pub fn new(motifs: &[Motif]) -> Self {
+        Self {
+            motifs: motifs.to_vec(),
+            nanos: motifs.iter().map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Adds `elapsed` to the time of `motif`. Unknown motifs are ignored.
+    pub fn record(&self, motif: &Motif, elapsed: Duration) {
+        if let Some(index) = self.motifs.iter().position(|m| m == motif) {
+            let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+            self.nanos[index].fetch_add(nanos, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the time of every motif, slowest first.
+    pub fn summary(&self) -> Vec<(Motif, Duration)> {
+        let mut summary: Vec<(Motif, Duration)> = self
+            .motifs
+            .iter()
+            .zip(&self.nanos)
+            .map(|(motif, nanos)| {
+                (
+                    motif.clone(),
+                    Duration::from_nanos(nanos.load(Ordering::Relaxed)),
+                )
+            })
+            .collect();
+        summary.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+        summary
+    }
 }
 
+/// Maximum number of sites in `packed_sites`. Rows with more sites are truncated.
+pub const MAX_PACKED_SITES: usize = 10_000;
+
 pub fn calculate_contig_read_methylation_pattern(
     contigs: GenomeWorkspace,
     motifs: Vec<Motif>,
     num_threads: usize,
+    options: &PatternOptions,
 ) -> Result<Vec<MotifMethylationDegree>> {
-
     rayon::ThreadPoolBuilder::new()
         .num_threads(num_threads)
         .build()
         .expect("Could not initialize threadpool");
 
-    let motifs = Arc::new(motifs);
+    let contig_ids: Vec<&String> = contigs.contig_ids().collect();
+
+    let results: Vec<Vec<MotifMethylationDegree>> = contig_ids
+        .par_iter()
+        .map(|contig_id| contigs.contig_methylation_pattern(contig_id, &motifs, options))
+        .collect::<Result<_>>()?;
 
-    let results: Vec<MotifMethylationDegree> = contigs.get_workspace().par_iter().flat_map(|(contig_id, contig)| {
-    let contig_seq = &contig.sequence;
+    Ok(results.into_iter().flatten().collect())
+}
 
-    let mut local_results = Vec::new();
+/// Calculates the methylation pattern of every motif in a single contig.
+pub fn contig_methylation_pattern(
+    contig: &Contig,
+    motifs: &[Motif],
+    options: &PatternOptions,
+) -> Vec<MotifMethylationDegree> {
+    let mut results = Vec::new();
 
-    for motif in motifs.iter() {
-        let mod_type = motif.mod_type;
+    // A single pass over a linear contig for the motifs of the automaton. The time of
+    // this pass is not part of the per-motif timings.
+    let mut automaton_indices = options
+        .motif_automaton
+        .as_deref()
+        .filter(|_| !contig.circular)
+        .map(|automaton| (automaton, automaton.scan(&contig.sequence)));
 
-        let fwd_indices: Vec<usize> = find_motif_indices_in_contig(&contig_seq, motif);
-        let rev_indices: Vec<usize> = find_motif_indices_in_contig(&contig_seq, &motif.reverse_complement());
+    for motif in motifs {
+        let start = Instant::now();
 
-        if fwd_indices.is_empty() && rev_indices.is_empty() {
+        if contig.sequence.len() < motif.sequence.len() {
+            debug!(
+                "Skipping motif {} in contig {}: the contig ({} bp) is shorter than the motif.",
+                motif.sequence_to_string(),
+                contig.id,
+                contig.sequence.len()
+            );
             continue;
         }
 
-        // This is the actual number of motifs in the contig
-        let motif_occurences_total = fwd_indices.len() as u32 + rev_indices.len() as u32;
-
-        let mut fwd_methylation = contig.get_methylated_positions(&fwd_indices, methylome::Strand::Positive, mod_type);
-        let mut rev_methylation = contig.get_methylated_positions(&rev_indices, methylome::Strand::Negative, mod_type);
+        let scanned = automaton_indices.as_mut().and_then(|(automaton, indices)| {
+            let i = automaton.motifs.iter().position(|m| m == motif)?;
+            indices[i].take()
+        });
+        let (fwd_indices, rev_indices) = match (scanned, &options.duplicate_sequences) {
+            (Some(indices), _) => indices,
+            (None, Some(duplicates)) => {
+                duplicates.motif_indices(contig, &options.motif_searcher(motif))
+            }
+            (None, None) => scan_motif(contig, &options.motif_searcher(motif)),
+        };
 
-        fwd_methylation.append(&mut rev_methylation);
+        let background = if options.motif_score {
+            background_mean(contig, motif, &fwd_indices, &rev_indices, options)
+        } else {
+            None
+        };
 
-        let methylation_data: Vec<MethylationCoverage> = fwd_methylation.into_iter().filter_map(|maybe_cov| maybe_cov.cloned()).collect();
+        let mut motif_results =
+            summarize_motif_in_contig(contig, motif, fwd_indices, rev_indices, options);
+        for degree in motif_results.iter_mut() {
+            degree.motif_vs_background = background.map(|b| degree.stats.mean - b);
+        }
+        results.append(&mut motif_results);
 
-        if methylation_data.is_empty() {
-            continue;
+        if let Some(timings) = &options.timings {
+            timings.record(motif, start.elapsed());
         }
+    }
 
-        // This is number of motif obervations with methylation data
-        let n_motif_obs = methylation_data.len() as u32;
-         
-        let mean_read_cov = {
-            let total_cov: u64 = methylation_data.iter().map(|cov| cov.get_n_valid_cov() as u64).sum();
-            total_cov as f64 / methylation_data.len() as f64
-        };
+    results
+}
 
-        let mut fractions: Vec<f64> = methylation_data
-           .iter()
-           .map(|cov| cov.fraction_modified())
-           .collect();
+/// Aggregates the methylation of a motif in a contig, restricted to and optionally
+/// split by the regions in `options`.
+fn summarize_motif_in_contig(
+    contig: &Contig,
+    motif: &Motif,
+    fwd_indices: Vec<usize>,
+    rev_indices: Vec<usize>,
+    options: &PatternOptions,
+) -> Vec<MotifMethylationDegree> {
+    let Some(regions) = &options.regions else {
+        return summarize_motif_sites(contig, motif, &fwd_indices, &rev_indices, options);
+    };
 
-        fractions.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let median = if fractions.len() % 2 == 0 {
-            let mid = fractions.len() / 2;
-            (fractions[mid - 1] + fractions[mid]) / 2.0
-        } else {
-            fractions[fractions.len() / 2]
+    // Contigs without intervals have no sites inside the regions.
+    let Some(index) = regions.get(&contig.id) else {
+        return Vec::new();
+    };
+
+    if options.per_feature {
+        index
+            .intervals()
+            .iter()
+            .flat_map(|interval| {
+                let mut degrees = summarize_motif_sites(
+                    contig,
+                    motif,
+                    interval.slice(&fwd_indices),
+                    interval.slice(&rev_indices),
+                    options,
+                );
+                for degree in degrees.iter_mut() {
+                    degree.feature = Some(interval.name.clone());
+                }
+                degrees
+            })
+            .collect()
+    } else {
+        let in_regions = |indices: Vec<usize>| -> Vec<usize> {
+            indices.into_iter().filter(|i| index.contains(*i)).collect()
         };
+        summarize_motif_sites(
+            contig,
+            motif,
+            &in_regions(fwd_indices),
+            &in_regions(rev_indices),
+            options,
+        )
+    }
+}
+
+/// Mean methylation fraction of the background of a motif in a contig.
+///
+/// The background is every methylation record of the motif's mod type that is not a
+/// site of the motif (on either strand), i.e. the same base in other sequence
+/// contexts. Sites of other motifs are part of the background. Returns `None` if the
+/// contig has no background records.
+fn background_mean(
+    contig: &Contig,
+    motif: &Motif,
+    fwd_indices: &[usize],
+    rev_indices: &[usize],
+    options: &PatternOptions,
+) -> Option<f64> {
+    let motif_sites: AHashSet<(usize, Strand)> = fwd_indices
+        .iter()
+        .map(|&i| {
+            (
+                i,
+                options.strand_convention.reported_strand(Strand::Positive),
+            )
+        })
+        .chain(rev_indices.iter().map(|&i| {
+            (
+                i,
+                options.strand_convention.reported_strand(Strand::Negative),
+            )
+        }))
+        .collect();
 
-        local_results.push(MotifMethylationDegree {
-            contig: contig_id.clone(),
-            motif: motif.clone(),
-            median,
-            mean_read_cov,
-            n_motif_obs,
-            motif_occurences_total,
+    let (sum, n) = contig
+        .methylated_positions
+        .iter()
+        .filter(|((position, strand, mod_type), _)| {
+            *mod_type == motif.mod_type && !motif_sites.contains(&(*position, *strand))
         })
-     }
+        .fold((0.0, 0usize), |(sum, n), (_, cov)| {
+            (sum + cov.fraction_modified(), n + 1)
+        });
+
+    (n > 0).then(|| sum / n as f64)
+}
+
+/// Aggregates the methylation of the motif sites at `fwd_indices` and `rev_indices`.
+///
+/// Gives one row, or one row per coverage bin with sites if `options` has coverage
+/// bins. Sites without methylation data are left out, and no row is returned if none
+/// of the sites have methylation data.
+fn summarize_motif_sites(
+    contig: &Contig,
+    motif: &Motif,
+    fwd_indices: &[usize],
+    rev_indices: &[usize],
+    options: &PatternOptions,
+) -> Vec<MotifMethylationDegree> {
+    let mod_type = motif.mod_type;
+
+    if fwd_indices.is_empty() && rev_indices.is_empty() {
+        return Vec::new();
+    }
+
+    // This is the actual number of motifs in the contig (or region)
+    let motif_occurences_total = fwd_indices.len() as u32 + rev_indices.len() as u32;
+
+    let mut fwd_methylation = contig.get_methylated_positions(
+        fwd_indices,
+        options.strand_convention.reported_strand(Strand::Positive),
+        mod_type,
+    );
+    let mut rev_methylation = contig.get_methylated_positions(
+        rev_indices,
+        options.strand_convention.reported_strand(Strand::Negative),
+        mod_type,
+    );
+
+    fwd_methylation.append(&mut rev_methylation);
 
-     local_results
+    let sites: Vec<(usize, MethylationCoverage)> = fwd_indices
+        .iter()
+        .chain(rev_indices)
+        .zip(fwd_methylation)
+        .filter_map(|(&position, maybe_cov)| maybe_cov.map(|cov| (position, *cov)))
+        .collect();
 
-        
-    }).collect();
+    let Some(bins) = &options.coverage_bins else {
+        return summarize_sites(contig, motif, &sites, motif_occurences_total, options)
+            .into_iter()
+            .collect();
+    };
 
-    Ok(results)
+    let mut binned_sites: Vec<Vec<(usize, MethylationCoverage)>> = vec![Vec::new(); bins.len()];
+    for site in sites {
+        if let Some(bin) = bins.bin_of(site.1.get_n_valid_cov()) {
+            binned_sites[bin].push(site);
+        }
+    }
+
+    binned_sites
+        .iter()
+        .enumerate()
+        .filter_map(|(bin, sites)| {
+            let mut degree =
+                summarize_sites(contig, motif, sites, motif_occurences_total, options)?;
+            degree.coverage_bin = Some(bins.label(bin));
+            Some(degree)
+        })
+        .collect()
 }
 
-pub fn create_motifs(motifs_str: Vec<String>) -> Result<Vec<Motif>> {
-    motifs_str.into_iter().map(|motif| {
-        let parts: Vec<&str> = motif.split("_").collect();
+/// Summarizes the `(position, coverage)` of motif sites with methylation data into a
+/// row. Returns `None` if there are no sites.
+fn summarize_sites(
+    contig: &Contig,
+    motif: &Motif,
+    sites: &[(usize, MethylationCoverage)],
+    motif_occurences_total: u32,
+    options: &PatternOptions,
+) -> Option<MotifMethylationDegree> {
+    let methylation_data: Vec<MethylationCoverage> = sites.iter().map(|(_, cov)| *cov).collect();
 
-        if parts.len() != 3 {
-            anyhow::bail!(
-                "Invalid motif format '{}' encountered. Expected format: '<sequence>_<mod_type>_<mod_position>'",
-                motif
-            );
+    let stats = MethylationStats::from_coverages(&methylation_data, options.median_type)?;
+
+    // This is number of motif obervations with methylation data
+    let n_motif_obs = methylation_data.len() as u32;
+
+    Some(MotifMethylationDegree {
+        contig: contig.id.clone(),
+        motif: motif.clone(),
+        stats,
+        n_motif_obs,
+        motif_occurences_total,
+        occurrence_density: options
+            .occurrence_density
+            .then(|| occurrence_density(motif_occurences_total, contig.len())),
+        normalized_median: None,
+        control_difference: None,
+        reference_difference: None,
+        deviating_contig: None,
+        motif_vs_background: None,
+        heterogeneity: options
+            .heterogeneity_window
+            .and_then(|window| heterogeneity(sites, window, options.median_type)),
+        is_methylated: None,
+        q_value: None,
+        packed_sites: options.pack_sites.then(|| pack_sites(&methylation_data)),
+        feature: None,
+        coverage_bin: None,
+        provenance_hash: options
+            .provenance_parameters
+            .as_ref()
+            .map(|parameters| provenance_hash(contig, motif, sites, parameters)),
+        mode: options.mode_bin_width.map(|bin_width| {
+            let fractions: Vec<f64> = methylation_data
+                .iter()
+                .map(|cov| cov.fraction_modified())
+                .collect();
+            mode_of_fractions(&fractions, bin_width)
+        }),
+    })
+}
+
+/// Coverage bins given by ascending edges, e.g. `0,10,30,inf` for the bins
+/// `[0, 10)`, `[10, 30)` and `[30, inf)`. Sites outside the edges are in no bin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageBins {
+    edges: Vec<f64>,
+}
+
+impl CoverageBins {
+    pub fn new(edges: Vec<f64>) -> Result<Self> {
+        if edges.len() < 2 {
+            bail!("Coverage bins need at least two edges, got {}", edges.len());
         }
+        if edges.iter().any(|edge| edge.is_nan() || *edge < 0.0) {
+            bail!("Coverage bin edges must be non-negative numbers.");
+        }
+        if edges.windows(2).any(|w| w[0] >= w[1]) {
+            bail!("Coverage bin edges must be strictly increasing.");
+        }
+        Ok(Self { edges })
+    }
 
-            let sequence = parts[0];
-            let mod_type = parts[1];
-            let mod_position = u8::from_str(parts[2]).with_context(|| {
-                format!("Failed to parse mod_position '{}' in motif '{}'.", parts[2], motif)
-            })?;
+    /// Number of bins.
+    pub fn len(&self) -> usize {
+        self.edges.len() - 1
+    }
+
+    /// Index of the bin holding `coverage`.
+    pub fn bin_of(&self, coverage: u32) -> Option<usize> {
+        let coverage = coverage as f64;
+        self.edges
+            .windows(2)
+            .position(|w| w[0] <= coverage && coverage < w[1])
+    }
+
+    /// Label of a bin, e.g. `10-30`.
+    pub fn label(&self, bin: usize) -> String {
+        format!("{}-{}", self.edges[bin], self.edges[bin + 1])
+    }
+}
 
-            Motif::new(sequence, mod_type, mod_position).with_context(|| {
-                format!("Failed to create motif from '{}'", motif)
+impl FromStr for CoverageBins {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let edges = s
+            .split(',')
+            .map(|edge| {
+                edge.trim()
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("Invalid coverage bin edge '{}'", edge))
             })
-        
-    }).collect()
+            .collect::<Result<Vec<f64>>>()?;
+        Self::new(edges)
+    }
+}
+
+/// Scores how much the methylation of the sites changes along the contig.
+///
+/// The sites are binned into windows of `window` bp by position, and the score is the
+/// population variance of the median fraction of each window with sites. A contig
+/// with a uniform methylome scores close to 0, while a contig mixing a methylated and
+/// an unmethylated part (e.g. a chimera) scores up to 0.25. Returns `None` if fewer
+/// than two windows have sites.
+pub fn heterogeneity(
+    sites: &[(usize, MethylationCoverage)],
+    window: usize,
+    median_type: MedianType,
+) -> Option<f64> {
+    let mut windows: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+    for (position, cov) in sites {
+        windows
+            .entry(position / window)
+            .or_default()
+            .push(cov.fraction_modified());
+    }
+    if windows.len() < 2 {
+        return None;
+    }
+
+    let medians: Vec<f64> = windows
+        .into_values()
+        .map(|mut fractions| {
+            fractions.sort_by(|a, b| a.total_cmp(b));
+            median_type.median(&fractions)
+        })
+        .collect();
+    let mean = medians.iter().sum::<f64>() / medians.len() as f64;
+    let variance = medians.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / medians.len() as f64;
+
+    Some(variance)
+}
+
+/// Packs the coverages as `n_modified/n_valid_cov` separated by `;`, e.g.
+/// `15/20;5/20`.
+///
+/// Only the first `MAX_PACKED_SITES` sites are packed, followed by `;...` if there
+/// are more, so a motif with a huge number of sites does not produce an enormous cell.
+pub fn pack_sites(coverages: &[MethylationCoverage]) -> String {
+    let mut packed: Vec<String> = coverages
+        .iter()
+        .take(MAX_PACKED_SITES)
+        .map(|cov| format!("{}/{}", cov.get_n_modified(), cov.get_n_valid_cov()))
+        .collect();
+    if coverages.len() > MAX_PACKED_SITES {
+        packed.push("...".to_string());
+    }
+    packed.join(";")
+}
+
+/// Motif occurrences on both strands per kb of contig:
+/// `motif_occurences_total / contig_length * 1000`.
+pub fn occurrence_density(motif_occurences_total: u32, contig_length: usize) -> f64 {
+    motif_occurences_total as f64 / contig_length as f64 * 1000.0
+}
+
+/// Smallest bin width of `mode_of_fractions`, which allocates `1 / bin_width` bins.
+pub const MIN_MODE_BIN_WIDTH: f64 = 1e-4;
+
+/// Returns the modal methylation fraction.
+///
+/// The fractions are binned into a histogram over [0, 1] with bins of `bin_width`
+/// (the last bin also holds 1.0), and the center of the bin with the highest count is
+/// returned. Ties go to the lowest bin.
+pub fn mode_of_fractions(fractions: &[f64], bin_width: f64) -> f64 {
+    let n_bins = (1.0 / bin_width).ceil() as usize;
+    let mut histogram = vec![0usize; n_bins];
+
+    for fraction in fractions {
+        let bin = ((fraction / bin_width).floor() as usize).min(n_bins - 1);
+        histogram[bin] += 1;
+    }
+
+    let mut mode_bin = 0;
+    for (bin, count) in histogram.iter().enumerate() {
+        if *count > histogram[mode_bin] {
+            mode_bin = bin;
+        }
+    }
+
+    let bin_start = mode_bin as f64 * bin_width;
+    let bin_end = (bin_start + bin_width).min(1.0);
+    (bin_start + bin_end) / 2.0
+}
+
+/// Counts the occurrences of each motif across the assembly.
+///
+/// Occurrences are counted on both strands, the same way as `motif_occurences_total`.
+pub fn count_genome_occurrences(contigs: &AHashMap<String, Contig>, motifs: &[Motif]) -> Vec<u64> {
+    motifs
+        .par_iter()
+        .map(|motif| {
+            let searcher = MotifSearcher::new(motif);
+            contigs
+                .values()
+                .map(|contig| {
+                    let fwd = contig.count_motif_occurrences(&searcher, Strand::Positive);
+                    let rev = contig.count_motif_occurrences(&searcher, Strand::Negative);
+                    (fwd + rev) as u64
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Drops motifs occurring fewer than `min_occurrences` times across the assembly.
+///
+/// Returns the kept motifs and the dropped motifs with their occurrence count.
+pub fn filter_motifs_by_genome_occurrences(
+    contigs: &AHashMap<String, Contig>,
+    motifs: Vec<Motif>,
+    min_occurrences: u64,
+) -> (Vec<Motif>, Vec<(Motif, u64)>) {
+    let occurrences = count_genome_occurrences(contigs, &motifs);
+
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    for (motif, n) in motifs.into_iter().zip(occurrences) {
+        if n < min_occurrences {
+            dropped.push((motif, n));
+        } else {
+            kept.push(motif);
+        }
+    }
+    (kept, dropped)
+}
+
+/// Counts methylation records that are attributed to more than one motif occurrence.
+///
+/// Overlapping motifs (e.g. `GATC` and `RGATCY`) or overlapping occurrences of the same
+/// motif can point at the same modified base. Such a record then contributes to several
+/// motif observations, which inflates `n_motif_obs`.
+pub fn count_shared_methylation_records(
+    contig: &Contig,
+    motifs: &[Motif],
+    options: &PatternOptions,
+) -> usize {
+    let mut attributions: AHashMap<(usize, Strand, ModType), u32> = AHashMap::new();
+
+    for motif in motifs {
+        let searcher = options.motif_searcher(motif);
+        for motif_strand in [Strand::Positive, Strand::Negative] {
+            let strand = options.strand_convention.reported_strand(motif_strand);
+            for position in contig.find_motif_indices(&searcher, motif_strand) {
+                let key = (position, strand, motif.mod_type);
+                if contig.methylated_positions.contains_key(&key) {
+                    *attributions.entry(key).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    attributions.values().filter(|&&n| n > 1).count()
+}
+
+/// A motif site with methylation data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotifSite {
+    pub contig: String,
+    pub motif: Motif,
+    /// Position of the modified base.
+    pub position: usize,
+    /// Strand of the pileup record.
+    pub strand: Strand,
+    pub methylation: MethylationCoverage,
+    /// Sequence around the modified base, see `sequence_context`.
+    pub context: Option<String>,
+}
+
+/// Returns the motif sites of the contig that have methylation data, sorted by motif
+/// (in the order of `motifs`) and position.
+///
+/// With `context_window` the ±`context_window` bases around each site are added.
+pub fn collect_motif_sites(
+    contig: &Contig,
+    motifs: &[Motif],
+    options: &PatternOptions,
+    context_window: Option<usize>,
+) -> Vec<MotifSite> {
+    let mut sites = Vec::new();
+
+    for motif in motifs {
+        let searcher = options.motif_searcher(motif);
+        let mut motif_sites: Vec<MotifSite> = [Strand::Positive, Strand::Negative]
+            .into_iter()
+            .flat_map(|motif_strand| {
+                let strand = options.strand_convention.reported_strand(motif_strand);
+                contig
+                    .find_motif_indices(&searcher, motif_strand)
+                    .into_iter()
+                    .filter_map(move |position| {
+                        let methylation =
+                            contig
+                                .methylated_positions
+                                .get(&(position, strand, motif.mod_type))?;
+                        Some(MotifSite {
+                            contig: contig.id.clone(),
+                            motif: motif.clone(),
+                            position,
+                            strand,
+                            methylation: *methylation,
+                            context: context_window.map(|window| {
+                                sequence_context(&contig.sequence, position, window, motif_strand)
+                            }),
+                        })
+                    })
+            })
+            .collect();
+
+        motif_sites.sort_by_key(|site| site.position);
+        sites.append(&mut motif_sites);
+    }
+
+    sites
+}
+
+/// Returns the `window` bases on each side of `position` and the base itself.
+///
+/// Bases beyond the contig ends are padded with `-`, so every context is
+/// `2 * window + 1` bases long with the modified base in the middle. Contexts of sites
+/// on the negative strand are reverse complemented, so they read 5' to 3' on the
+/// strand of the motif occurrence.
+pub fn sequence_context(sequence: &str, position: usize, window: usize, strand: Strand) -> String {
+    let sequence = sequence.as_bytes();
+    let context = (position as isize - window as isize..=(position + window) as isize).map(|i| {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| sequence.get(i))
+            .map_or('-', |&base| base as char)
+    });
+
+    match strand {
+        Strand::Positive => context.collect(),
+        Strand::Negative => context
+            .rev()
+            .map(|base| complement(base).unwrap_or(base))
+            .collect(),
+    }
+}
+
+/// Returns the `(id, length)` of the assembly contigs that are not in `covered`,
+/// sorted by id.
+///
+/// `covered` holds the contigs that received at least one methylation record from the
+/// pileup.
+pub fn find_uncovered_contigs<'a>(
+    assembly: &'a AHashMap<String, Contig>,
+    covered: &AHashSet<String>,
+) -> Vec<(&'a str, usize)> {
+    let mut uncovered: Vec<(&str, usize)> = assembly
+        .values()
+        .filter(|contig| !covered.contains(&contig.id))
+        .map(|contig| (contig.id.as_str(), contig.sequence.len()))
+        .collect();
+    uncovered.sort();
+    uncovered
+}
+
+/// Parses every motif with `Motif::from_str`, failing on the first invalid one.
+pub fn create_motifs(motifs_str: Vec<String>) -> Result<Vec<Motif>> {
+    motifs_str.iter().map(|motif| motif.parse()).collect()
+}
+
+/// Parses every motif and returns the valid motifs together with the invalid motif
+/// strings and the reason they failed, so all problems can be reported at once.
+pub fn partition_motifs(motifs_str: Vec<String>) -> (Vec<Motif>, Vec<(String, anyhow::Error)>) {
+    let mut valid = Vec::new();
+    let mut invalid = Vec::new();
+
+    for motif in motifs_str {
+        match motif.parse() {
+            Ok(parsed) => valid.push(parsed),
+            Err(e) => invalid.push((motif, e)),
+        }
+    }
+    (valid, invalid)
+}
+
+/// Checks a set of parsed motifs for suspicious combinations.
+///
+/// Returns a warning for each motif supplied more than once and for each motif base
+/// (same sequence and mod_position) claimed by more than one mod type, e.g. both 5mC
+/// and 4mC at the same C.
+pub fn find_motif_conflicts(motifs: &[Motif]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut mod_types_by_base: AHashMap<(String, u8), Vec<ModType>> = AHashMap::new();
+
+    for (i, motif) in motifs.iter().enumerate() {
+        let sequence = motif.sequence_to_string();
+
+        if motifs[..i].contains(motif) {
+            warnings.push(format!("Motif {} is supplied more than once.", motif));
+            continue;
+        }
+
+        for mod_position in motif.mod_positions() {
+            mod_types_by_base
+                .entry((sequence.clone(), mod_position))
+                .or_default()
+                .push(motif.mod_type);
+        }
+    }
+
+    let mut conflicting_bases: Vec<_> = mod_types_by_base
+        .into_iter()
+        .filter(|(_, mod_types)| mod_types.len() > 1)
+        .collect();
+    conflicting_bases.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for ((sequence, mod_position), mod_types) in conflicting_bases {
+        let mod_types: Vec<String> = mod_types.iter().map(|m| m.to_string()).collect();
+        warnings.push(format!(
+            "Motif {} at mod_position {} is claimed by multiple mod types: {}.",
+            sequence,
+            mod_position,
+            mod_types.join(", ")
+        ));
+    }
+
+    warnings
 }
 
 #[cfg(test)]
 mod tests {
     use csv::ReaderBuilder;
+    use methylome::find_motif_indices_in_contig;
+    use std::{
+        fs::File,
+        io::{BufReader, Write},
+    };
     use tempfile::NamedTempFile;
-    use std::{fs::File, io::{BufReader, Write}};
 
-    use crate::{data::{contig::Contig, GenomeWorkspaceBuilder}, extract_methylation_pattern::parse_to_methylation_record};
+    use crate::{
+        batch_loader::BatchLoader,
+        data::{
+            pileup::{ColumnLayout, ModCodeCase, PileupRecord},
+            GenomeWorkspaceBuilder, MethylationRecord,
+        },
+        regions::Interval,
+    };
 
     use super::*;
 
@@ -156,13 +1155,15 @@ mod tests {
             "contig_3\t13\t1\ta\t133\t-\t0\t1\t255,0,0\t20\t0.00\t5\t123\t0\t0\t6\t0\t0"
         )?;
 
-
-
         let mut workspace_builder = GenomeWorkspaceBuilder::new();
 
         // Add a mock contig to the workspace
-        workspace_builder.add_contig(Contig::new("contig_3".to_string(), "TGGACGATCCCGATC".to_string())).unwrap();
-
+        workspace_builder
+            .add_contig(Contig::new(
+                "contig_3".to_string(),
+                "TGGACGATCCCGATC".to_string(),
+            ))
+            .unwrap();
 
         let file = File::open(pileup_file).unwrap();
         let reader = BufReader::new(file);
@@ -173,57 +1174,1152 @@ mod tests {
 
         for res in rdr.records() {
             let record = res.unwrap();
-
-            let n_valid_cov_str = record.get(9).unwrap();
-            let n_valid_cov = n_valid_cov_str.parse().unwrap();
-            let meth_record =
-                parse_to_methylation_record("contig_3".to_string(), n_valid_cov, &record)
-                    .unwrap();
+            let meth_record: MethylationRecord = PileupRecord::from_string_record(
+                &record,
+                &ColumnLayout::default(),
+                ModCodeCase::Exact,
+            )
+            .unwrap()
+            .try_into()
+            .unwrap();
             workspace_builder.add_record(meth_record).unwrap();
         }
 
         let workspace = workspace_builder.build();
 
-        
         let motifs = vec![
             Motif::new("GATC", "a", 1).unwrap(),
             Motif::new("GATC", "m", 3).unwrap(),
             Motif::new("GATC", "21839", 3).unwrap(),
         ];
-        let contig_methylation_pattern = calculate_contig_read_methylation_pattern(workspace, motifs, 1).unwrap();
+        let contig_methylation_pattern = calculate_contig_read_methylation_pattern(
+            workspace,
+            motifs,
+            1,
+            &PatternOptions::default(),
+        )
+        .unwrap();
 
         let expected_median_result = vec![0.625, 1.0];
-        let meth_result: Vec<f64> = contig_methylation_pattern.iter().map(|res| res.median).collect();
+        let meth_result: Vec<f64> = contig_methylation_pattern
+            .iter()
+            .map(|res| res.stats.median)
+            .collect();
+        assert_eq!(meth_result, expected_median_result);
+
+        let expected_mean_read_cov = vec![18.75, 20.0];
+        let meth_result: Vec<f64> = contig_methylation_pattern
+            .iter()
+            .map(|res| res.stats.mean_read_cov)
+            .collect();
+        assert_eq!(meth_result, expected_mean_read_cov);
+
+        let expected_n_motif_obs = vec![4, 1];
+        let meth_result: Vec<u32> = contig_methylation_pattern
+            .iter()
+            .map(|res| res.n_motif_obs)
+            .collect();
+        assert_eq!(meth_result, expected_n_motif_obs);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode_of_fractions_bimodal() {
+        let fractions = vec![0.0, 0.02, 0.05, 0.5, 0.91, 0.95, 0.97, 1.0];
+        assert!((mode_of_fractions(&fractions, 0.1) - 0.95).abs() < 1e-12);
+
+        let fractions = vec![0.01, 0.03, 0.04, 0.6, 0.95, 1.0];
+        assert!((mode_of_fractions(&fractions, 0.1) - 0.05).abs() < 1e-12);
+
+        // Ties go to the lowest bin, and bins are clipped at 1.0.
+        let fractions = vec![0.1, 0.9];
+        assert!((mode_of_fractions(&fractions, 0.25) - 0.125).abs() < 1e-12);
+        let fractions = vec![0.9, 1.0];
+        assert!((mode_of_fractions(&fractions, 0.3) - 0.95).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_strand_convention() -> Result<()> {
+        let build_workspace = || -> Result<GenomeWorkspace> {
+            let mut builder = GenomeWorkspaceBuilder::new();
+            builder.add_contig(Contig::new("contig_1".to_string(), "GATC".to_string()))?;
+            builder.add_record(MethylationRecord::new(
+                "contig_1".to_string(),
+                1,
+                Strand::Negative,
+                ModType::SixMA,
+                MethylationCoverage::new(10, 20)?,
+            ))?;
+            builder.add_record(MethylationRecord::new(
+                "contig_1".to_string(),
+                2,
+                Strand::Positive,
+                ModType::SixMA,
+                MethylationCoverage::new(20, 20)?,
+            ))?;
+            Ok(builder.build())
+        };
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+
+        // With the sense convention the records are on the wrong strands for GATC_a_1.
+        let sense = calculate_contig_read_methylation_pattern(
+            build_workspace()?,
+            motifs.clone(),
+            1,
+            &PatternOptions::default(),
+        )?;
+        assert!(sense.is_empty());
+
+        let antisense_options = PatternOptions {
+            strand_convention: StrandConvention::Antisense,
+            ..Default::default()
+        };
+        let antisense = calculate_contig_read_methylation_pattern(
+            build_workspace()?,
+            motifs,
+            1,
+            &antisense_options,
+        )?;
+        assert_eq!(antisense.len(), 1);
+        assert_eq!(antisense[0].n_motif_obs, 2);
+        assert_eq!(antisense[0].stats.median, 0.75);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mean_read_cov_near_u32_max() -> Result<()> {
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(Contig::new("contig_1".to_string(), "GATCGATC".to_string()))?;
+        for position in [1, 5] {
+            builder.add_record(MethylationRecord::new(
+                "contig_1".to_string(),
+                position,
+                Strand::Positive,
+                ModType::SixMA,
+                MethylationCoverage::new(u32::MAX, u32::MAX)?,
+            ))?;
+        }
+
+        let result = calculate_contig_read_methylation_pattern(
+            builder.build(),
+            vec![Motif::new("GATC", "a", 1)?],
+            1,
+            &PatternOptions::default(),
+        )?;
+        // The coverage is summed in u64, so two u32::MAX sites do not wrap.
+        assert_eq!(result[0].stats.mean_read_cov, u32::MAX as f64);
+        assert_eq!(result[0].stats.median, 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_regions() -> Result<()> {
+        let build_workspace = || -> Result<GenomeWorkspace> {
+            let mut builder = GenomeWorkspaceBuilder::new();
+            builder.add_contig(Contig::new(
+                "contig_1".to_string(),
+                "GATCAAAAGATC".to_string(),
+            ))?;
+            for (position, n_modified) in [(1, 10), (9, 20)] {
+                builder.add_record(MethylationRecord::new(
+                    "contig_1".to_string(),
+                    position,
+                    Strand::Positive,
+                    ModType::SixMA,
+                    MethylationCoverage::new(n_modified, 20)?,
+                ))?;
+            }
+            Ok(builder.build())
+        };
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let gene = |start, end, name: &str| {
+            (
+                "contig_1".to_string(),
+                Interval {
+                    start,
+                    end,
+                    name: name.to_string(),
+                },
+            )
+        };
+        let regions: Regions = vec![gene(0, 4, "geneA"), gene(6, 12, "geneB")]
+            .into_iter()
+            .collect();
+
+        let unrestricted = calculate_contig_read_methylation_pattern(
+            build_workspace()?,
+            motifs.clone(),
+            1,
+            &PatternOptions::default(),
+        )?;
+        assert_eq!(unrestricted[0].stats.median, 0.75);
+
+        // Only the GATC in geneA is used.
+        let options = PatternOptions {
+            regions: Some(Arc::new(vec![gene(0, 4, "geneA")].into_iter().collect())),
+            ..Default::default()
+        };
+        let restricted = calculate_contig_read_methylation_pattern(
+            build_workspace()?,
+            motifs.clone(),
+            1,
+            &options,
+        )?;
+        assert_eq!(restricted.len(), 1);
+        assert_eq!(restricted[0].stats.median, 0.5);
+        assert_eq!(restricted[0].n_motif_obs, 1);
+        assert_eq!(restricted[0].motif_occurences_total, 2);
+        assert_eq!(restricted[0].feature, None);
+
+        let options = PatternOptions {
+            regions: Some(Arc::new(regions)),
+            per_feature: true,
+            ..Default::default()
+        };
+        let per_feature =
+            calculate_contig_read_methylation_pattern(build_workspace()?, motifs, 1, &options)?;
+        let features: Vec<(Option<&str>, f64)> = per_feature
+            .iter()
+            .map(|r| (r.feature.as_deref(), r.stats.median))
+            .collect();
+        assert_eq!(features, vec![(Some("geneA"), 0.5), (Some("geneB"), 1.0)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage_bins() -> Result<()> {
+        let bins: CoverageBins = "0,10,30,inf".parse()?;
+        assert_eq!(bins.len(), 3);
+        assert_eq!(bins.bin_of(0), Some(0));
+        assert_eq!(bins.bin_of(10), Some(1));
+        assert_eq!(bins.bin_of(5000), Some(2));
+        assert_eq!(bins.label(2), "30-inf");
+
+        let bounded: CoverageBins = "5,10".parse()?;
+        assert_eq!(bounded.bin_of(4), None);
+        assert_eq!(bounded.bin_of(10), None);
+
+        assert!("10".parse::<CoverageBins>().is_err());
+        assert!("10,5".parse::<CoverageBins>().is_err());
+        assert!("0,x".parse::<CoverageBins>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stratify_by_coverage_bins() -> Result<()> {
+        //                                          0123456789012345
+        let mut contig = Contig::new("contig_1".to_string(), "GATCAAAAGATCAAAA".to_string());
+        for (position, strand, n_modified, n_valid_cov) in [
+            (1, Strand::Positive, 4, 5),
+            (2, Strand::Negative, 2, 8),
+            (9, Strand::Positive, 40, 40),
+            (10, Strand::Negative, 30, 50),
+        ] {
+            contig.add_methylation(
+                position,
+                strand,
+                ModType::SixMA,
+                MethylationCoverage::new(n_modified, n_valid_cov)?,
+            )?;
+        }
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let options = PatternOptions {
+            coverage_bins: Some("0,10,30,inf".parse()?),
+            ..Default::default()
+        };
+
+        let results = contig_methylation_pattern(&contig, &motifs, &options);
+        let rows: Vec<(Option<&str>, u32, f64)> = results
+            .iter()
+            .map(|r| (r.coverage_bin.as_deref(), r.n_motif_obs, r.stats.median))
+            .collect();
+        // The 10-30 bin has no sites and gives no row.
         assert_eq!(
-            meth_result,
-            expected_median_result
+            rows,
+            vec![(Some("0-10"), 2, 0.525), (Some("30-inf"), 2, 0.8)]
         );
+        assert!(results.iter().all(|r| r.motif_occurences_total == 4));
 
-        let expected_mean_read_cov = vec![18.75, 20.0];
-        let meth_result: Vec<f64> = contig_methylation_pattern.iter().map(|res| res.mean_read_cov).collect();
+        Ok(())
+    }
+
+    #[test]
+    fn test_heterogeneity_flags_methylation_boundary() -> Result<()> {
+        // GATC every 10 bp over 200 bp.
+        let sequence = "GATCAAAAAA".repeat(20);
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let options = PatternOptions {
+            heterogeneity_window: Some(50),
+            ..Default::default()
+        };
+
+        let score = |fraction_of: &dyn Fn(usize) -> u32| -> Result<Option<f64>> {
+            let mut contig = Contig::new("contig_1".to_string(), sequence.clone());
+            for position in (1..200).step_by(10) {
+                contig.add_methylation(
+                    position,
+                    Strand::Positive,
+                    ModType::SixMA,
+                    MethylationCoverage::new(fraction_of(position), 20)?,
+                )?;
+            }
+            Ok(contig_methylation_pattern(&contig, &motifs, &options)[0].heterogeneity)
+        };
+
+        // Methylated first half, unmethylated second half: window medians 1, 1, 0, 0.
+        let boundary = score(&|position| if position < 100 { 20 } else { 0 })?;
+        assert_eq!(boundary, Some(0.25));
+
+        let uniform = score(&|position| if position % 20 == 1 { 18 } else { 17 })?;
+        assert!(uniform.unwrap() < 1e-3);
+
+        let single_window = PatternOptions {
+            heterogeneity_window: Some(1000),
+            ..Default::default()
+        };
+        let mut contig = Contig::new("contig_1".to_string(), sequence.clone());
+        contig.add_methylation(
+            1,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(20, 20)?,
+        )?;
         assert_eq!(
-            meth_result,
-            expected_mean_read_cov
+            contig_methylation_pattern(&contig, &motifs, &single_window)[0].heterogeneity,
+            None
         );
 
-        let expected_n_motif_obs = vec![4, 1];
-        let meth_result: Vec<u32> = contig_methylation_pattern.iter().map(|res| res.n_motif_obs).collect();
-        assert_eq!(meth_result, expected_n_motif_obs);
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_timings_list_each_motif() -> Result<()> {
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(Contig::new(
+            "contig_1".to_string(),
+            "GATCCCAGGAAAAGATC".to_string(),
+        ))?;
+        builder.add_record(MethylationRecord::new(
+            "contig_1".to_string(),
+            1,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(10, 20)?,
+        ))?;
+        let motifs = vec![Motif::new("GATC", "a", 1)?, Motif::new("CCWGG", "m", 1)?];
+        let timings = Arc::new(MotifTimings::new(&motifs));
+        let options = PatternOptions {
+            timings: Some(Arc::clone(&timings)),
+            ..Default::default()
+        };
+
+        calculate_contig_read_methylation_pattern(builder.build(), motifs.clone(), 1, &options)?;
+
+        let summary = timings.summary();
+        assert_eq!(summary.len(), 2);
+        for motif in &motifs {
+            assert!(summary.iter().any(|(m, _)| m == motif));
+        }
+        assert!(summary.windows(2).all(|w| w[0].1 >= w[1].1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_automaton_matches_regex() -> Result<()> {
+        let motifs: Vec<Motif> = [
+            "GATC_a_1",
+            "GATC_m_3",
+            "RGATCY_a_2",
+            "CCWGG_m_1",
+            "GAAC_a_1+2",
+            "GCAGC_m_1+4",
+            "TTCGAA_a_5",
+            "GANTC_a_1",
+            "GATC_a_1",
+        ]
+        .iter()
+        .map(|motif| motif.parse())
+        .collect::<Result<_>>()?;
+        let automaton = MotifAutomaton::new(&motifs)?;
+        // GANTC has an N, which the regex matches to any character.
+        assert_eq!(automaton.n_motifs(), 8);
+        assert_eq!(expand_motif(&motifs[2]).unwrap().len(), 4);
+
+        let mut state: u64 = 7;
+        let mut random_sequence = |len: usize| -> String {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    b"ACGTacgtN"[(state >> 33) as usize % 9] as char
+                })
+                .collect()
+        };
+        let mut sequences: Vec<String> = (0..50).map(|_| random_sequence(300)).collect();
+        sequences.push("GATCCWGGATCCAGGTTCGAAGAACGATCTGCAGCAGCTGCTGCTGC".to_string());
+        sequences.push("GAT".to_string());
+
+        for sequence in sequences {
+            let mut contig = Contig::new("contig_1".to_string(), sequence);
+            for (i, motif) in motifs.iter().enumerate() {
+                let automaton_indices = automaton.scan(&contig.sequence)[i].clone();
+                if i != 7 {
+                    assert_eq!(
+                        automaton_indices,
+                        Some(scan_motif(&contig, &MotifSearcher::new(motif)))
+                    );
+                }
+            }
+
+            for position in (0..contig.sequence.len()).step_by(3) {
+                contig.add_methylation(
+                    position,
+                    Strand::Positive,
+                    ModType::SixMA,
+                    MethylationCoverage::new(position as u32 % 7, 10)?,
+                )?;
+            }
+            let options = PatternOptions {
+                motif_automaton: Some(Arc::new(MotifAutomaton::new(&motifs)?)),
+                ..Default::default()
+            };
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    contig_methylation_pattern(&contig, &motifs, &options)
+                ),
+                format!(
+                    "{:?}",
+                    contig_methylation_pattern(&contig, &motifs, &PatternOptions::default())
+                )
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_sequences_share_scanning() -> Result<()> {
+        let mut assembly: AHashMap<String, Contig> = AHashMap::new();
+        for (id, sequence) in [
+            ("contig_1", "GATCAAAAGATC"),
+            ("contig_2", "GATCAAAAGATC"),
+            ("contig_3", "GATCAAAAAAAA"),
+        ] {
+            assembly.insert(
+                id.to_string(),
+                Contig::new(id.to_string(), sequence.to_string()),
+            );
+        }
+        let duplicates = Arc::new(DuplicateSequences::new(&assembly));
+        assert_eq!(duplicates.n_duplicates(), 1);
+
+        let motif = Motif::new("GATC", "a", 1)?;
+        let options = PatternOptions {
+            duplicate_sequences: Some(Arc::clone(&duplicates)),
+            ..Default::default()
+        };
+
+        // The copies share the scan but keep their own methylation.
+        let mut results = Vec::new();
+        for (id, n_modified) in [("contig_1", 20), ("contig_2", 0), ("contig_3", 20)] {
+            let mut contig = assembly[id].clone();
+            contig.add_methylation(
+                1,
+                Strand::Positive,
+                ModType::SixMA,
+                MethylationCoverage::new(n_modified, 20)?,
+            )?;
+            results.extend(contig_methylation_pattern(
+                &contig,
+                std::slice::from_ref(&motif),
+                &options,
+            ));
+        }
+        assert_eq!(duplicates.indices.lock().unwrap().len(), 1);
+
+        let rows: Vec<(f64, u32)> = results
+            .iter()
+            .map(|r| (r.stats.median, r.motif_occurences_total))
+            .collect();
+        assert_eq!(rows, vec![(1.0, 4), (0.0, 4), (1.0, 2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_contig_shorter_than_motif() -> Result<()> {
+        let mut contig = Contig::new("contig_1".to_string(), "GA".to_string());
+        contig.add_methylation(
+            1,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(15, 20)?,
+        )?;
+        let motifs = vec![Motif::new("GATC", "a", 1)?, Motif::new("GA", "a", 1)?];
+        let options = PatternOptions {
+            motif_score: true,
+            ..Default::default()
+        };
+
+        // GATC is longer than the contig and gives no row, GA still does.
+        let results = contig_methylation_pattern(&contig, &motifs, &options);
+        let motifs_found: Vec<String> = results
+            .iter()
+            .map(|r| r.motif.sequence_to_string())
+            .collect();
+        assert_eq!(motifs_found, vec!["GA"]);
+        assert_eq!(
+            collect_motif_sites(&contig, &motifs[..1], &options, Some(2)).len(),
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_contig_methylation_pattern() -> Result<()> {
+        let mut builder = GenomeWorkspaceBuilder::new();
+        for (id, sequence) in [("contig_1", "GATCAAAAGATC"), ("contig_2", "TTGATCCCGG")] {
+            builder.add_contig(Contig::new(id.to_string(), sequence.to_string()))?;
+        }
+        for (contig, position, n_modified) in
+            [("contig_1", 1, 10), ("contig_1", 9, 20), ("contig_2", 3, 5)]
+        {
+            builder.add_record(MethylationRecord::new(
+                contig.to_string(),
+                position,
+                Strand::Positive,
+                ModType::SixMA,
+                MethylationCoverage::new(n_modified, 20)?,
+            ))?;
+        }
+        let workspace = builder.build();
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let options = PatternOptions::default();
+
+        let single = workspace.contig_methylation_pattern("contig_2", &motifs, &options)?;
+        assert!(workspace
+            .contig_methylation_pattern("contig_3", &motifs, &options)
+            .is_err());
+
+        let full = calculate_contig_read_methylation_pattern(workspace, motifs, 1, &options)?;
+        let full_contig_2: Vec<(f64, u32, u32)> = full
+            .iter()
+            .filter(|r| r.contig == "contig_2")
+            .map(|r| (r.stats.median, r.n_motif_obs, r.motif_occurences_total))
+            .collect();
+        let single: Vec<(f64, u32, u32)> = single
+            .iter()
+            .map(|r| (r.stats.median, r.n_motif_obs, r.motif_occurences_total))
+            .collect();
+        assert_eq!(single, vec![(0.25, 1, 2)]);
+        assert_eq!(single, full_contig_2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_sites() -> Result<()> {
+        let coverages = [
+            MethylationCoverage::new(15, 20)?,
+            MethylationCoverage::new(5, 20)?,
+            MethylationCoverage::new(0, 3)?,
+        ];
+        assert_eq!(pack_sites(&coverages), "15/20;5/20;0/3");
+        assert_eq!(pack_sites(&[]), "");
+
+        let many = vec![MethylationCoverage::new(1, 2)?; MAX_PACKED_SITES + 1];
+        let packed = pack_sites(&many);
+        assert_eq!(packed.split(';').count(), MAX_PACKED_SITES + 1);
+        assert!(packed.ends_with("1/2;..."));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_occurrence_density() -> Result<()> {
+        assert_eq!(occurrence_density(4, 2000), 2.0);
+        assert_eq!(occurrence_density(3, 500), 6.0);
+
+        // GATC occurs twice in the 15 bp contig, once per strand each.
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(Contig::new(
+            "contig_1".to_string(),
+            "TGGACGATCCCGATC".to_string(),
+        ))?;
+        builder.add_record(MethylationRecord::new(
+            "contig_1".to_string(),
+            6,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(15, 20)?,
+        ))?;
+        let workspace = builder.build();
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let options = PatternOptions {
+            occurrence_density: true,
+            ..Default::default()
+        };
+        let results = calculate_contig_read_methylation_pattern(workspace, motifs, 1, &options)?;
+
+        assert_eq!(results[0].motif_occurences_total, 4);
+        assert!((results[0].occurrence_density.unwrap() - 4.0 / 15.0 * 1000.0).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_circular_contig_junction_sites() -> Result<()> {
+        // GATC spans the junction as GA at 6-7 and TC at 0-1. The modified A is at 7
+        // on the forward strand and at 0, the T, on the reverse strand.
+        let mut contig = Contig::new("plasmid".to_string(), "TCAAAAGA".to_string());
+        contig.circular = true;
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(contig)?;
+        for (position, strand) in [(7, Strand::Positive), (0, Strand::Negative)] {
+            builder.add_record(MethylationRecord::new(
+                "plasmid".to_string(),
+                position,
+                strand,
+                ModType::SixMA,
+                MethylationCoverage::new(15, 20)?,
+            ))?;
+        }
+        let workspace = builder.build();
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+        let results = calculate_contig_read_methylation_pattern(
+            workspace,
+            motifs,
+            1,
+            &PatternOptions::default(),
+        )?;
+
+        assert_eq!(results[0].motif_occurences_total, 2);
+        assert_eq!(results[0].n_motif_obs, 2);
+        assert_eq!(results[0].stats.median, 0.75);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_occurrences_are_counted() -> Result<()> {
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(Contig::new("contig_1".to_string(), "GCGCGC".to_string()))?;
+        for position in [1, 3] {
+            builder.add_record(MethylationRecord::new(
+                "contig_1".to_string(),
+                position,
+                Strand::Positive,
+                ModType::FiveMC,
+                MethylationCoverage::new(10, 20)?,
+            ))?;
+        }
+        let workspace = builder.build();
+        let motifs = vec![Motif::new("GCGC", "m", 1)?];
+        let results = calculate_contig_read_methylation_pattern(
+            workspace,
+            motifs,
+            1,
+            &PatternOptions::default(),
+        )?;
+
+        assert_eq!(results[0].motif_occurences_total, 4);
+        assert_eq!(results[0].n_motif_obs, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_provenance_hash() -> Result<()> {
+        let hash_with_pileup = |n_modified: [u32; 2]| -> Result<String> {
+            let mut builder = GenomeWorkspaceBuilder::new();
+            builder.add_contig(Contig::new("contig_1".to_string(), "GATCGATC".to_string()))?;
+            for (position, n_modified) in [1, 5].into_iter().zip(n_modified) {
+                builder.add_record(MethylationRecord::new(
+                    "contig_1".to_string(),
+                    position,
+                    Strand::Positive,
+                    ModType::SixMA,
+                    MethylationCoverage::new(n_modified, 20)?,
+                ))?;
+            }
+            let options = PatternOptions {
+                provenance_parameters: Some("min_valid_read_coverage=3".to_string()),
+                ..Default::default()
+            };
+            let results = calculate_contig_read_methylation_pattern(
+                builder.build(),
+                vec![Motif::new("GATC", "a", 1)?],
+                1,
+                &options,
+            )?;
+            Ok(results[0].provenance_hash.clone().unwrap())
+        };
+
+        assert_eq!(hash_with_pileup([15, 5])?, hash_with_pileup([15, 5])?);
+        assert_ne!(hash_with_pileup([15, 5])?, hash_with_pileup([15, 6])?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_mod_positions_are_aggregated() -> Result<()> {
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(Contig::new(
+            "contig_1".to_string(),
+            "TGAACGTTCA".to_string(),
+        ))?;
+        // Both As of GAAC at 2 and 3 and of its reverse complement GTTC at 6 and 7,
+        // where 7 has no data.
+        for (position, strand, n_modified) in [
+            (2, Strand::Positive, 20),
+            (3, Strand::Positive, 10),
+            (6, Strand::Negative, 0),
+        ] {
+            builder.add_record(MethylationRecord::new(
+                "contig_1".to_string(),
+                position,
+                strand,
+                ModType::SixMA,
+                MethylationCoverage::new(n_modified, 20)?,
+            ))?;
+        }
+        let results = calculate_contig_read_methylation_pattern(
+            builder.build(),
+            vec!["GAAC_a_1+2".parse()?],
+            1,
+            &PatternOptions::default(),
+        )?;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].motif.to_string(), "GAAC_a_1+2");
+        assert_eq!(results[0].stats.median, 0.5);
+        assert_eq!(results[0].n_motif_obs, 3);
+        assert_eq!(results[0].motif_occurences_total, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_mod_positions_are_counted_once() -> Result<()> {
+        // Both matches of GCAGC modify the C at 4, which is one site.
+        let mut contig = Contig::new("contig_1".to_string(), "GCAGCAGC".to_string());
+        for (position, n_modified) in [(1, 20), (4, 0), (7, 20)] {
+            contig.add_methylation(
+                position,
+                Strand::Positive,
+                ModType::FiveMC,
+                MethylationCoverage::new(n_modified, 20)?,
+            )?;
+        }
+        let motif: Motif = "GCAGC_m_1+4".parse()?;
+        for options in [
+            PatternOptions::default(),
+            PatternOptions {
+                motif_automaton: Some(Arc::new(MotifAutomaton::new(std::slice::from_ref(&motif))?)),
+                ..Default::default()
+            },
+        ] {
+            let results =
+                contig_methylation_pattern(&contig, std::slice::from_ref(&motif), &options);
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].stats.median, 1.0);
+            assert_eq!(results[0].n_motif_obs, 3);
+            assert_eq!(results[0].motif_occurences_total, 3);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_score() -> Result<()> {
+        let mut builder = GenomeWorkspaceBuilder::new();
+        builder.add_contig(Contig::new(
+            "contig_1".to_string(),
+            "GATCAAAAGATC".to_string(),
+        ))?;
+        // The GATC sites are methylated, the A's in between are not (A at 4 is
+        // half methylated). The 5mC record is not part of the 6mA background.
+        for (position, strand, mod_type, n_modified) in [
+            (1, Strand::Positive, ModType::SixMA, 18),
+            (2, Strand::Negative, ModType::SixMA, 20),
+            (9, Strand::Positive, ModType::SixMA, 20),
+            (4, Strand::Positive, ModType::SixMA, 10),
+            (5, Strand::Positive, ModType::SixMA, 0),
+            (3, Strand::Positive, ModType::FiveMC, 20),
+        ] {
+            builder.add_record(MethylationRecord::new(
+                "contig_1".to_string(),
+                position,
+                strand,
+                mod_type,
+                MethylationCoverage::new(n_modified, 20)?,
+            ))?;
+        }
+        let options = PatternOptions {
+            motif_score: true,
+            ..Default::default()
+        };
+
+        let results = calculate_contig_read_methylation_pattern(
+            builder.build(),
+            vec![Motif::new("GATC", "a", 1)?],
+            1,
+            &options,
+        )?;
+        // mean(0.9, 1.0, 1.0) - mean(0.5, 0.0)
+        let score = results[0].motif_vs_background.unwrap();
+        assert!((score - (29.0 / 30.0 - 0.25)).abs() < 1e-12);
 
         Ok(())
     }
 
+    #[test]
+    fn test_sequence_context() {
+        //              0123456789
+        let sequence = "CCAGATCTCC";
+        assert_eq!(
+            sequence_context(sequence, 4, 3, Strand::Positive),
+            "CAGATCT"
+        );
+        assert_eq!(
+            sequence_context(sequence, 4, 3, Strand::Negative),
+            "AGATCTG"
+        );
+
+        // Sites near the contig ends are padded.
+        assert_eq!(
+            sequence_context(sequence, 1, 3, Strand::Positive),
+            "--CCAGA"
+        );
+        assert_eq!(
+            sequence_context(sequence, 8, 3, Strand::Positive),
+            "TCTCC--"
+        );
+        assert_eq!(sequence_context(sequence, 0, 0, Strand::Positive), "C");
+
+        // Soft-masked bases are complemented too and keep their case.
+        assert_eq!(
+            sequence_context("ccaGATCtcc", 4, 3, Strand::Negative),
+            "aGATCtg"
+        );
+    }
+
+    #[test]
+    fn test_collect_motif_sites() -> Result<()> {
+        let mut contig = Contig::new("contig_1".to_string(), "CCAGATCTCC".to_string());
+        contig.add_methylation(
+            4,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(15, 20)?,
+        )?;
+        contig.add_methylation(
+            5,
+            Strand::Negative,
+            ModType::SixMA,
+            MethylationCoverage::new(5, 20)?,
+        )?;
+        let motifs = vec![Motif::new("GATC", "a", 1)?];
+
+        let sites = collect_motif_sites(&contig, &motifs, &PatternOptions::default(), Some(2));
+        let sites: Vec<(usize, Strand, f64, Option<&str>)> = sites
+            .iter()
+            .map(|site| {
+                (
+                    site.position,
+                    site.strand,
+                    site.methylation.fraction_modified(),
+                    site.context.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            vec![
+                (4, Strand::Positive, 0.75, Some("AGATC")),
+                (5, Strand::Negative, 0.25, Some("AGATC")),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_shared_methylation_records() -> Result<()> {
+        let mut contig = Contig::new("contig_1".to_string(), "CCAGATCTCC".to_string());
+        // The A at position 4 is the 6mA of both GATC_a_1 and RGATCY_a_2.
+        contig.add_methylation(
+            4,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(10, 20)?,
+        )?;
+        // ...and the A at position 5 is the negative strand 6mA of both.
+        contig.add_methylation(
+            5,
+            Strand::Negative,
+            ModType::SixMA,
+            MethylationCoverage::new(10, 20)?,
+        )?;
+
+        let gatc = Motif::new("GATC", "a", 1)?;
+        let rgatcy = Motif::new("RGATCY", "a", 2)?;
+
+        let options = PatternOptions::default();
+        assert_eq!(
+            count_shared_methylation_records(&contig, std::slice::from_ref(&gatc), &options),
+            0
+        );
+        // The same count with the regexes compiled upfront.
+        let motifs = [gatc, rgatcy];
+        let options = PatternOptions {
+            motif_searchers: motifs.iter().map(MotifSearcher::new).collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_shared_methylation_records(&contig, &motifs, &options),
+            2
+        );
+
+        // With the antisense convention the same bases are reported on the opposite
+        // strand, so the records of the sense convention are not attributed.
+        let mut antisense_contig = Contig::new("contig_1".to_string(), "CCAGATCTCC".to_string());
+        antisense_contig.add_methylation(
+            4,
+            Strand::Negative,
+            ModType::SixMA,
+            MethylationCoverage::new(10, 20)?,
+        )?;
+        antisense_contig.add_methylation(
+            5,
+            Strand::Positive,
+            ModType::SixMA,
+            MethylationCoverage::new(10, 20)?,
+        )?;
+        let antisense = PatternOptions {
+            strand_convention: StrandConvention::Antisense,
+            ..Default::default()
+        };
+        assert_eq!(
+            count_shared_methylation_records(&antisense_contig, &motifs, &antisense),
+            2
+        );
+        assert_eq!(
+            count_shared_methylation_records(&antisense_contig, &motifs, &options),
+            0
+        );
+        assert_eq!(
+            count_shared_methylation_records(&contig, &motifs, &antisense),
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dcm_motif_positions() -> Result<()> {
+        //               0         1
+        //               01234567890123
+        let sequence = "ACCAGGTTCCTGGA";
+        let mut assembly = AHashMap::new();
+        assembly.insert(
+            "contig_1".to_string(),
+            Contig::new("contig_1".to_string(), sequence.to_string()),
+        );
+        let dcm = Motif::new("CCWGG", "m", 1)?;
+
+        // The second C of CCAGG (1-5) and CCTGG (8-12) on the forward strand, and the
+        // second C of the reverse strand reading, opposite the first G.
+        assert_eq!(find_motif_indices_in_contig(sequence, &dcm), vec![2, 9]);
+        assert_eq!(
+            find_motif_indices_in_contig(sequence, &dcm.reverse_complement()),
+            vec![4, 11]
+        );
+
+        // The Dcm sites are fully methylated, the other C's and G's are not.
+        let mut pileup = String::new();
+        for (position, strand, n_modified) in [
+            (1, '+', 0),
+            (2, '+', 20),
+            (4, '-', 20),
+            (5, '-', 0),
+            (8, '+', 0),
+            (9, '+', 20),
+            (11, '-', 20),
+            (12, '-', 0),
+        ] {
+            pileup.push_str(&format!(
+                "contig_1\t{}\t{}\tm\t20\t{}\t0\t1\t255,0,0\t20\t0.00\t{}\t0\t0\t0\t0\t0\t0\n",
+                position,
+                position + 1,
+                strand,
+                n_modified
+            ));
+        }
+        let workspace = BatchLoader::new(std::io::Cursor::new(pileup.as_bytes()), &assembly, 1, 3)
+            .next()
+            .unwrap()?;
+
+        let results = calculate_contig_read_methylation_pattern(
+            workspace,
+            vec![dcm],
+            1,
+            &PatternOptions::default(),
+        )?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].stats.median, 1.0);
+        assert_eq!(results[0].stats.min, 1.0);
+        assert_eq!(results[0].n_motif_obs, 4);
+        assert_eq!(results[0].motif_occurences_total, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_uncovered_contigs() -> Result<()> {
+        let mut assembly = AHashMap::new();
+        for (id, sequence) in [
+            ("contig_1", "GATC"),
+            ("contig_2", "GATCGATC"),
+            ("contig_3", "GG"),
+        ] {
+            assembly.insert(
+                id.to_string(),
+                Contig::new(id.to_string(), sequence.to_string()),
+            );
+        }
+
+        // Only contig_2 has pileup records.
+        let pileup = "contig_2\t1\t2\ta\t20\t+\t1\t2\t255,0,0\t20\t50.00\t10\t10\t0\t0\t0\t0\t0\n";
+        let mut covered = AHashSet::new();
+        for workspace in BatchLoader::new(std::io::Cursor::new(pileup.as_bytes()), &assembly, 10, 3)
+        {
+            covered.extend(workspace?.contigs().map(|contig| contig.id.clone()));
+        }
+
+        assert_eq!(
+            find_uncovered_contigs(&assembly, &covered),
+            vec![("contig_1", 4), ("contig_3", 2)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_motifs_by_genome_occurrences() {
+        let mut contigs = AHashMap::new();
+        contigs.insert(
+            "contig_1".to_string(),
+            Contig::new("contig_1".to_string(), "GATCCCGATCAAGTT".to_string()),
+        );
+        contigs.insert(
+            "contig_2".to_string(),
+            Contig::new("contig_2".to_string(), "TTGATCTT".to_string()),
+        );
+
+        let gatc = Motif::new("GATC", "a", 1).unwrap();
+        let aagtt = Motif::new("AAGTT", "a", 1).unwrap();
+
+        // GATC is palindromic and found 3 times on each strand, AAGTT once.
+        assert_eq!(
+            count_genome_occurrences(&contigs, &[gatc.clone(), aagtt.clone()]),
+            vec![6, 1]
+        );
+
+        let (kept, dropped) =
+            filter_motifs_by_genome_occurrences(&contigs, vec![gatc.clone(), aagtt.clone()], 2);
+        assert_eq!(kept, vec![gatc]);
+        assert_eq!(dropped, vec![(aagtt, 1)]);
+    }
+
+    #[test]
+    fn test_find_motif_conflicts() {
+        let motifs = create_motifs(vec![
+            "GATC_a_1".to_string(),
+            "GATC_m_3".to_string(),
+            "RGATCY_a_2".to_string(),
+        ])
+        .unwrap();
+        assert!(find_motif_conflicts(&motifs).is_empty());
+
+        let motifs = create_motifs(vec![
+            "GATC_a_1".to_string(),
+            "GATC_m_3".to_string(),
+            "GATC_21839_3".to_string(),
+            "GATC_a_1".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            find_motif_conflicts(&motifs),
+            vec![
+                "Motif GATC_a_1 is supplied more than once.",
+                "Motif GATC at mod_position 3 is claimed by multiple mod types: 5mC (m), 4mC (21839).",
+            ]
+        );
+    }
+
     #[test]
     fn test_create_motifs_success() {
         let motifs_args = vec!["GATC_a_1".to_string()];
         let result = create_motifs(motifs_args);
-        assert!(result.is_ok(), "Expected Ok, but got err: {:?}", result.err());
+        assert!(
+            result.is_ok(),
+            "Expected Ok, but got err: {:?}",
+            result.err()
+        );
     }
     #[test]
     fn test_create_motifs_failure() {
         let motifs_args = vec!["GATC_a_3".to_string()];
         let result = create_motifs(motifs_args);
-        assert!(result.is_err(), "Expected Err, but got Ok: {:?}", result.ok());
+        assert!(
+            result.is_err(),
+            "Expected Err, but got Ok: {:?}",
+            result.ok()
+        );
+    }
+
+    #[test]
+    fn test_create_motifs_swapped_fields() {
+        let error = create_motifs(vec!["a_GATC_1".to_string()]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid motif 'a_GATC_1': 'a' is a mod type and 'GATC' a sequence. Did you swap the fields? Expected 'GATC_a_1'."
+        );
+
+        let error = create_motifs(vec!["GATC_CCWGG_1".to_string()]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("the mod_type 'CCWGG' looks like a nucleotide sequence"));
+
+        // Mod codes that are also IUPAC bases are still mod types in the mod_type
+        // field.
+        assert!(create_motifs(vec!["GATC_a_1".to_string(), "CCWGG_m_1".to_string()]).is_ok());
+        let error = create_motifs(vec!["GATC_A_1".to_string()]).unwrap_err();
+        assert!(!format!("{:#}", error).contains("nucleotide sequence"));
+    }
+
+    #[test]
+    fn test_partition_motifs() {
+        let motifs_args = vec![
+            "GATC_a_1".to_string(),
+            "GATC_a_3".to_string(),
+            "GATC".to_string(),
+            "RGATCY_a_2".to_string(),
+        ];
+        let (valid, invalid) = partition_motifs(motifs_args);
+
+        assert_eq!(
+            valid,
+            vec![
+                Motif::new("GATC", "a", 1).unwrap(),
+                Motif::new("RGATCY", "a", 2).unwrap()
+            ]
+        );
+        let invalid: Vec<&str> = invalid.iter().map(|(motif, _)| motif.as_str()).collect();
+        assert_eq!(invalid, vec!["GATC_a_3", "GATC"]);
     }
-    
 }
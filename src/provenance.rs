@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use methylome::Motif;
+use std::{fs::File, io::Read, path::Path};
+
+use crate::data::{contig::Contig, methylation::MethylationCoverage};
+
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// 128-bit FNV-1a hash. Unlike `DefaultHasher` and ahash, the output is the same
+/// across runs, platforms and Rust versions, so it can be stored and compared later.
+/// It is not a cryptographic hash.
+struct StableHasher(u128);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u128;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Writes a length-prefixed field, so adjacent fields cannot run into each other.
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    fn finish_hex(&self) -> String {
+        format!("{:032x}", self.0)
+    }
+}
+
+/// Hash of the inputs of a result row: the contig sequence (and whether it is
+/// circular), the motif, the position and counts of every site in the row, and the
+/// run `parameters`. Rows with identical inputs get identical hashes, e.g. to skip
+/// recomputation in a pipeline.
+///
+/// The contig id is not part of the hash. Values computed from other rows, such as the
+/// normalization or the background of `--motif-score`, are only covered by their
+/// parameters. Input files are covered by their `file_hash` in `parameters`.
+pub fn provenance_hash(
+    contig: &Contig,
+    motif: &Motif,
+    sites: &[(usize, MethylationCoverage)],
+    parameters: &str,
+) -> String {
+    let mut hasher = StableHasher::new();
+    hasher.write_field(contig.sequence.as_bytes());
+    hasher.write(&[contig.circular as u8]);
+    hasher.write_field(motif.to_string().as_bytes());
+    hasher.write(&(sites.len() as u64).to_le_bytes());
+    for (position, cov) in sites {
+        hasher.write(&(*position as u64).to_le_bytes());
+        hasher.write(&cov.get_n_modified().to_le_bytes());
+        hasher.write(&cov.get_n_valid_cov().to_le_bytes());
+    }
+    hasher.write_field(parameters.as_bytes());
+    hasher.finish_hex()
+}
+
+/// Hash of the content of the file at `path`, so the provenance hash changes when an
+/// input file changes, and not when it is moved.
+pub fn file_hash<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file at: {:?}", path))?;
+
+    let mut hasher = StableHasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n_read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read file at: {:?}", path))?;
+        if n_read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..n_read]);
+    }
+    Ok(hasher.finish_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_stable_hasher() {
+        // Test vectors of 128-bit FNV-1a.
+        assert_eq!(
+            StableHasher::new().finish_hex(),
+            format!("{:032x}", FNV_OFFSET_BASIS)
+        );
+        let mut hasher = StableHasher::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish_hex(), "d228cb696f1a8caf78912b704e4a8964");
+    }
+
+    #[test]
+    fn test_provenance_hash() -> Result<()> {
+        let contig = Contig::new("contig_1".to_string(), "GATCGATC".to_string());
+        let motif = Motif::new("GATC", "a", 1)?;
+        let sites = vec![(1, MethylationCoverage::new(15, 20)?)];
+        let hash = provenance_hash(&contig, &motif, &sites, "min_cov=5");
+
+        assert_eq!(hash.len(), 32);
+        assert_eq!(hash, provenance_hash(&contig, &motif, &sites, "min_cov=5"));
+
+        let renamed = Contig::new("contig_2".to_string(), "GATCGATC".to_string());
+        assert_eq!(hash, provenance_hash(&renamed, &motif, &sites, "min_cov=5"));
+
+        let changed_sites = vec![(1, MethylationCoverage::new(14, 20)?)];
+        for other in [
+            provenance_hash(&contig, &motif, &changed_sites, "min_cov=5"),
+            provenance_hash(&contig, &motif, &sites, "min_cov=6"),
+            provenance_hash(&contig, &Motif::new("GATC", "m", 3)?, &sites, "min_cov=5"),
+            provenance_hash(
+                &Contig::new("contig_1".to_string(), "GATCGATT".to_string()),
+                &motif,
+                &sites,
+                "min_cov=5",
+            ),
+        ] {
+            assert_ne!(hash, other);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_hash() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let [a, b, c] = ["a.bed", "b.bed", "c.bed"].map(|name| dir.path().join(name));
+        std::fs::write(&a, "contig_1\t0\t100\n")?;
+        std::fs::write(&b, "contig_1\t0\t100\n")?;
+        std::fs::write(&c, "contig_1\t0\t101\n")?;
+
+        // The same content at another path gives the same hash.
+        assert_eq!(file_hash(&a)?, file_hash(&b)?);
+        assert_ne!(file_hash(&a)?, file_hash(&c)?);
+        assert!(file_hash(dir.path().join("missing.bed")).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use ahash::AHashMap;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// A half-open, 0-based interval `[start, end)` from a BED file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interval {
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+}
+
+impl Interval {
+    pub fn contains(&self, position: usize) -> bool {
+        self.start <= position && position < self.end
+    }
+
+    /// Returns the positions of the sorted `positions` inside the interval.
+    pub fn slice<'a>(&self, positions: &'a [usize]) -> &'a [usize] {
+        let from = positions.partition_point(|p| *p < self.start);
+        let to = positions.partition_point(|p| *p < self.end);
+        &positions[from..to]
+    }
+}
+
+/// Static interval tree over the intervals of a single contig.
+///
+/// Intervals are sorted by start, and `max_end[i]` holds the largest end of the
+/// first `i + 1` intervals. A point query binary searches the last interval starting
+/// at or before the position and walks left only while an interval can still reach
+/// the position.
+#[derive(Debug, Clone)]
+pub struct IntervalIndex {
+    intervals: Vec<Interval>,
+    max_end: Vec<usize>,
+}
+
+impl IntervalIndex {
+    pub fn new(mut intervals: Vec<Interval>) -> Self {
+        intervals.sort_by(|a, b| a.start.cmp(&b.start).then(a.end.cmp(&b.end)));
+
+        let mut max_end = Vec::with_capacity(intervals.len());
+        let mut running_max = 0;
+        for interval in &intervals {
+            running_max = running_max.max(interval.end);
+            max_end.push(running_max);
+        }
+
+        Self { intervals, max_end }
+    }
+
+    /// Returns true if any interval contains `position`.
+    pub fn contains(&self, position: usize) -> bool {
+        let n_candidates = self.intervals.partition_point(|i| i.start <= position);
+
+        for i in (0..n_candidates).rev() {
+            if self.max_end[i] <= position {
+                return false;
+            }
+            if self.intervals[i].contains(position) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The intervals sorted by start.
+    pub fn intervals(&self) -> &[Interval] {
+        &self.intervals
+    }
+}
+
+/// BED intervals grouped by contig.
+#[derive(Debug, Clone, Default)]
+pub struct Regions {
+    by_contig: AHashMap<String, IntervalIndex>,
+}
+
+impl Regions {
+    pub fn get(&self, contig: &str) -> Option<&IntervalIndex> {
+        self.by_contig.get(contig)
+    }
+
+    pub fn n_intervals(&self) -> usize {
+        self.by_contig.values().map(|i| i.intervals.len()).sum()
+    }
+}
+
+impl FromIterator<(String, Interval)> for Regions {
+    fn from_iter<I: IntoIterator<Item = (String, Interval)>>(iter: I) -> Self {
+        let mut intervals: AHashMap<String, Vec<Interval>> = AHashMap::new();
+        for (contig, interval) in iter {
+            intervals.entry(contig).or_default().push(interval);
+        }
+
+        let by_contig = intervals
+            .into_iter()
+            .map(|(contig, intervals)| (contig, IntervalIndex::new(intervals)))
+            .collect();
+
+        Self { by_contig }
+    }
+}
+
+/// Loads the intervals of a BED file.
+///
+/// Only the first four columns are used: contig, start, end and an optional name.
+/// Intervals without a name are named `contig:start-end`. Empty lines and `#`,
+/// `track` and `browser` lines are skipped.
+pub fn load_regions<P: AsRef<Path>>(path: P) -> Result<Regions> {
+    let file = File::open(&path)
+        .with_context(|| format!("Failed to open regions BED at: {:?}", path.as_ref()))?;
+
+    let mut intervals = Vec::new();
+
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+
+        let (contig, interval) = parse_bed_line(&line)
+            .with_context(|| format!("Invalid BED line {}: '{}'", line_number + 1, line))?;
+        intervals.push((contig, interval));
+    }
+
+    Ok(intervals.into_iter().collect())
+}
+
+fn parse_bed_line(line: &str) -> Result<(String, Interval)> {
+    let mut fields = line.split('\t');
+
+    let contig = fields
+        .next()
+        .ok_or_else(|| anyhow!("Missing contig field."))?
+        .to_string();
+    let start: usize = fields
+        .next()
+        .ok_or_else(|| anyhow!("Missing start field."))?
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid start field"))?;
+    let end: usize = fields
+        .next()
+        .ok_or_else(|| anyhow!("Missing end field."))?
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid end field"))?;
+
+    if start >= end {
+        bail!("Start ({}) must be less than end ({}).", start, end);
+    }
+
+    let name = match fields.next().map(str::trim) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => format!("{}:{}-{}", contig, start, end),
+    };
+
+    Ok((contig, Interval { start, end, name }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn interval(start: usize, end: usize) -> Interval {
+        Interval {
+            start,
+            end,
+            name: format!("{}-{}", start, end),
+        }
+    }
+
+    #[test]
+    fn test_interval_index_contains() {
+        let index = IntervalIndex::new(vec![interval(10, 20), interval(0, 100), interval(30, 40)]);
+        assert!(index.contains(0));
+        assert!(index.contains(99));
+        assert!(!index.contains(100));
+
+        let index = IntervalIndex::new(vec![interval(30, 40), interval(10, 20)]);
+        assert!(!index.contains(5));
+        assert!(index.contains(10));
+        assert!(!index.contains(20));
+        assert!(!index.contains(25));
+        assert!(index.contains(39));
+        assert!(!index.contains(40));
+    }
+
+    #[test]
+    fn test_interval_slice() {
+        let positions = [1, 5, 9, 13];
+        assert_eq!(interval(5, 13).slice(&positions), &[5, 9]);
+        assert!(interval(14, 20).slice(&positions).is_empty());
+    }
+
+    #[test]
+    fn test_load_regions() -> Result<()> {
+        let mut bed = NamedTempFile::new()?;
+        writeln!(bed, "track name=genes")?;
+        writeln!(bed, "contig_3\t0\t8\tgeneA\t0\t+")?;
+        writeln!(bed, "contig_3\t8\t15")?;
+        writeln!(bed, "contig_4\t2\t6\tgeneB")?;
+
+        let regions = load_regions(bed.path())?;
+        assert_eq!(regions.n_intervals(), 3);
+
+        let names: Vec<&str> = regions
+            .get("contig_3")
+            .unwrap()
+            .intervals()
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["geneA", "contig_3:8-15"]);
+        assert!(regions.get("contig_1").is_none());
+
+        let mut bed = NamedTempFile::new()?;
+        writeln!(bed, "contig_3\t8\t2")?;
+        assert!(load_regions(bed.path()).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::fs;
+
+use crate::extract_methylation_pattern::{
+    args::MethylationPatternArgs, extract_methylation_pattern,
+};
+
+const ASSEMBLY: &str = ">contig_3
+TGGACGATCCCGATC
+>contig_4
+GATCGATCAAAA
+";
+
+/// Pileup of the assembly, with GATC sites 6, 7, 12 and 13 on contig_3 and 1 and 5 on
+/// contig_4.
+const PILEUP: &str = "\
+contig_3\t6\t7\ta\t133\t+\t6\t7\t255,0,0\t15\t100.00\t15\t0\t0\t0\t0\t0\t0
+contig_3\t8\t9\tm\t133\t+\t8\t9\t255,0,0\t20\t100.00\t20\t0\t0\t0\t0\t0\t0
+contig_3\t12\t13\ta\t133\t+\t12\t13\t255,0,0\t20\t25.00\t5\t15\t0\t0\t0\t0\t0
+contig_3\t7\t8\ta\t133\t-\t7\t8\t255,0,0\t20\t100.00\t20\t0\t0\t0\t0\t0\t0
+contig_3\t13\t14\ta\t133\t-\t13\t14\t255,0,0\t20\t25.00\t5\t15\t0\t0\t0\t0\t0
+contig_4\t1\t2\ta\t133\t+\t1\t2\t255,0,0\t10\t100.00\t10\t0\t0\t0\t0\t0\t0
+contig_4\t5\t6\ta\t133\t+\t5\t6\t255,0,0\t10\t20.00\t2\t8\t0\t0\t0\t0\t0
+";
+
+/// Expected median per (contig, motif) of the bundled pileup.
+const EXPECTED_MEDIANS: [(&str, &str, f64); 3] = [
+    ("contig_3", "GATC_a_1", 0.625),
+    ("contig_3", "GATC_m_3", 1.0),
+    ("contig_4", "GATC_a_1", 0.6),
+];
+
+/// Runs methylation-pattern on a small bundled assembly and pileup and compares the
+/// medians to the expected values. Fails if any median differs or is missing.
+pub fn self_test() -> Result<()> {
+    let dir = tempfile::tempdir().context("Failed to create a temporary directory")?;
+    let assembly = dir.path().join("assembly.fa");
+    let pileup = dir.path().join("pileup.bed");
+    let output = dir.path().join("motifs.tsv");
+    fs::write(&assembly, ASSEMBLY)?;
+    fs::write(&pileup, PILEUP)?;
+
+    let assembly = assembly.to_string_lossy().into_owned();
+    let pileup = pileup.to_string_lossy().into_owned();
+    let output_path = output.to_string_lossy().into_owned();
+    let args = MethylationPatternArgs::try_parse_from([
+        "self-test",
+        "-p",
+        &pileup,
+        "-a",
+        &assembly,
+        "-o",
+        &output_path,
+        "-m",
+        "GATC_a_1,GATC_m_3",
+        "-t",
+        "1",
+    ])?;
+    extract_methylation_pattern(args).context("Self-test analysis failed")?;
+
+    let results = fs::read_to_string(&output)
+        .with_context(|| format!("Failed to read self-test output: {:?}", output))?;
+    let medians = read_medians(&results)?;
+
+    let mut n_failed = 0;
+    for (contig, motif, expected) in EXPECTED_MEDIANS {
+        let observed = medians
+            .iter()
+            .find(|(c, m, _)| c == contig && m == motif)
+            .map(|(_, _, median)| *median);
+        match observed {
+            Some(median) if (median - expected).abs() < 1e-9 => {
+                println!("PASS {} {}: median {}", contig, motif, median);
+            }
+            Some(median) => {
+                n_failed += 1;
+                println!(
+                    "FAIL {} {}: median {}, expected {}",
+                    contig, motif, median, expected
+                );
+            }
+            None => {
+                n_failed += 1;
+                println!("FAIL {} {}: missing, expected {}", contig, motif, expected);
+            }
+        }
+    }
+
+    if n_failed > 0 {
+        bail!(
+            "Self-test failed: {} of {} checks failed.",
+            n_failed,
+            EXPECTED_MEDIANS.len()
+        );
+    }
+    println!("Self-test passed: {} checks.", EXPECTED_MEDIANS.len());
+    Ok(())
+}
+
+/// Reads `(contig, <motif>_<mod_type>_<mod_position>, median)` from the output TSV.
+fn read_medians(results: &str) -> Result<Vec<(String, String, f64)>> {
+    let mut lines = results.lines();
+    let header: Vec<&str> = lines
+        .next()
+        .context("Self-test output is empty")?
+        .split('\t')
+        .collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| *column == name)
+            .with_context(|| format!("Self-test output has no '{}' column", name))
+    };
+    let [contig, motif, mod_type, mod_position, median] = [
+        column("contig")?,
+        column("motif")?,
+        column("mod_type")?,
+        column("mod_position")?,
+        column("median")?,
+    ];
+
+    lines
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let median = fields[median]
+                .parse()
+                .with_context(|| format!("Invalid median in self-test output: '{}'", line))?;
+            Ok((
+                fields[contig].to_string(),
+                format!(
+                    "{}_{}_{}",
+                    fields[motif], fields[mod_type], fields[mod_position]
+                ),
+                median,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        self_test().unwrap();
+    }
+
+    #[test]
+    fn test_read_medians() -> Result<()> {
+        let results = "contig\tmotif\tmod_type\tmod_position\tmedian\n\
+                       contig_3\tGATC\ta\t1\t0.625\n";
+        assert_eq!(
+            read_medians(results)?,
+            vec![("contig_3".to_string(), "GATC_a_1".to_string(), 0.625)]
+        );
+        assert!(read_medians("contig\tmotif\n").is_err());
+        Ok(())
+    }
+}